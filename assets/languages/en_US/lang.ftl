@@ -1,3 +1,66 @@
 pencil = Pencil
 bucket = Bucket
-brush = Brush
\ No newline at end of file
+brush = Brush
+open = Open
+save = Save
+open_project = Open Project
+save_project = Save Project
+layer_add = Add
+layer_remove = Remove
+layer_raise = Up
+layer_lower = Down
+layer_merge_down = Merge
+masks = Masks
+brush_presets = Brushes
+capture_brush = Capture
+select_rect = Rectangle
+select_ellipse = Ellipse
+lasso = Lasso
+polygon = Polygon
+magic_wand = Wand
+move = Move
+paste_file = Paste File
+line = Line
+rect = Rect
+filled_rect = Filled Rect
+ellipse = Oval
+filled_ellipse = Filled Oval
+polygon_shape = Shape
+eyedropper = Picker
+palette = Palette
+indexed = Indexed
+palette_lock = Lock
+palette_add = Add
+palette_remove = Remove
+palette_replace = Set
+import_palette = Import Palette
+export_palette = Export Palette
+play = Play
+pause = Pause
+loop = Loop
+ping_pong = Ping-Pong
+frame_add = Add
+frame_duplicate = Dup
+frame_remove = Remove
+frame_left = <
+frame_right = >
+frame_shorter = -ms
+frame_longer = +ms
+onion_skin = Onion
+export_gif = Export GIF
+export_apng = Export APNG
+export_sheet = Export Sheet
+scale_nearest = Nearest 2x
+scale2x = Scale2x
+scale3x = Scale3x
+hq2x = hq2x
+rotsprite = Rotate
+canvas_size = Canvas Size
+crop_to_selection = Crop to Selection
+trim = Trim
+canvas_width = Width
+canvas_height = Height
+fill_background = Background
+fill_transparent = Transparent
+apply = Apply
+cancel = Cancel
@@ -0,0 +1,515 @@
+// Frames of an animation, each a layer stack with its own undo history and duration.
+// The current frame lives in the `LayerStack` and `History` resources, so that the
+// tools don't know about the animation, the others are stored here.
+use bevy::{
+    prelude::*,
+    sprite::Anchor,
+};
+use image::RgbaImage;
+use pixelin::export::ExportFrame;
+use std::cmp::Reverse;
+
+use crate::canvas::{self, Canvas};
+use crate::history::History;
+use crate::layers::LayerStack;
+use crate::mix_methods::MixMethod;
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<Animation>()
+        .init_resource::<Playback>()
+        .init_resource::<OnionSkin>()
+        .add_systems(Update, (frame_by_keys, play_animation).chain())
+        .add_systems(PostUpdate, update_onion_skin);
+}
+
+pub(crate) const DEFAULT_DURATION_MS: u32 = 100;
+
+#[derive(Debug)]
+struct Frame {
+    duration_ms: u32,
+    stored: Option<(LayerStack, History)>, // none for the current frame.
+}
+
+#[derive(Resource, Debug)]
+pub(crate) struct Animation {
+    frames: Vec<Frame>,
+    current: usize,
+    next_group: u64,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            frames: vec![Frame {
+                duration_ms: DEFAULT_DURATION_MS,
+                stored: None,
+            }],
+            current: 0,
+            next_group: 0,
+        }
+    }
+}
+
+impl Animation {
+    // The first frame goes to `live`, the histories start empty.
+    pub(crate) fn from_frames(frames: Vec<(u32, LayerStack)>, live: &mut LayerStack, history: &mut History) -> Self {
+        let budget = history.byte_budget();
+        let mut frames = frames.into_iter();
+        let mut ret = Self::default();
+        history.clear();
+        if let Some((duration_ms, layers)) = frames.next() {
+            ret.frames[0].duration_ms = duration_ms.max(1);
+            *live = layers;
+        }
+        ret.frames.extend(frames.map(|(duration_ms, layers)| Frame {
+            duration_ms: duration_ms.max(1),
+            stored: Some((layers, History::new(budget))),
+        }));
+        ret
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(crate) fn current(&self) -> usize {
+        self.current
+    }
+
+    pub(crate) fn durations(&self) -> Vec<u32> {
+        self.frames.iter().map(|x| x.duration_ms).collect()
+    }
+
+    pub(crate) fn set_duration(&mut self, index: usize, duration_ms: u32) {
+        if let Some(o) = self.frames.get_mut(index) {
+            o.duration_ms = duration_ms.max(1);
+        }
+    }
+
+    // The layers of a frame, `live` is the current one.
+    pub(crate) fn layers<'a>(&'a self, index: usize, live: &'a LayerStack) -> Option<&'a LayerStack> {
+        let frame = self.frames.get(index)?;
+        Some(frame.stored.as_ref().map_or(live, |x| &x.0))
+    }
+
+    // Makes a frame current, not in the middle of an edit.
+    pub(crate) fn select(&mut self, index: usize, live: &mut LayerStack, history: &mut History) -> bool {
+        if index >= self.frames.len() || history.is_recording() { return false; }
+        if index == self.current { return true; }
+        let (layers, frame_history) = self.frames[index].stored.take().expect("the frame isn't stored.");
+        let layers = std::mem::replace(live, layers);
+        let frame_history = std::mem::replace(history, frame_history);
+        self.frames[self.current].stored = Some((layers, frame_history));
+        self.current = index;
+        true
+    }
+
+    // Adds a frame after the current one and makes it current. It is a copy of the
+    // current frame, or has the same layers left transparent.
+    pub(crate) fn insert_frame(&mut self, live: &mut LayerStack, history: &mut History, duplicate: bool) -> bool {
+        if history.is_recording() { return false; }
+        let mut layers = live.clone();
+        if !duplicate {
+            for i in 0..layers.len() {
+                let layer = layers.layer_mut(i).expect("layer index out of range.");
+                layer.image.pixels_mut().for_each(|x| x.0 = [0; 4]);
+            }
+        }
+        self.frames.insert(self.current + 1, Frame {
+            duration_ms: self.frames[self.current].duration_ms,
+            stored: Some((layers, History::new(history.byte_budget()))),
+        });
+        self.select(self.current + 1, live, history)
+    }
+
+    // The last frame is kept.
+    pub(crate) fn remove_frame(&mut self, live: &mut LayerStack, history: &mut History) -> bool {
+        if self.frames.len() < 2 { return false; }
+        let removed = self.current;
+        let next = if removed + 1 < self.frames.len() { removed + 1 } else { removed - 1 };
+        if !self.select(next, live, history) { return false; }
+        self.frames.remove(removed);
+        self.current = self.current_position();
+        true
+    }
+
+    pub(crate) fn move_frame(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.frames.len() || to >= self.frames.len() { return false; }
+        let frame = self.frames.remove(from);
+        self.frames.insert(to, frame);
+        self.current = self.current_position();
+        true
+    }
+
+    // Changes the layers of every frame, like resizing the canvas, as one undo step
+    // of each frame.
+    pub(crate) fn map_layers(&mut self, live: &mut LayerStack, history: &mut History,
+        mut f: impl FnMut(&mut LayerStack)) -> bool {
+        if history.is_recording() { return false; }
+        let group = self.next_group;
+        self.next_group += 1;
+        for (layers, history) in self.stacks_mut(live, history) {
+            let before = layers.clone();
+            f(layers);
+            history.push_stack(Some(group), before, layers);
+        }
+        true
+    }
+
+    // Undoes the last edit of the current frame. A change of every frame is undone
+    // in all of them together, once it is the last edit of each.
+    pub(crate) fn undo(&mut self, live: &mut LayerStack, history: &mut History) -> bool {
+        let Some(group) = history.undo_group() else { return history.undo(live); };
+        if !self.frames.iter().filter_map(|x| x.stored.as_ref()).all(|x| x.1.undo_group() == Some(group)) {
+            info!("Other frames have been edited since, undo their edits first.");
+            return false;
+        }
+        self.stacks_mut(live, history).for_each(|(layers, history)| { history.undo(layers); });
+        true
+    }
+
+    pub(crate) fn redo(&mut self, live: &mut LayerStack, history: &mut History) -> bool {
+        let Some(group) = history.redo_group() else { return history.redo(live); };
+        if !self.frames.iter().filter_map(|x| x.stored.as_ref()).all(|x| x.1.redo_group() == Some(group)) {
+            info!("Other frames have been edited since, the change can't be redone.");
+            return false;
+        }
+        self.stacks_mut(live, history).for_each(|(layers, history)| { history.redo(layers); });
+        true
+    }
+
+    fn stacks_mut<'a>(&'a mut self, live: &'a mut LayerStack, history: &'a mut History)
+        -> impl Iterator<Item = (&'a mut LayerStack, &'a mut History)> {
+        std::iter::once((live, history))
+            .chain(self.frames.iter_mut().filter_map(|x| x.stored.as_mut().map(|(a, b)| (a, b))))
+    }
+
+    // The composited frames.
+    pub(crate) fn export_frames(&self, live: &LayerStack) -> Vec<ExportFrame> {
+        self.frames.iter().map(|frame| ExportFrame {
+            image: frame.stored.as_ref().map_or(live, |x| &x.0).composite(),
+            duration_ms: frame.duration_ms,
+        }).collect()
+    }
+
+    fn current_position(&self) -> usize {
+        self.frames.iter().position(|x| x.stored.is_none()).expect("no current frame.")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum PlayMode {
+    #[default]
+    Loop,
+    PingPong,
+}
+
+#[derive(Resource, Debug, Default)]
+pub(crate) struct Playback {
+    pub playing: bool,
+    pub mode: PlayMode,
+    elapsed_ms: f32, // time spent on the current frame.
+    backwards: bool,
+}
+
+impl Playback {
+    // Moves the time on by `dt_ms` and gives the frame to show.
+    pub(crate) fn advance(&mut self, durations: &[u32], current: usize, dt_ms: f32) -> usize {
+        let len = durations.len();
+        if len < 2 {
+            self.elapsed_ms = 0.;
+            return 0;
+        }
+        let mut frame = current.min(len - 1);
+        self.elapsed_ms += dt_ms;
+        while self.elapsed_ms >= durations[frame].max(1) as f32 {
+            self.elapsed_ms -= durations[frame].max(1) as f32;
+            frame = match self.mode {
+                PlayMode::Loop => (frame + 1) % len,
+                PlayMode::PingPong => {
+                    if (self.backwards && frame == 0) || (!self.backwards && frame == len - 1) {
+                        self.backwards = !self.backwards;
+                    }
+                    if self.backwards { frame - 1 } else { frame + 1 }
+                },
+            };
+        }
+        frame
+    }
+
+    pub(crate) fn toggle(&mut self) {
+        self.playing = !self.playing;
+        self.elapsed_ms = 0.;
+        self.backwards = false;
+    }
+}
+
+// The frames around the current one, drawn over the canvas tinted and faded.
+#[derive(Resource, Debug, Clone)]
+pub(crate) struct OnionSkin {
+    pub enabled: bool,
+    pub before: usize,
+    pub after: usize,
+    pub opacity: f32, // of the nearest frames, it falls off with the distance.
+    pub before_tint: [u8; 4],
+    pub after_tint: [u8; 4],
+    pub tint_method: MixMethod,
+}
+
+impl Default for OnionSkin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            before: 1,
+            after: 1,
+            opacity: 0.4,
+            before_tint: [255, 64, 64, 255],
+            after_tint: [64, 128, 255, 255],
+            tint_method: MixMethod::Color,
+        }
+    }
+}
+
+fn to_unit(x: &[u8]) -> [f32; 3] {
+    [x[0] as f32 / 255., x[1] as f32 / 255., x[2] as f32 / 255.]
+}
+
+// The bottom layer is left out when there are others, it is usually an opaque background.
+pub(crate) fn onion_image(animation: &Animation, live: &LayerStack, skin: &OnionSkin) -> RgbaImage {
+    let size = live.size();
+    let mut ret = RgbaImage::new(size.x, size.y);
+    let current = animation.current() as isize;
+    let mut neighbours: Vec<(isize, [u8; 4])> = (1..=skin.before as isize).map(|d| (-d, skin.before_tint))
+        .chain((1..=skin.after as isize).map(|d| (d, skin.after_tint)))
+        .collect();
+    // the far frames first, the near ones end on top.
+    neighbours.sort_by_key(|x| Reverse(x.0.abs()));
+
+    for (offset, tint) in neighbours {
+        let Some(layers) = usize::try_from(current + offset).ok().and_then(|i| animation.layers(i, live))
+            else { continue; };
+        if layers.size() != size { continue; }
+        let opacity = skin.opacity / offset.unsigned_abs() as f32;
+        let skip = (layers.len() > 1) as usize;
+        for layer in layers.layers()[skip..].iter().filter(|x| x.info.visible) {
+            for (dst, src) in ret.pixels_mut().zip(layer.image.pixels()) {
+                if src.0[3] == 0 { continue; }
+                let rgb = skin.tint_method.blend(to_unit(&tint), to_unit(&src.0));
+                let tinted = rgb.map(|x| (x.clamp(0., 1.) * 255.).round() as u8);
+                let tinted = [tinted[0], tinted[1], tinted[2], src.0[3]];
+                dst.0 = MixMethod::Normal.composite(&tinted, &dst.0, opacity * layer.info.opacity);
+            }
+        }
+    }
+    ret
+}
+
+// Shows the frame before or after with ',' and '.'.
+fn frame_by_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut animation: ResMut<Animation>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+) {
+    let len = animation.len();
+    let index = if keys.just_pressed(KeyCode::Comma) {
+        (animation.current() + len - 1) % len
+    } else if keys.just_pressed(KeyCode::Period) {
+        (animation.current() + 1) % len
+    } else {
+        return;
+    };
+    animation.select(index, &mut layers, &mut history);
+}
+
+fn play_animation(
+    time: Res<Time>,
+    mut playback: ResMut<Playback>,
+    mut animation: ResMut<Animation>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+) {
+    if !playback.playing { return; }
+    let next = playback.advance(&animation.durations(), animation.current(), time.delta_secs() * 1000.);
+    if next != animation.current() {
+        animation.select(next, &mut layers, &mut history);
+    }
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct OnionSprite;
+
+// Hidden while playing.
+fn update_onion_skin(
+    mut commands: Commands,
+    animation: Res<Animation>,
+    layers: Res<LayerStack>,
+    skin: Res<OnionSkin>,
+    playback: Res<Playback>,
+    canvas: Single<Entity, With<Canvas>>,
+    sprite: Option<Single<(Entity, &Sprite, &mut Transform), With<OnionSprite>>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let shown = skin.enabled && !playback.playing && animation.len() > 1;
+    if !shown {
+        if let Some(sprite) = sprite {
+            commands.entity(sprite.0).despawn();
+        }
+        return;
+    }
+    // the other frames only change when they become current.
+    let changed = animation.is_changed() || skin.is_changed() || playback.is_changed() || layers.is_changed();
+    if sprite.is_some() && !changed { return; }
+
+    let rgba = onion_image(&animation, &layers, &skin);
+    // follows the size of the canvas.
+    let half = layers.size().as_vec2() / 2.;
+    match sprite {
+        Some(sprite) => {
+            let (_, sprite, mut transform) = sprite.into_inner();
+            if let Some(image) = images.get_mut(&sprite.image) {
+                canvas::rgba_to_image(rgba, image);
+            }
+            transform.translation = Vec3::new(-half.x, half.y, 0.02);
+        },
+        None => {
+            let mut image = Image::default();
+            canvas::rgba_to_image(rgba, &mut image);
+            image.sampler = bevy::image::ImageSampler::nearest();
+            let sprite = commands.spawn((
+                    OnionSprite,
+                    Sprite {
+                        image: images.add(image),
+                        anchor: Anchor::TopLeft,
+                        ..default()
+                    },
+                    Transform::from_xyz(-half.x, half.y, 0.02),
+            )).id();
+            commands.entity(*canvas).add_child(sprite);
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    fn marked(live: &LayerStack) -> Option<[u8; 4]> {
+        Some(live.active().image.get_pixel(0, 0).0).filter(|x| x[3] > 0)
+    }
+
+    #[test]
+    fn test_frames_swap_with_the_resources() {
+        let mut live = LayerStack::new(UVec2::new(2, 2), [0; 4]);
+        let mut history = History::new(1024);
+        let mut animation = Animation::default();
+        live.active_mut().image.put_pixel(0, 0, Rgba(RED));
+
+        assert!(animation.insert_frame(&mut live, &mut history, false));
+        assert_eq!((animation.len(), animation.current()), (2, 1));
+        assert_eq!(marked(&live), None);
+        assert_eq!(marked(animation.layers(0, &live).unwrap()), Some(RED));
+
+        // each frame has its own history.
+        history.begin(live.active());
+        live.active_mut().image.put_pixel(1, 1, Rgba(RED));
+        assert!(!animation.select(0, &mut live, &mut history));
+        history.end(&live);
+        assert!(animation.select(0, &mut live, &mut history));
+        assert!(!history.undo(&mut live));
+        assert!(animation.select(1, &mut live, &mut history));
+        assert!(history.undo(&mut live));
+
+        assert!(animation.insert_frame(&mut live, &mut history, true));
+        animation.set_duration(2, 40);
+        assert!(animation.move_frame(2, 0));
+        assert_eq!(animation.current(), 0);
+        assert_eq!(animation.durations(), vec![40, DEFAULT_DURATION_MS, DEFAULT_DURATION_MS]);
+
+        assert!(animation.remove_frame(&mut live, &mut history));
+        assert_eq!((animation.len(), animation.current()), (2, 0));
+        assert_eq!(marked(&live), Some(RED));
+        assert!(animation.remove_frame(&mut live, &mut history));
+        assert!(!animation.remove_frame(&mut live, &mut history));
+        assert_eq!(marked(&live), None);
+    }
+
+    #[test]
+    fn test_undo_every_frame() {
+        let mut live = LayerStack::new(UVec2::new(2, 2), [0; 4]);
+        let mut history = History::new(usize::MAX);
+        let mut animation = Animation::default();
+        animation.insert_frame(&mut live, &mut history, false);
+        assert!(animation.map_layers(&mut live, &mut history, |x| x.resize_canvas(UVec2::new(3, 1), IVec2::ZERO, RED)));
+        assert_eq!(animation.layers(0, &live).unwrap().size(), UVec2::new(3, 1));
+
+        // the change is undone in both frames, not before the later edit of frame 0.
+        animation.select(0, &mut live, &mut history);
+        history.begin(live.active());
+        live.active_mut().image.put_pixel(2, 0, Rgba([0; 4]));
+        history.end(&live);
+        animation.select(1, &mut live, &mut history);
+        assert!(!animation.undo(&mut live, &mut history));
+        animation.select(0, &mut live, &mut history);
+        assert!(animation.undo(&mut live, &mut history));
+        assert!(animation.undo(&mut live, &mut history));
+        assert_eq!(live.size(), UVec2::new(2, 2));
+        assert_eq!(animation.layers(1, &live).unwrap().size(), UVec2::new(2, 2));
+
+        assert!(animation.redo(&mut live, &mut history));
+        assert_eq!(animation.layers(1, &live).unwrap().size(), UVec2::new(3, 1));
+        assert_eq!(marked(&live), None);
+        assert_eq!(live.active().image.get_pixel(2, 0).0, RED);
+    }
+
+    #[test]
+    fn test_playback() {
+        let durations = [100, 50, 100];
+        let mut playback = Playback::default();
+        assert_eq!(playback.advance(&durations, 0, 99.), 0);
+        assert_eq!(playback.advance(&durations, 0, 1.), 1);
+        assert_eq!(playback.advance(&durations, 1, 150.), 0);
+
+        let mut playback = Playback {
+            mode: PlayMode::PingPong,
+            ..default()
+        };
+        let frames: Vec<usize> = (0..6).scan(0, |frame, _| {
+            *frame = playback.advance(&[10, 10, 10], *frame, 10.);
+            Some(*frame)
+        }).collect();
+        assert_eq!(frames, vec![1, 2, 1, 0, 1, 2]);
+        assert_eq!(playback.advance(&[10], 0, 100.), 0);
+    }
+
+    #[test]
+    fn test_onion_image() {
+        let size = UVec2::new(2, 1);
+        let frame = |x: u32| {
+            let mut layers = LayerStack::new(size, [255; 4]);
+            layers.add_layer("ink".to_owned());
+            layers.active_mut().image.put_pixel(x, 0, Rgba([0, 0, 0, 255]));
+            (100, layers)
+        };
+        let mut live = LayerStack::new(size, [0; 4]);
+        let mut history = History::new(1024);
+        let mut animation = Animation::from_frames(vec![frame(0), frame(1)], &mut live, &mut history);
+        let skin = OnionSkin {
+            enabled: true,
+            tint_method: MixMethod::Normal,
+            opacity: 1.,
+            ..default()
+        };
+        // the background is left out, the ink of the next frame is tinted.
+        let onion = onion_image(&animation, &live, &skin);
+        assert_eq!(onion.get_pixel(0, 0).0, [0; 4]);
+        assert_eq!(onion.get_pixel(1, 0).0, skin.after_tint);
+
+        animation.select(1, &mut live, &mut history);
+        let onion = onion_image(&animation, &live, &skin);
+        assert_eq!(onion.get_pixel(0, 0).0, skin.before_tint);
+    }
+}
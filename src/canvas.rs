@@ -1,7 +1,8 @@
 use bevy::prelude::*;
-use bevy::render::render_resource::{TextureDimension, TextureFormat, };
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, };
 use bevy::asset::RenderAssetUsages;
 use bevy::image::ImageSampler;
+use image::RgbaImage;
 
 use crate::config::AppConfig;
 
@@ -20,6 +21,19 @@ pub(crate) fn make_canvas_image_by_config(app_config: &AppConfig) -> Image {
 
 }
 
+// Writes the pixels back into the canvas image, resizing it when needed.
+pub(crate) fn rgba_to_image(rgba: RgbaImage, image: &mut Image) {
+    let (width, height) = rgba.dimensions();
+    if image.size() != UVec2::new(width, height) {
+        image.resize(Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        });
+    }
+    image.data = rgba.into_raw();
+}
+
 #[derive(Component, Debug,)]
 pub(crate) struct Canvas {
 }
@@ -0,0 +1,161 @@
+use bevy::{
+    prelude::*,
+    color::palettes::css,
+};
+use my_fluent_rs_helper::build_language_0;
+
+use crate::animation::Animation;
+use crate::config::AppConfig;
+use crate::history::History;
+use crate::image_menu::{self, CanvasAnchor, MAX_CANVAS_SIZE};
+use crate::layers::LayerStack;
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<CanvasSizeDialog>()
+        .add_systems(Update, (canvas_size_button_clicked, rebuild_canvas_size_panel).chain());
+}
+
+// Opened by Image > Canvas Size with the current size.
+#[derive(Resource, Debug, Default, Clone, PartialEq)]
+pub(crate) struct CanvasSizeDialog {
+    pub open: bool,
+    pub size: UVec2,
+    pub anchor: CanvasAnchor,
+    pub transparent: bool, // or the new area of the background is `default_clear_color`.
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct CanvasSizePanel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CanvasSizeAction {
+    Width(i32),
+    Height(i32),
+    Anchor(CanvasAnchor),
+    ToggleFill,
+    Apply,
+    Cancel,
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct CanvasSizeButton(pub CanvasSizeAction);
+
+pub(crate) fn build_canvas_size_panel<'a, 'b>(parent: &'a mut ChildBuilder<'b>) {
+    parent.spawn((
+            CanvasSizePanel,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(35.),
+                top: Val::Percent(20.),
+                display: Display::None,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.),
+                padding: UiRect::all(Val::Px(6.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.8)),
+    ));
+}
+
+fn canvas_size_button_clicked(
+    query: Query<(&Interaction, &CanvasSizeButton), (Changed<Interaction>, With<Button>)>,
+    mut dialog: ResMut<CanvasSizeDialog>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+    mut animation: ResMut<Animation>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+) {
+    let step = |x: u32, d: i32| x.saturating_add_signed(d).clamp(1, MAX_CANVAS_SIZE);
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        match button.0 {
+            CanvasSizeAction::Width(d) => dialog.size.x = step(dialog.size.x, d),
+            CanvasSizeAction::Height(d) => dialog.size.y = step(dialog.size.y, d),
+            CanvasSizeAction::Anchor(anchor) => dialog.anchor = anchor,
+            CanvasSizeAction::ToggleFill => dialog.transparent = !dialog.transparent,
+            CanvasSizeAction::Apply => {
+                let (size, anchor) = (dialog.size, dialog.anchor);
+                let fill = if dialog.transparent { [0; 4] } else { app_config.default_clear_color.to_u8_array() };
+                if size != layers.size() && !image_menu::change_canvas(&mut animation, &mut layers, &mut history,
+                    &mut app_config, |x| x.resize_canvas(size, anchor.offset(x.size(), size), fill)) { continue; }
+                dialog.open = false;
+            },
+            CanvasSizeAction::Cancel => dialog.open = false,
+        }
+    }
+}
+
+fn spawn_text_button(builder: &mut ChildBuilder, action: CanvasSizeAction, text: String, font: &TextFont, color: Srgba) {
+    builder.spawn((
+            Button,
+            CanvasSizeButton(action),
+            Node {
+                padding: UiRect::horizontal(Val::Px(3.)),
+                ..default()
+            },
+    ))
+        .with_child((Text::new(text), font.clone(), TextColor(color.into())));
+}
+
+fn row(builder: &mut ChildBuilder, f: impl FnOnce(&mut ChildBuilder)) {
+    builder.spawn(Node {
+        display: Display::Flex,
+        flex_direction: FlexDirection::Row,
+        align_items: AlignItems::Center,
+        column_gap: Val::Px(2.),
+        ..default()
+    }).with_children(f);
+}
+
+fn rebuild_canvas_size_panel(
+    mut commands: Commands,
+    panel: Single<(Entity, &mut Node), With<CanvasSizePanel>>,
+    dialog: Res<CanvasSizeDialog>,
+    asset_server: Res<AssetServer>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut last: Local<Option<CanvasSizeDialog>>,
+) {
+    if last.as_ref() == Some(&*dialog) { return; }
+    let (entity, mut node) = panel.into_inner();
+    node.display = if dialog.open { Display::Flex } else { Display::None };
+
+    let tools_config = &app_config.tools_config;
+    let font = TextFont {
+        font: asset_server.load(&tools_config.default_font),
+        font_size: tools_config.default_text_size * 2.,
+        ..default()
+    };
+
+    commands.entity(entity).despawn_descendants().with_children(|builder| {
+        builder.spawn((Text::new(build_language_0("canvas_size")), font.clone()));
+        for (name, value, action) in [
+            ("canvas_width", dialog.size.x, CanvasSizeAction::Width as fn(i32) -> CanvasSizeAction),
+            ("canvas_height", dialog.size.y, CanvasSizeAction::Height),
+        ] {
+            row(builder, |builder| {
+                builder.spawn((Text::new(format!("{} {}", build_language_0(name), value)), font.clone()));
+                for d in [-16, -1, 1, 16] {
+                    spawn_text_button(builder, action(d), format!("{:+}", d), &font, css::LIME);
+                }
+            });
+        }
+
+        // the anchors as a 3x3 grid.
+        for anchors in CanvasAnchor::ALL.chunks(3) {
+            row(builder, |builder| {
+                for anchor in anchors {
+                    let (text, color) = if *anchor == dialog.anchor { ("#", css::RED) } else { ("+", css::WHITE) };
+                    spawn_text_button(builder, CanvasSizeAction::Anchor(*anchor), text.to_owned(), &font, color);
+                }
+            });
+        }
+
+        row(builder, |builder| {
+            let fill = if dialog.transparent { "fill_transparent" } else { "fill_background" };
+            spawn_text_button(builder, CanvasSizeAction::ToggleFill, build_language_0(fill), &font, css::LIME);
+            spawn_text_button(builder, CanvasSizeAction::Apply, build_language_0("apply"), &font, css::LIME);
+            spawn_text_button(builder, CanvasSizeAction::Cancel, build_language_0("cancel"), &font, css::LIME);
+        });
+    });
+    *last = Some(dialog.clone());
+}
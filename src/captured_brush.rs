@@ -0,0 +1,247 @@
+// Brushes captured from a region of the canvas, kept as PNGs in
+// `AppConfig::brushes_dir` and registered as a pattern and mask pair.
+use bevy::prelude::*;
+use bevy_pancam::PanCam;
+use image::{ImageResult, Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+
+use crate::canvas::Canvas;
+use crate::config::{AppConfig, ToolsConfig};
+use crate::file_io::{load_png, save_png};
+use crate::layers::LayerStack;
+use crate::painting::window_to_canvas;
+use crate::patterns::{BrushPreset, ColorMap, ComplexColor, PatternPlacement};
+use crate::pen_input::PenState;
+use crate::pressure_mask::{MaskKind, MaskPreset};
+use crate::selection::Selection;
+
+pub fn init_me(app: &mut App) {
+    let mut app_config = app.world_mut().resource_mut::<AppConfig<'static, 'static>>();
+    let dir = PathBuf::from(&app_config.brushes_dir);
+    for brush in CapturedBrush::load_dir(&dir) {
+        register(&mut app_config.tools_config, &brush);
+    }
+    app.add_systems(Update, capture_by_drag);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CapturedBrush {
+    pub name: String,
+    pub image: RgbaImage,
+}
+
+impl CapturedBrush {
+    // Copies `rect` of `image`, the part outside of the image is dropped.
+    pub(crate) fn capture(image: &RgbaImage, rect: URect, name: String) -> Option<Self> {
+        let rect = rect.intersect(URect::new(0, 0, image.width(), image.height()));
+        if rect.is_empty() { return None; }
+        let size = rect.size();
+        let image = image::imageops::crop_imm(image, rect.min.x, rect.min.y, size.x, size.y).to_image();
+        Some(Self {
+            name,
+            image,
+        })
+    }
+
+    // Copies the bounds of the selection, the unselected pixels are left transparent.
+    pub(crate) fn capture_selection(image: &RgbaImage, selection: &Selection, name: String) -> Option<Self> {
+        let rect = selection.bounds()?;
+        let mut ret = Self::capture(image, rect, name)?;
+        for (x, y, pixel) in ret.image.enumerate_pixels_mut() {
+            if !selection.contains(rect.min.x + x, rect.min.y + y) {
+                *pixel = Rgba([0; 4]);
+            }
+        }
+        Some(ret)
+    }
+
+    pub(crate) fn size(&self) -> UVec2 {
+        UVec2::from(self.image.dimensions())
+    }
+
+    // The transparent pixels are left out of the pattern.
+    pub(crate) fn complex_color(&self) -> ComplexColor {
+        let pattern = self.image.pixels().map(|x| (x.0[3] > 0) as u8).collect();
+        ComplexColor::new(self.size(), pattern, Srgba::NONE, ColorMap::Image(self.image.clone()))
+    }
+
+    pub(crate) fn brush_preset(&self) -> BrushPreset {
+        BrushPreset {
+            name: self.name.clone(),
+            color: self.complex_color(),
+            placement: PatternPlacement::Center,
+        }
+    }
+
+    pub(crate) fn mask_preset(&self) -> MaskPreset {
+        MaskPreset::new(&self.name, MaskKind::Alpha {
+            size: self.size(),
+            alpha: self.image.pixels().map(|x| x.0[3]).collect(),
+        })
+    }
+
+    pub(crate) fn path_in(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.png", self.name))
+    }
+
+    pub(crate) fn save(&self, dir: &Path) -> ImageResult<()> {
+        std::fs::create_dir_all(dir)?;
+        save_png(&self.image, &self.path_in(dir))
+    }
+
+    // Every PNG of `dir` is a brush named by its file stem.
+    pub(crate) fn load_dir(dir: &Path) -> Vec<Self> {
+        let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new(); };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("png")))
+            .collect();
+        paths.sort();
+
+        paths.into_iter().filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            match load_png(&path) {
+                Ok(image) => Some(Self {
+                    name,
+                    image,
+                }),
+                Err(e) => {
+                    warn!("Load brush {} failed: {}", path.display(), e);
+                    None
+                }
+            }
+        }).collect()
+    }
+}
+
+// Adds the pattern and the mask of `brush`, replacing the ones of the same name.
+pub(crate) fn register(tools_config: &mut ToolsConfig, brush: &CapturedBrush) {
+    tools_config.mask_library.insert(brush.mask_preset());
+    let preset = brush.brush_preset();
+    match tools_config.brush_presets.iter_mut().find(|x| x.name == preset.name) {
+        Some(o) => *o = preset,
+        None => tools_config.brush_presets.push(preset),
+    }
+}
+
+fn unused_name(tools_config: &ToolsConfig) -> String {
+    (1..).map(|i| format!("captured{}", i))
+        .find(|x| tools_config.brush_presets.iter().all(|p| p.name != *x))
+        .expect("no brush name left.")
+}
+
+// Drags a rectangle on the canvas with the capture tool, the brush is taken
+// from the visible layers when the button is released. With a selection the
+// selected pixels are taken instead of the rectangle.
+fn capture_by_drag(
+    pen: Res<PenState>,
+    camera: Single<(&Camera, &GlobalTransform), With<PanCam>>,
+    canvas: Single<&GlobalTransform, With<Canvas>>,
+    interactions: Query<&Interaction>,
+    layers: Res<LayerStack>,
+    selection: Res<Selection>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+    mut start: Local<Option<Vec2>>,
+    mut end: Local<Option<Vec2>>,
+) {
+    if app_config.tools_config.current_tool.as_deref() != Some("capture_brush") {
+        *start = None;
+        return;
+    }
+
+    let (camera, camera_transform) = camera.into_inner();
+    let loc = pen.sample.and_then(|x|
+        window_to_canvas(x.position, camera, camera_transform, *canvas, layers.size()));
+    if pen.pressed() {
+        if pen.just_pressed() && interactions.iter().all(|x| *x == Interaction::None) {
+            *start = loc;
+        }
+        if loc.is_some() {
+            *end = loc;
+        }
+        return;
+    }
+
+    let (Some(from), Some(to)) = (start.take(), end.take()) else { return; };
+    let min = from.min(to).floor().max(Vec2::ZERO).as_uvec2();
+    let max = from.max(to).floor().max(Vec2::ZERO).as_uvec2() + UVec2::ONE;
+    let name = unused_name(&app_config.tools_config);
+    let brush = if selection.is_active() {
+        CapturedBrush::capture_selection(&layers.composite(), &selection, name)
+    } else {
+        CapturedBrush::capture(&layers.composite(), URect::from_corners(min, max), name)
+    };
+    let Some(brush) = brush else { return; };
+
+    let dir = PathBuf::from(&app_config.brushes_dir);
+    if let Err(e) = brush.save(&dir) {
+        error!("Save brush {} failed: {}", brush.path_in(&dir).display(), e);
+    }
+    let tools_config = &mut app_config.tools_config;
+    register(tools_config, &brush);
+    tools_config.select_brush_preset(&brush.name);
+    tools_config.select_mask(&brush.name);
+    info!("Captured brush {} of {}.", brush.name, brush.size());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::selection::{SelectionMode, SelectionShape};
+
+    fn motif() -> RgbaImage {
+        // a 6x4 canvas with a 3x2 opaque motif at (1, 1), its middle top pixel transparent.
+        let mut image = RgbaImage::new(6, 4);
+        for (x, y) in [(1, 1), (3, 1), (1, 2), (2, 2), (3, 2)] {
+            image.put_pixel(x, y, Rgba([x as u8 * 10, y as u8 * 10, 0, 255]));
+        }
+        image
+    }
+
+    #[test]
+    fn test_capture_selection() {
+        let mut selection = Selection::new(UVec2::new(6, 4));
+        assert!(CapturedBrush::capture_selection(&motif(), &selection, "none".to_owned()).is_none());
+
+        // the bounds of two rects, the pixels between them are dropped.
+        selection.select(&SelectionShape::Rect(URect::new(1, 1, 2, 3)), SelectionMode::Replace);
+        selection.select(&SelectionShape::Rect(URect::new(3, 2, 4, 3)), SelectionMode::Add);
+        let brush = CapturedBrush::capture_selection(&motif(), &selection, "selected".to_owned()).unwrap();
+        assert_eq!(brush.size(), UVec2::new(3, 2));
+        assert_eq!(brush.image.get_pixel(0, 1).0[3], 255);
+        assert_eq!(brush.image.get_pixel(1, 1).0[3], 0);
+        assert_eq!(brush.image.get_pixel(2, 0).0[3], 0);
+        assert_eq!(brush.image.get_pixel(2, 1).0, [30, 20, 0, 255]);
+    }
+
+    #[test]
+    fn test_capture() {
+        let brush = CapturedBrush::capture(&motif(), URect::new(1, 1, 4, 3), "motif".to_owned()).unwrap();
+        assert_eq!(brush.size(), UVec2::new(3, 2));
+        let color = brush.complex_color();
+        assert_eq!(color.get_color_at(UVec2::new(2, 1)).unwrap().to_u8_array(), [30, 20, 0, 255]);
+        assert_eq!(color.get_color_at(UVec2::new(1, 0)), Some(Srgba::NONE));
+
+        let mask = brush.mask_preset().make();
+        let size = brush.size();
+        assert_eq!((mask.fun)(0, 0, &size), 1.);
+        assert_eq!((mask.fun)(1, 0, &size), 0.);
+
+        assert!(CapturedBrush::capture(&motif(), URect::new(6, 0, 9, 3), "out".to_owned()).is_none());
+        assert_eq!(CapturedBrush::capture(&motif(), URect::new(4, 2, 9, 9), "clipped".to_owned())
+            .unwrap().size(), UVec2::new(2, 2));
+    }
+
+    #[test]
+    fn test_save_and_reload() {
+        let dir = std::env::temp_dir().join(format!("pixelin_brushes_{}", std::process::id()));
+        let brush = CapturedBrush::capture(&motif(), URect::new(1, 1, 4, 3), "motif".to_owned()).unwrap();
+        brush.save(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a brush").unwrap();
+
+        let loaded = CapturedBrush::load_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, vec![brush]);
+        assert!(CapturedBrush::load_dir(&dir).is_empty());
+    }
+}
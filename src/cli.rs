@@ -0,0 +1,363 @@
+// The subcommands that run without a window, for asset pipelines. They load a
+// project or a png, change it with the editor's code and save it by the extension
+// of the output.
+use bevy::prelude::*;
+use pixelin::export::{self, ExportError, ExportFrame, SheetLayout, SheetOptions};
+use pixelin::transform::{self, Scaler};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::animation::DEFAULT_DURATION_MS;
+use crate::file_io::{load_png, save_png};
+use crate::layers::{LayerInfo, LayerStack};
+use crate::palette::Palette;
+use crate::palette_io::{self, PaletteError};
+use crate::project::{self, Project, ProjectError, ProjectFrame, ProjectLayer, ToolsSelection, ViewTransform};
+
+const USAGE: &str = "\
+usage:
+    pixelin convert <input> <output> [options]
+    pixelin rescale <input> <output> <scaler> [options]
+    pixelin recolor <input> <output> <palette> [options]
+    pixelin flatten <input> <output> [options]
+    pixelin sheet <input> <output.png> [--layout row|column|auto|grid:N] [--padding N] [options]
+options:
+    --frame N          the frame saved to .png, the first by default.
+    --upscale SCALER   scales the exported images, not the .pixelin ones.
+inputs are .pixelin or .png files. outputs are .pixelin, .png of one frame, animated
+.gif or .apng. a scaler is a whole factor for the nearest pixels, scale2x (epx),
+scale3x or hq2x. recolor snaps the pixels to a .gpl, .pal, .hex or .ase palette.";
+
+#[derive(Debug)]
+pub(crate) enum CliError {
+    Usage(String),
+    Project(ProjectError),
+    Image(image::ImageError),
+    Palette(PaletteError),
+    Export(ExportError),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(e) => write!(f, "{}\n{}", e, USAGE),
+            CliError::Project(e) => write!(f, "project error: {}", e),
+            CliError::Image(e) => write!(f, "image error: {}", e),
+            CliError::Palette(e) => write!(f, "palette error: {}", e),
+            CliError::Export(e) => write!(f, "export error: {}", e),
+        }
+    }
+}
+
+impl From<ProjectError> for CliError {
+    fn from(e: ProjectError) -> Self { CliError::Project(e) }
+}
+
+impl From<image::ImageError> for CliError {
+    fn from(e: image::ImageError) -> Self { CliError::Image(e) }
+}
+
+impl From<PaletteError> for CliError {
+    fn from(e: PaletteError) -> Self { CliError::Palette(e) }
+}
+
+impl From<ExportError> for CliError {
+    fn from(e: ExportError) -> Self { CliError::Export(e) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    Convert,
+    Rescale(Scaler),
+    Recolor(PathBuf),
+    Flatten,
+    Sheet(SheetOptions),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Invocation {
+    pub command: Command,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub frame: usize, // the one saved to png.
+    pub upscale: Scaler,
+}
+
+fn usage(message: impl Into<String>) -> CliError {
+    CliError::Usage(message.into())
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: Option<&str>) -> Result<T, CliError> {
+    let value = value.ok_or_else(|| usage(format!("{} needs a value", name)))?;
+    value.parse().map_err(|_| usage(format!("bad {}: {}", name, value)))
+}
+
+fn parse_scaler(name: &str, value: Option<&str>) -> Result<Scaler, CliError> {
+    let value = value.ok_or_else(|| usage(format!("{} needs a value", name)))?;
+    Scaler::parse(value).ok_or_else(|| usage(format!("bad {}: {}", name, value)))
+}
+
+fn parse_layout(value: Option<&str>) -> Result<SheetLayout, CliError> {
+    let value = value.ok_or_else(|| usage("--layout needs a value"))?;
+    match value {
+        "row" => Ok(SheetLayout::Row),
+        "column" => Ok(SheetLayout::Column),
+        "auto" => Ok(SheetLayout::Auto),
+        _ => match value.strip_prefix("grid:") {
+            Some(columns) => Ok(SheetLayout::Grid { columns: parse_number("grid columns", Some(columns))? }),
+            None => Err(usage(format!("bad --layout: {}", value))),
+        },
+    }
+}
+
+// `args` are the arguments after the program name.
+pub(crate) fn parse(args: &[String]) -> Result<Invocation, CliError> {
+    let (name, rest) = args.split_first().ok_or_else(|| usage("no subcommand"))?;
+    let mut positional = Vec::new();
+    let mut frame = 0;
+    let mut upscale = Scaler::default();
+    let mut sheet = SheetOptions::default();
+    let mut rest = rest.iter().map(String::as_str);
+    while let Some(arg) = rest.next() {
+        match arg {
+            "--frame" => frame = parse_number("--frame", rest.next())?,
+            "--upscale" => upscale = parse_scaler("--upscale", rest.next())?,
+            "--layout" => sheet.layout = parse_layout(rest.next())?,
+            "--padding" => sheet.padding = parse_number("--padding", rest.next())?,
+            _ if arg.starts_with("--") => return Err(usage(format!("unknown option {}", arg))),
+            _ => positional.push(arg),
+        }
+    }
+
+    let arity = match name.as_str() {
+        "rescale" | "recolor" => 3,
+        "convert" | "flatten" | "sheet" => 2,
+        _ => return Err(usage(format!("unknown subcommand {}", name))),
+    };
+    if positional.len() != arity {
+        return Err(usage(format!("{} takes {} arguments", name, arity)));
+    }
+    let command = match name.as_str() {
+        "convert" => Command::Convert,
+        "rescale" => Command::Rescale(parse_scaler("scaler", Some(positional[2]))?),
+        "recolor" => Command::Recolor(PathBuf::from(positional[2])),
+        "flatten" => Command::Flatten,
+        _ => Command::Sheet(sheet),
+    };
+    Ok(Invocation {
+        command,
+        input: PathBuf::from(positional[0]),
+        output: PathBuf::from(positional[1]),
+        frame,
+        upscale,
+    })
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().map_or(String::new(), |x| x.to_string_lossy().to_lowercase())
+}
+
+// A png becomes a project of one frame with one layer.
+pub(crate) fn load_input(path: &Path) -> Result<Project, CliError> {
+    match extension(path).as_str() {
+        project::PROJECT_EXTENSION => Ok(project::load_project(path)?),
+        "png" => {
+            let image = load_png(path)?;
+            Ok(Project {
+                canvas_size: UVec2::from(image.dimensions()),
+                frames: vec![ProjectFrame {
+                    duration_ms: DEFAULT_DURATION_MS,
+                    layers: vec![ProjectLayer {
+                        info: LayerInfo {
+                            name: "background".to_owned(),
+                            ..default()
+                        },
+                        image,
+                    }],
+                }],
+                palette: Vec::new(),
+                indexed: false,
+                tools: ToolsSelection::default(),
+                view: ViewTransform::default(),
+            })
+        },
+        _ => Err(usage(format!("unknown input format: {}", path.display()))),
+    }
+}
+
+fn frame_stack(project: &Project, frame: &ProjectFrame) -> LayerStack {
+    LayerStack::from_layers(project.canvas_size, frame.layers.iter().map(|x| (x.info.clone(), x.image.clone())))
+}
+
+fn export_frames(project: &Project) -> Vec<ExportFrame> {
+    project.frames.iter().map(|frame| ExportFrame {
+        image: frame_stack(project, frame).composite(),
+        duration_ms: frame.duration_ms,
+    }).collect()
+}
+
+// Every frame becomes one layer of its visible layers.
+pub(crate) fn flatten(project: &mut Project) {
+    let images: Vec<_> = export_frames(project).into_iter().map(|x| x.image).collect();
+    for (frame, image) in project.frames.iter_mut().zip(images) {
+        frame.layers = vec![ProjectLayer {
+            info: LayerInfo {
+                name: "background".to_owned(),
+                ..default()
+            },
+            image,
+        }];
+    }
+}
+
+// Every layer is scaled on its own.
+pub(crate) fn rescale(project: &mut Project, scaler: Scaler) -> Result<(), CliError> {
+    let size = transform::scaled_size(project.canvas_size.into(), scaler)
+        .ok_or_else(|| usage("the rescaled canvas is too large"))?;
+    for layer in project.frames.iter_mut().flat_map(|x| x.layers.iter_mut()) {
+        layer.image = transform::scale(&layer.image, scaler);
+    }
+    project.canvas_size = UVec2::new(size.0, size.1);
+    Ok(())
+}
+
+// The palette becomes the project's one.
+pub(crate) fn recolor(project: &mut Project, palette: &Palette) {
+    for layer in project.frames.iter_mut().flat_map(|x| x.layers.iter_mut()) {
+        layer.image.pixels_mut().for_each(|x| x.0 = palette.snap(x.0));
+    }
+    project.palette = palette.colors().to_vec();
+}
+
+pub(crate) fn save_output(project: &Project, path: &Path, frame: usize, upscale: Scaler) -> Result<(), CliError> {
+    match extension(path).as_str() {
+        project::PROJECT_EXTENSION => project::save_project(project, path)?,
+        "png" => {
+            let frame = project.frames.get(frame)
+                .ok_or_else(|| usage(format!("there is no frame {}", frame)))?;
+            if transform::scaled_size(project.canvas_size.into(), upscale).is_none() {
+                return Err(ExportError::TooLarge.into());
+            }
+            save_png(&transform::scale(&frame_stack(project, frame).composite(), upscale), path)?;
+        },
+        // an indexed project keeps its palette.
+        "gif" => {
+            let palette = (project.indexed && !project.palette.is_empty()).then_some(project.palette.as_slice());
+            export::save_gif(&export::upscale(export_frames(project), upscale)?, palette, path)?;
+        },
+        "apng" => export::save_apng(&export::upscale(export_frames(project), upscale)?, path)?,
+        _ => return Err(usage(format!("unknown output format: {}", path.display()))),
+    }
+    Ok(())
+}
+
+pub(crate) fn execute(invocation: &Invocation) -> Result<(), CliError> {
+    let mut project = load_input(&invocation.input)?;
+    match &invocation.command {
+        Command::Convert => {},
+        Command::Rescale(scaler) => rescale(&mut project, *scaler)?,
+        Command::Recolor(path) => recolor(&mut project, &palette_io::load_palette(path)?),
+        Command::Flatten => flatten(&mut project),
+        Command::Sheet(options) => {
+            if extension(&invocation.output) != "png" {
+                return Err(usage("sheets are saved as png"));
+            }
+            let frames = export::upscale(export_frames(&project), invocation.upscale)?;
+            export::save_sprite_sheet(&frames, *options, &invocation.output)?;
+            return Ok(());
+        },
+    }
+    save_output(&project, &invocation.output, invocation.frame, invocation.upscale)
+}
+
+// Runs a subcommand and gives the exit code, none without arguments, then the editor starts.
+pub(crate) fn run(args: &[String]) -> Option<i32> {
+    if args.is_empty() { return None; }
+    if matches!(args[0].as_str(), "help" | "--help" | "-h") {
+        println!("{}", USAGE);
+        return Some(0);
+    }
+    let result = parse(args).and_then(|x| execute(&x));
+    match result {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("{}", e);
+            Some(match e {
+                CliError::Usage(_) => 2,
+                _ => 1,
+            })
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let invocation = parse(&args("rescale in.pixelin out.png 3 --frame 2 --upscale epx")).unwrap();
+        assert_eq!(invocation, Invocation {
+            command: Command::Rescale(Scaler::Nearest(3)),
+            input: PathBuf::from("in.pixelin"),
+            output: PathBuf::from("out.png"),
+            frame: 2,
+            upscale: Scaler::Scale2x,
+        });
+        let invocation = parse(&args("sheet in.pixelin out.png --layout grid:4 --padding 2")).unwrap();
+        assert_eq!(invocation.command, Command::Sheet(SheetOptions {
+            layout: SheetLayout::Grid { columns: 4 },
+            padding: 2,
+        }));
+
+        for bad in ["", "paint a b", "convert a", "rescale a b 0", "flatten a b --frame x", "sheet a b --layout spiral"] {
+            assert!(matches!(parse(&args(bad)), Err(CliError::Usage(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_operations() {
+        let red = Rgba([250, 10, 10, 255]);
+        let mut top = RgbaImage::new(2, 1);
+        top.put_pixel(1, 0, red);
+        let mut project = Project {
+            canvas_size: UVec2::new(2, 1),
+            frames: vec![ProjectFrame {
+                duration_ms: 100,
+                layers: vec![
+                    ProjectLayer {
+                        info: LayerInfo::default(),
+                        image: RgbaImage::from_pixel(2, 1, Rgba([0, 0, 200, 255])),
+                    },
+                    ProjectLayer {
+                        info: LayerInfo::default(),
+                        image: top,
+                    },
+                ],
+            }],
+            palette: Vec::new(),
+            indexed: false,
+            tools: ToolsSelection::default(),
+            view: ViewTransform::default(),
+        };
+
+        flatten(&mut project);
+        assert_eq!(project.frames[0].layers.len(), 1);
+        assert_eq!(project.frames[0].layers[0].image.get_pixel(1, 0), &red);
+
+        rescale(&mut project, Scaler::Nearest(2)).unwrap();
+        assert_eq!(project.canvas_size, UVec2::new(4, 2));
+        let image = &project.frames[0].layers[0].image;
+        assert_eq!((image.get_pixel(1, 1), image.get_pixel(2, 1)), (&Rgba([0, 0, 200, 255]), &red));
+
+        recolor(&mut project, &Palette::new(vec![[255, 0, 0, 255], [0, 0, 255, 255]]));
+        let image = &project.frames[0].layers[0].image;
+        assert_eq!((image.get_pixel(0, 0).0, image.get_pixel(3, 0).0), ([0, 0, 255, 255], [255, 0, 0, 255]));
+        assert_eq!(project.palette.len(), 2);
+    }
+}
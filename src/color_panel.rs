@@ -0,0 +1,425 @@
+// The primary and secondary colours used for painting, and a panel to edit them
+// with a saturation/value square, a hue and an alpha slider and a hex entry.
+use bevy::{
+    prelude::*,
+    color::palettes::css,
+    input::{InputSystem, keyboard::{Key, KeyboardInput}, ButtonState},
+    ui::RelativeCursorPosition,
+};
+use image::{Rgba, RgbaImage};
+use std::sync::{Arc, RwLock};
+
+use crate::canvas;
+use crate::config::{AppConfig, ToolsConfig};
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<ColorPicker>()
+        .add_systems(PreUpdate, type_hex.after(InputSystem))
+        .add_systems(Update, (swap_by_key, color_button_clicked, drag_pickers, update_color_panel).chain());
+}
+
+const SQUARE_SIZE: u32 = 96;
+const STRIP_WIDTH: u32 = 96;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ColorSlot {
+    #[default]
+    Primary,
+    Secondary,
+}
+
+pub(crate) fn slot_color<'a>(tools_config: &'a ToolsConfig, slot: ColorSlot) -> &'a Arc<RwLock<Srgba>> {
+    match slot {
+        ColorSlot::Primary => &tools_config.primary_color,
+        ColorSlot::Secondary => &tools_config.secondary_color,
+    }
+}
+
+fn read_color(color: &Arc<RwLock<Srgba>>) -> Srgba {
+    *color.read().expect("read lock failed.")
+}
+
+// The colour being edited, kept as hsv so that the hue survives greys.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct ColorPicker {
+    pub slot: ColorSlot,
+    pub hsva: Hsva,
+    pub hex: Option<String>, // the hex entry while it is typed in.
+}
+
+impl ColorPicker {
+    pub(crate) fn color(&self) -> Srgba {
+        self.hsva.into()
+    }
+
+    pub(crate) fn set_color(&mut self, color: Srgba) {
+        if self.color().to_u8_array() == color.to_u8_array() { return; }
+        let hsva = Hsva::from(color);
+        self.hsva = Hsva {
+            hue: if hsva.saturation > 0. && hsva.value > 0. { hsva.hue } else { self.hsva.hue },
+            saturation: if hsva.value > 0. { hsva.saturation } else { self.hsva.saturation },
+            ..hsva
+        };
+    }
+}
+
+// Accepts the forms of `Srgba::hex`, with or without '#'.
+pub(crate) fn parse_hex(text: &str) -> Option<Srgba> {
+    Srgba::hex(text.trim()).ok()
+}
+
+// Saturation from left to right, value from top to bottom.
+pub(crate) fn sv_square(hue: f32, size: u32) -> RgbaImage {
+    let last = (size.max(2) - 1) as f32;
+    RgbaImage::from_fn(size, size, |x, y| {
+        let color: Srgba = Hsva::hsv(hue, x as f32 / last, 1. - y as f32 / last).into();
+        Rgba(color.to_u8_array())
+    })
+}
+
+pub(crate) fn hue_strip(width: u32) -> RgbaImage {
+    let last = (width.max(2) - 1) as f32;
+    RgbaImage::from_fn(width, 1, |x, _| {
+        let color: Srgba = Hsva::hsv(x as f32 / last * 360., 1., 1.).into();
+        Rgba(color.to_u8_array())
+    })
+}
+
+// The colour from transparent to opaque, over a checker so the alpha shows.
+pub(crate) fn alpha_strip(color: Srgba, width: u32) -> RgbaImage {
+    let last = (width.max(2) - 1) as f32;
+    RgbaImage::from_fn(width, 8, |x, y| {
+        let checker = if (x / 4 + y / 4) % 2 == 0 { [204; 3] } else { [255; 3] };
+        let alpha = x as f32 / last;
+        let [r, g, b, _] = color.to_u8_array();
+        let mix = |c: u8, k: u8| (c as f32 * alpha + k as f32 * (1. - alpha)).round() as u8;
+        Rgba([mix(r, checker[0]), mix(g, checker[1]), mix(b, checker[2]), 255])
+    })
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct ColorPanel;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColorButton {
+    Slot(ColorSlot),
+    Swap,
+    Hex,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PickerArea {
+    SvSquare,
+    Hue,
+    Alpha,
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct PickerMarker(PickerArea);
+
+#[derive(Component, Debug)]
+pub(crate) struct HexText;
+
+fn new_image(rgba: RgbaImage, images: &mut Assets<Image>) -> Handle<Image> {
+    let mut image = Image::default();
+    canvas::rgba_to_image(rgba, &mut image);
+    images.add(image)
+}
+
+fn spawn_picker_area(parent: &mut ChildBuilder, area: PickerArea, image: Handle<Image>, size: Vec2) {
+    parent.spawn((
+            Button,
+            area,
+            RelativeCursorPosition::default(),
+            ImageNode::new(image),
+            Node {
+                width: Val::Px(size.x),
+                height: Val::Px(size.y),
+                ..default()
+            },
+    ))
+        .with_child((
+            PickerMarker(area),
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Px(4.),
+                height: Val::Px(4.),
+                border: UiRect::all(Val::Px(1.)),
+                ..default()
+            },
+            BorderColor(css::WHITE.into()),
+            BackgroundColor(css::BLACK.into()),
+        ));
+}
+
+pub(crate) fn build_color_panel<'a, 'b>(parent: &'a mut ChildBuilder<'b>, images: &mut Assets<Image>) {
+    let square = new_image(sv_square(0., SQUARE_SIZE), images);
+    let hue = new_image(hue_strip(STRIP_WIDTH), images);
+    let alpha = new_image(alpha_strip(Srgba::BLACK, STRIP_WIDTH), images);
+
+    parent.spawn((
+            ColorPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.),
+                bottom: Val::Percent(8.),
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(4.),
+                padding: UiRect::all(Val::Px(4.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.6)),
+    )).with_children(|panel| {
+        panel.spawn(Node {
+            display: Display::Flex,
+            column_gap: Val::Px(4.),
+            align_items: AlignItems::Center,
+            ..default()
+        }).with_children(|row| {
+            for (slot, button) in [(ColorSlot::Primary, ColorButton::Slot(ColorSlot::Primary)),
+                (ColorSlot::Secondary, ColorButton::Slot(ColorSlot::Secondary))] {
+                row.spawn((
+                        Button,
+                        button,
+                        Node {
+                            width: Val::Px(20.),
+                            height: Val::Px(20.),
+                            border: UiRect::all(Val::Px(2.)),
+                            ..default()
+                        },
+                        BorderColor(if slot == ColorSlot::Primary { css::RED } else { css::WHITE }.into()),
+                        BackgroundColor(css::BLACK.into()),
+                ));
+            }
+            row.spawn((
+                    Button,
+                    ColorButton::Swap,
+                    Node {
+                        padding: UiRect::horizontal(Val::Px(3.)),
+                        ..default()
+                    },
+            ))
+                .with_child((Text::new("<>"), TextColor(css::WHITE.into())));
+        });
+        spawn_picker_area(panel, PickerArea::SvSquare, square, Vec2::splat(SQUARE_SIZE as f32));
+        spawn_picker_area(panel, PickerArea::Hue, hue, Vec2::new(STRIP_WIDTH as f32, 10.));
+        spawn_picker_area(panel, PickerArea::Alpha, alpha, Vec2::new(STRIP_WIDTH as f32, 10.));
+        panel.spawn((
+                Button,
+                ColorButton::Hex,
+                Node {
+                    padding: UiRect::horizontal(Val::Px(3.)),
+                    ..default()
+                },
+        ))
+            .with_child((HexText, Text::new(""), TextColor(css::WHITE.into())));
+    });
+}
+
+// While the hex entry is typed in, the keys are taken from the other shortcuts.
+fn type_hex(
+    mut events: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut picker: ResMut<ColorPicker>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    let Some(mut hex) = picker.hex.clone() else {
+        events.clear();
+        return;
+    };
+    for event in events.read() {
+        if event.state != ButtonState::Pressed { continue; }
+        match &event.logical_key {
+            Key::Character(c) => {
+                hex.extend(c.chars().filter(|x| x.is_ascii_hexdigit()));
+                hex.truncate(8);
+            },
+            Key::Backspace => { hex.pop(); },
+            Key::Enter => {
+                match parse_hex(&hex) {
+                    Some(color) => {
+                        *slot_color(&app_config.tools_config, picker.slot).write().expect("write lock failed.") = color;
+                    },
+                    None => warn!("{} is not a hex colour.", hex),
+                }
+                picker.hex = None;
+                keys.clear();
+                return;
+            },
+            Key::Escape => {
+                picker.hex = None;
+                keys.clear();
+                return;
+            },
+            _ => {},
+        }
+    }
+    if picker.hex.as_ref() != Some(&hex) {
+        picker.hex = Some(hex);
+    }
+    keys.clear();
+}
+
+fn swap_by_key(
+    keys: Res<ButtonInput<KeyCode>>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if keys.just_pressed(KeyCode::KeyX) && !ctrl {
+        app_config.tools_config.swap_colors();
+    }
+}
+
+fn color_button_clicked(
+    query: Query<(&Interaction, &ColorButton), (Changed<Interaction>, With<Button>)>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut picker: ResMut<ColorPicker>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    let mut hex_clicked = false;
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        match button {
+            ColorButton::Slot(slot) => picker.slot = *slot,
+            ColorButton::Swap => app_config.tools_config.swap_colors(),
+            ColorButton::Hex => {
+                hex_clicked = true;
+                let color = read_color(slot_color(&app_config.tools_config, picker.slot));
+                picker.hex = Some(color.to_hex().trim_start_matches('#').to_owned());
+            },
+        }
+    }
+    // clicking anywhere else leaves the hex entry.
+    if !hex_clicked && picker.hex.is_some() && mouse.get_just_pressed().next().is_some() {
+        picker.hex = None;
+    }
+}
+
+fn drag_pickers(
+    query: Query<(&Interaction, &RelativeCursorPosition, &PickerArea)>,
+    mut picker: ResMut<ColorPicker>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    for (interaction, cursor, area) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        let Some(p) = cursor.normalized else { continue; };
+        let p = p.clamp(Vec2::ZERO, Vec2::ONE);
+        let hsva = &mut picker.hsva;
+        match area {
+            PickerArea::SvSquare => {
+                hsva.saturation = p.x;
+                hsva.value = 1. - p.y;
+            },
+            PickerArea::Hue => hsva.hue = p.x * 360.,
+            PickerArea::Alpha => hsva.alpha = p.x,
+        }
+        let color = picker.color();
+        *slot_color(&app_config.tools_config, picker.slot).write().expect("write lock failed.") = color;
+    }
+}
+
+// Follows the colours, which may also be changed by the eyedropper or a swap.
+fn update_color_panel(
+    mut picker: ResMut<ColorPicker>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut swatches: Query<(&ColorButton, &mut BackgroundColor, &mut BorderColor)>,
+    mut markers: Query<(&PickerMarker, &mut Node)>,
+    mut hex_text: Query<&mut Text, With<HexText>>,
+    areas: Query<(&PickerArea, &ImageNode)>,
+    mut images: ResMut<Assets<Image>>,
+    mut last: Local<Option<(Srgba, Srgba, ColorSlot, Hsva, Option<String>)>>,
+) {
+    let tools_config = &app_config.tools_config;
+    let (primary, secondary) = (read_color(&tools_config.primary_color), read_color(&tools_config.secondary_color));
+    let editing = read_color(slot_color(tools_config, picker.slot));
+    picker.set_color(editing);
+
+    let current = (primary, secondary, picker.slot, picker.hsva, picker.hex.clone());
+    let hue_changed = last.as_ref().is_none_or(|x| x.3.hue != picker.hsva.hue);
+    let color_changed = last.as_ref().is_none_or(|x| x.3 != picker.hsva);
+    if last.as_ref() == Some(&current) { return; }
+
+    for (button, mut background, mut border) in &mut swatches {
+        let ColorButton::Slot(slot) = button else { continue; };
+        let color = if *slot == ColorSlot::Primary { primary } else { secondary };
+        background.0 = color.into();
+        border.0 = if *slot == picker.slot { css::RED } else { css::WHITE }.into();
+    }
+
+    let hsva = picker.hsva;
+    for (marker, mut node) in &mut markers {
+        let p = match marker.0 {
+            PickerArea::SvSquare => Vec2::new(hsva.saturation, 1. - hsva.value),
+            PickerArea::Hue => Vec2::new(hsva.hue / 360., 0.5),
+            PickerArea::Alpha => Vec2::new(hsva.alpha, 0.5),
+        };
+        node.left = Val::Percent(p.x * 100.);
+        node.top = Val::Percent(p.y * 100.);
+    }
+
+    for mut text in &mut hex_text {
+        text.0 = match &picker.hex {
+            Some(hex) => format!("#{}_", hex),
+            None => editing.to_hex(),
+        };
+    }
+
+    for (area, image_node) in &areas {
+        let rgba = match area {
+            PickerArea::SvSquare if hue_changed => sv_square(hsva.hue, SQUARE_SIZE),
+            PickerArea::Alpha if color_changed => alpha_strip(editing, STRIP_WIDTH),
+            _ => continue,
+        };
+        if let Some(image) = images.get_mut(&image_node.image) {
+            canvas::rgba_to_image(rgba, image);
+        }
+    }
+    *last = Some(current);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("#ff0000"), Some(Srgba::rgb_u8(255, 0, 0)));
+        assert_eq!(parse_hex("00ff0080").unwrap().to_u8_array(), [0, 255, 0, 128]);
+        assert_eq!(parse_hex(" fff "), Some(Srgba::WHITE));
+        assert_eq!(parse_hex("12345"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+
+    #[test]
+    fn test_picker_keeps_hue_of_greys() {
+        let mut picker = ColorPicker::default();
+        picker.set_color(Srgba::rgb_u8(0, 0, 255));
+        assert_eq!(picker.hsva.hue.round(), 240.);
+
+        picker.set_color(Srgba::rgb_u8(128, 128, 128));
+        assert_eq!(picker.hsva.hue.round(), 240.);
+        assert_eq!(picker.hsva.saturation, 0.);
+        assert_eq!(picker.color().to_u8_array(), [128, 128, 128, 255]);
+
+        picker.set_color(Srgba::BLACK);
+        assert_eq!(picker.hsva.hue.round(), 240.);
+        assert_eq!(picker.color().to_u8_array(), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_picker_images() {
+        let square = sv_square(0., 4);
+        assert_eq!(square.get_pixel(0, 0).0, [255, 255, 255, 255]);
+        assert_eq!(square.get_pixel(3, 0).0, [255, 0, 0, 255]);
+        assert_eq!(square.get_pixel(3, 3).0, [0, 0, 0, 255]);
+
+        let hue = hue_strip(7);
+        assert_eq!(hue.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(hue.get_pixel(2, 0).0, [0, 255, 0, 255]);
+
+        let alpha = alpha_strip(Srgba::BLACK, 8);
+        assert_eq!(alpha.get_pixel(0, 0).0, [204, 204, 204, 255]);
+        assert_eq!(alpha.get_pixel(7, 0).0, [0, 0, 0, 255]);
+    }
+}
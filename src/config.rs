@@ -5,11 +5,16 @@ use bevy::{
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use bevy::render::render_resource::{Extent3d, };
+use pixelin::export::{SheetLayout, SheetOptions};
+use pixelin::transform::Scaler;
 
 use crate::{
-    pressure_mask::MaskGeneratingFunc,
+    pressure_mask::{MaskGeneratingFunc, MaskLibrary, PressureDynamics},
     mix_methods::MixMethod,
-    patterns::PatternGeneratingFunc,
+    patterns::{self, BrushPreset, PatternGeneratingFunc},
+    flood_fill::{Connectivity, FillMode},
+    eyedropper::SampleSource,
+    palette::Palette,
 };
 
 
@@ -37,16 +42,63 @@ pub(crate) struct ToolsConfig<'a, 'b> {
 
     pub selecting_color: Arc<RwLock<Srgba>>,
     pub deselecting_color: Arc<RwLock<Srgba>>,
+    pub primary_color: Arc<RwLock<Srgba>>, // the colour of the single colour patterns.
+    pub secondary_color: Arc<RwLock<Srgba>>,
 
     pub exclusive_tools: HashMap<String, Entity>,
     pub current_tool: Option<String>,
     
+    pub brush_size: Arc<RwLock<UVec2>>,
+    pub brush_spacing: f32, // relative to the brush size.
+    pub pixel_perfect: bool, // drops the L shaped corners of pencil strokes.
+    pub pressure_dynamics: PressureDynamics,
+
+    pub fill_tolerance: [u8; 4],
+    pub fill_connectivity: Connectivity,
+    pub fill_mode: FillMode,
+    pub eyedropper_source: SampleSource,
+    pub eyedropper_size: u32, // picks the average of size x size pixels.
     pub mix_method: MixMethod,
+    pub palette: Palette,
+    pub palette_lock: bool, // snaps the mixed colours to the palette.
+    pub mask_library: MaskLibrary,
     pub pressure_mask: MaskGeneratingFunc<'a>,
     pub pattern: PatternGeneratingFunc<'b>,
+    pub brush_presets: Vec<BrushPreset>,
 }
 
 
+impl <'a, 'b> ToolsConfig<'a, 'b> {
+    pub(crate) fn swap_colors(&self) {
+        let mut primary = self.primary_color.write().expect("write lock failed.");
+        let mut secondary = self.secondary_color.write().expect("write lock failed.");
+        std::mem::swap(&mut *primary, &mut *secondary);
+    }
+
+    // Returns false when there is no mask of the name.
+    pub(crate) fn select_mask(&mut self, name: &str) -> bool {
+        match self.mask_library.make(name) {
+            Some(o) => {
+                self.pressure_mask = o;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // The preset brings its pattern and the brush size.
+    pub(crate) fn select_brush_preset(&mut self, name: &str) -> bool {
+        match self.brush_presets.iter().find(|x| x.name == name) {
+            Some(o) => {
+                self.pattern = o.make(&self.primary_color);
+                *self.brush_size.write().expect("write brush size failed.") = o.size();
+                true
+            },
+            None => false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct MenuConfig {
     pub menu_info: Vec<MenuInfo>,
@@ -62,6 +114,12 @@ pub(crate) struct AppConfig<'a, 'b> {
     pub default_top_menu_percentage: f32,
     pub default_bottom_menu_percentage: f32,
 
+    pub history_byte_budget: usize,
+    pub brushes_dir: String, // captured brushes are kept here.
+    pub sheet_options: SheetOptions, // of the sprite sheets exported from the menu.
+    pub export_scaler: Scaler, // the exported images are scaled up by it.
+    pub rotate_degrees: f32, // of the rotate menu, clockwise.
+
     pub tools_config: ToolsConfig<'a, 'b>,
     pub menu_config: MenuConfig,
 }
@@ -70,6 +128,9 @@ impl <'a, 'b> Default for AppConfig<'a, 'b> {
     fn default() -> Self {
         let selecting_color1 = Arc::new(RwLock::new(css::RED.into()));
         let deselecting_color1 = Arc::new(RwLock::new(css::WHITE.into()));
+        let primary_color1 = Arc::new(RwLock::new(Srgba::BLACK));
+        let mask_library = MaskLibrary::default();
+        let brush_presets = patterns::brush_presets();
         Self {
             default_canvas_size: Extent3d {
                 width: 320u32, 
@@ -80,6 +141,15 @@ impl <'a, 'b> Default for AppConfig<'a, 'b> {
 
             default_top_menu_percentage: 5.,
             default_bottom_menu_percentage: 5.,
+
+            history_byte_budget: 256 * 1024 * 1024,
+            brushes_dir: "brushes".to_owned(),
+            sheet_options: SheetOptions {
+                layout: SheetLayout::Auto,
+                padding: 1,
+            },
+            export_scaler: Scaler::Nearest(1),
+            rotate_degrees: 15.,
             tools_config: ToolsConfig { tools_info: vec![
                 ToolInfo {
                     name: "pencil".to_owned(),
@@ -90,31 +160,197 @@ impl <'a, 'b> Default for AppConfig<'a, 'b> {
                     name: "bucket".to_owned(),
                     icon: "icons/bucket.png".to_owned(),
                     icon_handle: None,
+                },
+                ToolInfo {
+                    name: "eyedropper".to_owned(),
+                    icon: "icons/eyedropper.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "line".to_owned(),
+                    icon: "icons/line.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "rect".to_owned(),
+                    icon: "icons/rect.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "filled_rect".to_owned(),
+                    icon: "icons/filled_rect.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "ellipse".to_owned(),
+                    icon: "icons/ellipse.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "filled_ellipse".to_owned(),
+                    icon: "icons/filled_ellipse.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "polygon_shape".to_owned(),
+                    icon: "icons/polygon_shape.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "move".to_owned(),
+                    icon: "icons/move.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "capture_brush".to_owned(),
+                    icon: "icons/capture_brush.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "select_rect".to_owned(),
+                    icon: "icons/select_rect.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "select_ellipse".to_owned(),
+                    icon: "icons/select_ellipse.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "lasso".to_owned(),
+                    icon: "icons/lasso.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "polygon".to_owned(),
+                    icon: "icons/polygon.png".to_owned(),
+                    icon_handle: None,
+                },
+                ToolInfo {
+                    name: "magic_wand".to_owned(),
+                    icon: "icons/magic_wand.png".to_owned(),
+                    icon_handle: None,
                 },],
 
                 default_font: "fonts/AaBianYaKai-2.ttf".to_owned(),
                 default_text_size: 7f32,
                 selecting_color: selecting_color1.clone(),
                 deselecting_color: deselecting_color1.clone(),
+                primary_color: primary_color1.clone(),
+                secondary_color: Arc::new(RwLock::new(Srgba::WHITE)),
                 exclusive_tools: HashMap::new(),
                 current_tool: None,
 
+                brush_size: Arc::new(RwLock::new(UVec2::splat(1))),
+                brush_spacing: 0.25,
+                pixel_perfect: false,
+                pressure_dynamics: PressureDynamics::default(),
+
+                fill_tolerance: [0; 4],
+                fill_connectivity: Connectivity::Four,
+                fill_mode: FillMode::Contiguous,
+                eyedropper_source: SampleSource::Composite,
+                eyedropper_size: 1,
                 mix_method: MixMethod::Normal,
-                pressure_mask: MaskGeneratingFunc {
-                    name: "overwrite".to_owned(),
-                    fun: Box::new(|_x, _y, _size| { 1.0 })
-                },
-                pattern: PatternGeneratingFunc {
-                    name: "dot".to_owned(),
-                    fun: Box::new(move |_x, _y, _sz| {
-                        selecting_color1.read()
-                            .expect("read lock failed.")
-                            .clone()
-                    })
-                }
+                palette: Palette::pico8(),
+                palette_lock: false,
+                mask_library: mask_library.clone(),
+                pressure_mask: mask_library.make("overwrite").expect("no overwrite mask."),
+                pattern: brush_presets[0].make(&primary_color1),
+                brush_presets,
             },
             menu_config: MenuConfig {
-                menu_info: Vec::new(),
+                menu_info: vec![
+                    MenuInfo {
+                        name: "open".to_owned(),
+                        icon: "icons/open.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "save".to_owned(),
+                        icon: "icons/save.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "open_project".to_owned(),
+                        icon: "icons/open_project.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "save_project".to_owned(),
+                        icon: "icons/save_project.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "paste_file".to_owned(),
+                        icon: "icons/paste_file.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "import_palette".to_owned(),
+                        icon: "icons/import_palette.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "export_palette".to_owned(),
+                        icon: "icons/export_palette.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "export_gif".to_owned(),
+                        icon: "icons/export_gif.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "export_apng".to_owned(),
+                        icon: "icons/export_apng.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "export_sheet".to_owned(),
+                        icon: "icons/export_sheet.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "scale_nearest".to_owned(),
+                        icon: "icons/scale_nearest.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "scale2x".to_owned(),
+                        icon: "icons/scale2x.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "scale3x".to_owned(),
+                        icon: "icons/scale3x.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "hq2x".to_owned(),
+                        icon: "icons/hq2x.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "rotsprite".to_owned(),
+                        icon: "icons/rotsprite.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "canvas_size".to_owned(),
+                        icon: "icons/canvas_size.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "crop_to_selection".to_owned(),
+                        icon: "icons/crop_to_selection.png".to_owned(),
+                        icon_handle: None,
+                    },
+                    MenuInfo {
+                        name: "trim".to_owned(),
+                        icon: "icons/trim.png".to_owned(),
+                        icon_handle: None,
+                    },],
                 default_font: "fonts/AaBianYaKai-2.ttf".to_owned(),
                 default_text_size: 7f32,
             },
@@ -0,0 +1,487 @@
+// Animated GIF, APNG and sprite sheet exporters. They take composited frames
+// only, so that build pipelines can run them without the editor.
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::transform::{self, Scaler};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// GIF has 256 entries, the last one is kept for transparency.
+pub const GIF_MAX_COLORS: usize = 255;
+
+// GIF pixels are transparent below this alpha.
+const ALPHA_THRESHOLD: u8 = 128;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Gif(gif::EncodingError),
+    Png(png::EncodingError),
+    Json(serde_json::Error),
+    NoFrames,
+    // every frame must be of the size of the first one.
+    SizeMismatch { index: usize },
+    TooLarge,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "io error: {}", e),
+            ExportError::Image(e) => write!(f, "image error: {}", e),
+            ExportError::Gif(e) => write!(f, "gif error: {}", e),
+            ExportError::Png(e) => write!(f, "png error: {}", e),
+            ExportError::Json(e) => write!(f, "json error: {}", e),
+            ExportError::NoFrames => write!(f, "there are no frames"),
+            ExportError::SizeMismatch { index } => write!(f, "frame {} isn't of the size of the first one", index),
+            ExportError::TooLarge => write!(f, "the image is too large for the format"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self { ExportError::Io(e) }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(e: image::ImageError) -> Self { ExportError::Image(e) }
+}
+
+impl From<gif::EncodingError> for ExportError {
+    fn from(e: gif::EncodingError) -> Self { ExportError::Gif(e) }
+}
+
+impl From<png::EncodingError> for ExportError {
+    fn from(e: png::EncodingError) -> Self { ExportError::Png(e) }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self { ExportError::Json(e) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportFrame {
+    pub image: RgbaImage,
+    pub duration_ms: u32,
+}
+
+// Scales the frames up for the export, the durations are kept.
+pub fn upscale(frames: Vec<ExportFrame>, scaler: Scaler) -> Result<Vec<ExportFrame>, ExportError> {
+    if scaler == Scaler::Nearest(1) { return Ok(frames); }
+    if frames.iter().any(|x| transform::scaled_size(x.image.dimensions(), scaler).is_none()) {
+        return Err(ExportError::TooLarge);
+    }
+    Ok(frames.into_iter().map(|x| ExportFrame {
+        image: transform::scale(&x.image, scaler),
+        duration_ms: x.duration_ms,
+    }).collect())
+}
+
+fn frame_size(frames: &[ExportFrame]) -> Result<(u32, u32), ExportError> {
+    let first = frames.first().ok_or(ExportError::NoFrames)?;
+    let size = first.image.dimensions();
+    match frames.iter().position(|x| x.image.dimensions() != size) {
+        Some(index) => Err(ExportError::SizeMismatch { index }),
+        None => Ok(size),
+    }
+}
+
+fn rgb(pixel: &Rgba<u8>) -> [u8; 3] {
+    [pixel.0[0], pixel.0[1], pixel.0[2]]
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> i32 {
+    a.iter().zip(b).map(|(a, b)| (*a as i32 - b as i32).pow(2)).sum()
+}
+
+// The opaque colours of the frames, reduced to `max_colors` by median cut when there
+// are more. Pixel art seldom has more, then the colours are kept exactly.
+pub fn quantize(frames: &[ExportFrame], max_colors: usize) -> Vec<[u8; 3]> {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+    for pixel in frames.iter().flat_map(|x| x.image.pixels()).filter(|x| x.0[3] >= ALPHA_THRESHOLD) {
+        *counts.entry(rgb(pixel)).or_default() += 1;
+    }
+    let mut colors: Vec<([u8; 3], u64)> = counts.into_iter().collect();
+    colors.sort_unstable();
+    if colors.len() <= max_colors {
+        return colors.into_iter().map(|x| x.0).collect();
+    }
+
+    let range = |colors: &[([u8; 3], u64)], channel: usize| {
+        let (min, max) = colors.iter().fold((255, 0), |(min, max), x| (x.0[channel].min(min), x.0[channel].max(max)));
+        max - min
+    };
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        // the box with the widest channel is split at the median of the pixels.
+        let Some((index, channel, _)) = boxes.iter().enumerate()
+            .filter(|(_, x)| x.len() > 1)
+            .flat_map(|(i, x)| (0..3).map(move |c| (i, c, range(x, c))))
+            .max_by_key(|x| x.2)
+            else { break; };
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|x| x.0[channel]);
+        let total: u64 = colors.iter().map(|x| x.1).sum();
+        let mut count = 0;
+        let median = colors.iter().position(|x| {
+            count += x.1;
+            count * 2 >= total
+        }).unwrap_or(0);
+        let rest = colors.split_off((median + 1).clamp(1, colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(rest);
+    }
+
+    boxes.iter().map(|colors| {
+        let total: u64 = colors.iter().map(|x| x.1).sum();
+        let mut sum = [0u64; 3];
+        for (color, count) in colors {
+            for (s, c) in sum.iter_mut().zip(color) {
+                *s += *c as u64 * count;
+            }
+        }
+        sum.map(|x| ((x + total / 2) / total) as u8)
+    }).collect()
+}
+
+// Indices into `colors`, transparent pixels take `colors.len()`.
+fn index_pixels(image: &RgbaImage, colors: &[[u8; 3]], cache: &mut HashMap<[u8; 3], u8>) -> Vec<u8> {
+    let transparent = colors.len() as u8;
+    image.pixels().map(|pixel| {
+        if pixel.0[3] < ALPHA_THRESHOLD || colors.is_empty() { return transparent; }
+        let color = rgb(pixel);
+        *cache.entry(color).or_insert_with(|| {
+            colors.iter().enumerate().min_by_key(|(_, x)| distance(**x, color)).map_or(0, |(i, _)| i as u8)
+        })
+    }).collect()
+}
+
+// Loops forever. The frames are mapped to `palette` when there is one, its alpha is
+// ignored, else to the colours quantized from the frames.
+pub fn write_gif<W: Write>(frames: &[ExportFrame], palette: Option<&[[u8; 4]]>, writer: W) -> Result<(), ExportError> {
+    let (width, height) = frame_size(frames)?;
+    let width = u16::try_from(width).map_err(|_| ExportError::TooLarge)?;
+    let height = u16::try_from(height).map_err(|_| ExportError::TooLarge)?;
+    let colors = match palette {
+        Some(palette) => palette.iter().take(GIF_MAX_COLORS).map(|x| [x[0], x[1], x[2]]).collect(),
+        None => quantize(frames, GIF_MAX_COLORS),
+    };
+    let transparent = colors.len() as u8;
+    let mut table: Vec<u8> = colors.iter().flatten().copied().collect();
+    table.extend([0; 3]);
+
+    let mut encoder = gif::Encoder::new(writer, width, height, &table)?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    let mut cache = HashMap::new();
+    for frame in frames {
+        encoder.write_frame(&gif::Frame {
+            width,
+            height,
+            buffer: Cow::Owned(index_pixels(&frame.image, &colors, &mut cache)),
+            // in hundredths of a second.
+            delay: ((frame.duration_ms + 5) / 10).clamp(1, u16::MAX as u32) as u16,
+            transparent: Some(transparent),
+            dispose: gif::DisposalMethod::Background,
+            ..Default::default()
+        })?;
+    }
+    Ok(())
+}
+
+// Loops forever, every frame covers the whole image.
+pub fn write_apng<W: Write>(frames: &[ExportFrame], writer: W) -> Result<(), ExportError> {
+    let (width, height) = frame_size(frames)?;
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.set_frame_delay(frame.duration_ms.min(u16::MAX as u32) as u16, 1000)?;
+        writer.write_image_data(frame.image.as_raw())?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SheetLayout {
+    Row,
+    Column,
+    // zero columns make the grid about square.
+    Grid { columns: u32 },
+    #[default]
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SheetOptions {
+    pub layout: SheetLayout,
+    // between the frames and around them.
+    pub padding: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SheetRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteSheet {
+    pub image: RgbaImage,
+    pub rects: Vec<SheetRect>,
+}
+
+impl SheetLayout {
+    pub fn columns(&self, frame_count: u32) -> u32 {
+        let columns = match *self {
+            SheetLayout::Row => frame_count,
+            SheetLayout::Column => 1,
+            SheetLayout::Grid { columns } if columns > 0 => columns.min(frame_count),
+            SheetLayout::Grid { .. } | SheetLayout::Auto => (frame_count as f64).sqrt().ceil() as u32,
+        };
+        columns.max(1)
+    }
+}
+
+pub fn pack_sheet(frames: &[ExportFrame], options: SheetOptions) -> Result<SpriteSheet, ExportError> {
+    let (width, height) = frame_size(frames)?;
+    let count = frames.len() as u32;
+    let columns = options.layout.columns(count);
+    let rows = count.div_ceil(columns);
+    let padding = options.padding;
+    let span = |cells: u32, size: u32| {
+        cells.checked_mul(size + padding).and_then(|x| x.checked_add(padding)).ok_or(ExportError::TooLarge)
+    };
+    let mut image = RgbaImage::new(span(columns, width)?, span(rows, height)?);
+
+    let mut rects = Vec::with_capacity(frames.len());
+    for (i, frame) in frames.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let rect = SheetRect {
+            x: padding + column * (width + padding),
+            y: padding + row * (height + padding),
+            w: width,
+            h: height,
+        };
+        image::imageops::replace(&mut image, &frame.image, rect.x as i64, rect.y as i64);
+        rects.push(rect);
+    }
+    Ok(SpriteSheet {
+        image,
+        rects,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SheetSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+// The fields of Aseprite's json export in the array form.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetFrame {
+    pub filename: String,
+    pub frame: SheetRect,
+    pub rotated: bool,
+    pub trimmed: bool,
+    pub sprite_source_size: SheetRect,
+    pub source_size: SheetSize,
+    pub duration: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetMeta {
+    pub app: String,
+    pub version: String,
+    pub image: String,
+    pub format: String,
+    pub size: SheetSize,
+    pub scale: String,
+    pub frame_tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SheetMetadata {
+    pub frames: Vec<SheetFrame>,
+    pub meta: SheetMeta,
+}
+
+// `image_name` is the sheet file as seen from the json file.
+pub fn sheet_metadata(sheet: &SpriteSheet, frames: &[ExportFrame], image_name: &str) -> SheetMetadata {
+    let stem = Path::new(image_name).file_stem().map_or(Cow::Borrowed("sprite"), |x| x.to_string_lossy());
+    SheetMetadata {
+        frames: sheet.rects.iter().zip(frames).enumerate().map(|(i, (rect, frame))| SheetFrame {
+            filename: format!("{} {}", stem, i),
+            frame: *rect,
+            rotated: false,
+            trimmed: false,
+            sprite_source_size: SheetRect {
+                x: 0,
+                y: 0,
+                w: rect.w,
+                h: rect.h,
+            },
+            source_size: SheetSize {
+                w: rect.w,
+                h: rect.h,
+            },
+            duration: frame.duration_ms,
+        }).collect(),
+        meta: SheetMeta {
+            app: "pixelin".to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            image: image_name.to_owned(),
+            format: "RGBA8888".to_owned(),
+            size: SheetSize {
+                w: sheet.image.width(),
+                h: sheet.image.height(),
+            },
+            scale: "1".to_owned(),
+            frame_tags: Vec::new(),
+        },
+    }
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, ExportError> {
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+pub fn save_gif(frames: &[ExportFrame], palette: Option<&[[u8; 4]]>, path: &Path) -> Result<(), ExportError> {
+    let mut writer = create(path)?;
+    write_gif(frames, palette, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn save_apng(frames: &[ExportFrame], path: &Path) -> Result<(), ExportError> {
+    let mut writer = create(path)?;
+    write_apng(frames, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Writes the sheet to `path` as png and its metadata next to it with the json extension.
+pub fn save_sprite_sheet(frames: &[ExportFrame], options: SheetOptions, path: &Path) -> Result<(), ExportError> {
+    let sheet = pack_sheet(frames, options)?;
+    sheet.image.save_with_format(path, image::ImageFormat::Png)?;
+    let image_name = path.file_name().map_or(Cow::Borrowed("sheet.png"), |x| x.to_string_lossy());
+    let metadata = sheet_metadata(&sheet, frames, &image_name);
+    let mut writer = create(&path.with_extension("json"))?;
+    serde_json::to_writer_pretty(&mut writer, &metadata)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    fn frames() -> Vec<ExportFrame> {
+        (0..3).map(|i| ExportFrame {
+            image: RgbaImage::from_fn(2, 2, |x, _| if x == i % 2 { RED } else { CLEAR }),
+            duration_ms: 100 + i * 50,
+        }).collect()
+    }
+
+    #[test]
+    fn test_upscale() {
+        let scaled = upscale(frames(), Scaler::Nearest(3)).unwrap();
+        assert_eq!(scaled[1].image.dimensions(), (6, 6));
+        assert_eq!(scaled[1].duration_ms, 150);
+        assert!(matches!(upscale(frames(), Scaler::Nearest(100000)), Err(ExportError::TooLarge)));
+    }
+
+    #[test]
+    fn test_quantize() {
+        assert_eq!(quantize(&frames(), GIF_MAX_COLORS), vec![[255, 0, 0]]);
+
+        // a gradient of 64 greys into 4 colours, each the average of its box.
+        let image = RgbaImage::from_fn(64, 1, |x, _| Rgba([x as u8 * 4, x as u8 * 4, x as u8 * 4, 255]));
+        let gradient = [ExportFrame { image, duration_ms: 100 }];
+        let mut colors = quantize(&gradient, 4);
+        colors.sort_unstable();
+        assert_eq!(colors, vec![[30; 3], [94; 3], [158; 3], [222; 3]]);
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut bytes = Vec::new();
+        write_gif(&frames(), None, &mut bytes).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(Cursor::new(bytes)).unwrap();
+        assert_eq!(decoder.global_palette().map(|x| &x[..3]), Some(&[255, 0, 0][..]));
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+            assert_eq!(frame.transparent, Some(1));
+            assert_eq!(frame.buffer[0], if delays.len() == 2 { 1 } else { 0 });
+        }
+        assert_eq!(delays, vec![10, 15, 20]);
+
+        let mismatched = vec![frames()[0].clone(), ExportFrame { image: RgbaImage::new(3, 2), duration_ms: 100 }];
+        assert!(matches!(write_gif(&mismatched, None, Vec::new()), Err(ExportError::SizeMismatch { index: 1 })));
+        assert!(matches!(write_gif(&[], None, Vec::new()), Err(ExportError::NoFrames)));
+    }
+
+    #[test]
+    fn test_apng() {
+        let mut bytes = Vec::new();
+        write_apng(&frames(), &mut bytes).unwrap();
+
+        let mut reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        let control = reader.info().animation_control.unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (3, 0));
+        let mut buffer = vec![0; 2 * 2 * 4];
+        for frame in frames() {
+            reader.next_frame(&mut buffer).unwrap();
+            let control = reader.info().frame_control.unwrap();
+            assert_eq!((control.delay_num as u32, control.delay_den), (frame.duration_ms, 1000));
+            assert_eq!(buffer, frame.image.into_raw());
+        }
+    }
+
+    #[test]
+    fn test_sprite_sheet() {
+        let frames = frames();
+        let options = SheetOptions {
+            layout: SheetLayout::Grid { columns: 2 },
+            padding: 1,
+        };
+        let sheet = pack_sheet(&frames, options).unwrap();
+        assert_eq!(sheet.image.dimensions(), (7, 7));
+        assert_eq!(sheet.rects[2], SheetRect { x: 1, y: 4, w: 2, h: 2 });
+        assert_eq!(*sheet.image.get_pixel(4, 1), CLEAR);
+        assert_eq!(*sheet.image.get_pixel(5, 1), RED);
+
+        assert_eq!(SheetLayout::Row.columns(5), 5);
+        assert_eq!(SheetLayout::Column.columns(5), 1);
+        assert_eq!(SheetLayout::Auto.columns(5), 3);
+
+        let metadata = sheet_metadata(&sheet, &frames, "walk.png");
+        assert_eq!(metadata.frames[1].filename, "walk 1");
+        assert_eq!(metadata.frames[1].frame, SheetRect { x: 4, y: 1, w: 2, h: 2 });
+        assert_eq!(metadata.frames[1].duration, 150);
+        assert_eq!(metadata.meta.size, SheetSize { w: 7, h: 7 });
+    }
+}
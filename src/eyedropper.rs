@@ -0,0 +1,129 @@
+// Picks a colour of the canvas into `ToolsConfig::primary_color`, with the
+// eyedropper tool or by alt clicking with a painting tool.
+use bevy::prelude::*;
+use bevy_pancam::PanCam;
+use image::RgbaImage;
+
+use crate::canvas::Canvas;
+use crate::config::AppConfig;
+use crate::layers::LayerStack;
+use crate::painting::window_to_canvas;
+use crate::pen_input::PenState;
+use crate::shapes::ShapeKind;
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, pick_color.before(crate::painting::paint_on_canvas));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum SampleSource {
+    ActiveLayer,
+    #[default]
+    Composite,
+}
+
+// The average of the `size`x`size` pixels around `loc`, weighted by their alpha
+// so that transparent pixels don't darken the colour. The part outside of the image is left out.
+pub(crate) fn sample_color(image: &RgbaImage, loc: UVec2, size: u32) -> Option<Srgba> {
+    let URect { min, max } = sample_window(UVec2::from(image.dimensions()), loc, size)?;
+    let mut sum = [0f32; 4];
+    let mut count = 0f32;
+    for y in min.y..max.y {
+        for x in min.x..max.x {
+            let p = Srgba::from_u8_array(image.get_pixel(x, y).0);
+            sum[0] += p.red * p.alpha;
+            sum[1] += p.green * p.alpha;
+            sum[2] += p.blue * p.alpha;
+            sum[3] += p.alpha;
+            count += 1.;
+        }
+    }
+    if sum[3] <= 0. {
+        return Some(Srgba::NONE);
+    }
+    Some(Srgba::new(sum[0] / sum[3], sum[1] / sum[3], sum[2] / sum[3], sum[3] / count))
+}
+
+// The pixels sampled around `loc` in an image of `image_size`.
+fn sample_window(image_size: UVec2, loc: UVec2, size: u32) -> Option<URect> {
+    if loc.x >= image_size.x || loc.y >= image_size.y { return None; }
+    let half = size.max(1) / 2;
+    let min = loc.saturating_sub(UVec2::splat(half));
+    let max = (min + UVec2::splat(size.max(1))).min(image_size);
+    Some(URect { min, max })
+}
+
+pub(crate) fn picking_by_alt(keys: &ButtonInput<KeyCode>, tool: Option<&str>) -> bool {
+    let painting = tool.is_some_and(|x| matches!(x, "pencil" | "bucket") || ShapeKind::from_tool_name(x).is_some());
+    painting && keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight])
+}
+
+// The eyedropper keeps picking while it is dragged, alt picks once per click.
+fn pick_color(
+    pen: Res<PenState>,
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Single<(&Camera, &GlobalTransform), With<PanCam>>,
+    canvas: Single<&GlobalTransform, With<Canvas>>,
+    interactions: Query<&Interaction>,
+    layers: Res<LayerStack>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut dragging: Local<bool>,
+) {
+    let tools_config = &app_config.tools_config;
+    let tool = tools_config.current_tool.as_deref();
+    if !pen.pressed() {
+        *dragging = false;
+        return;
+    }
+    let outside_ui = interactions.iter().all(|x| *x == Interaction::None);
+    if pen.just_pressed() {
+        *dragging = tool == Some("eyedropper") && outside_ui;
+    }
+    let by_alt = pen.just_pressed() && outside_ui && picking_by_alt(&keys, tool);
+    if !*dragging && !by_alt { return; }
+
+    let (camera, camera_transform) = camera.into_inner();
+    let Some(loc) = pen.sample.and_then(|x|
+        window_to_canvas(x.position, camera, camera_transform, *canvas, layers.size())) else { return; };
+    if loc.x < 0. || loc.y < 0. { return; }
+
+    let loc = loc.floor().as_uvec2();
+    let size = tools_config.eyedropper_size;
+    let color = match tools_config.eyedropper_source {
+        SampleSource::ActiveLayer => sample_color(&layers.active().image, loc, size),
+        // only the sampled pixels are composited.
+        SampleSource::Composite => sample_window(layers.size(), loc, size)
+            .and_then(|x| sample_color(&layers.composite_rect(x), loc - x.min, size)),
+    };
+    let Some(color) = color else { return; };
+    let mut primary_color = tools_config.primary_color.write().expect("write lock failed.");
+    if *primary_color != color {
+        *primary_color = color;
+        info!("Picked {}.", color.to_hex());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_sample_color() {
+        let mut image = RgbaImage::new(3, 3);
+        image.put_pixel(1, 1, Rgba([255, 0, 0, 255]));
+        image.put_pixel(0, 0, Rgba([0, 0, 255, 255]));
+
+        assert_eq!(sample_color(&image, UVec2::new(1, 1), 1).unwrap().to_u8_array(), [255, 0, 0, 255]);
+        assert_eq!(sample_color(&image, UVec2::new(2, 2), 1), Some(Srgba::NONE));
+        assert_eq!(sample_color(&image, UVec2::new(3, 0), 1), None);
+
+        // the transparent pixels only lower the alpha.
+        let average = sample_color(&image, UVec2::new(1, 1), 3).unwrap();
+        assert_eq!(average.to_u8_array(), [128, 0, 128, 57]);
+
+        // the square is cut by the border.
+        let corner = sample_color(&image, UVec2::new(0, 0), 2).unwrap();
+        assert_eq!(corner.to_u8_array(), [128, 0, 128, 128]);
+    }
+}
@@ -0,0 +1,317 @@
+use bevy::prelude::*;
+use bevy_pancam::PanCam;
+use image::{ImageFormat, ImageResult, RgbaImage};
+use pixelin::export;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::config::AppConfig;
+use crate::animation::Animation;
+use crate::history::History;
+use crate::layers::{LayerInfo, LayerStack};
+use crate::menu_bar::MenuClicked;
+use crate::palette::{IndexedCanvas, Palette};
+use crate::palette_io::{self, PaletteFormat};
+use crate::project::{self, Project, ProjectFrame, ProjectLayer, ToolsSelection, ViewTransform};
+use crate::tools_bar::SelectTool;
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<OpenedFile>()
+        .add_systems(Update, (on_file_menu_clicked, on_project_menu_clicked, on_palette_menu_clicked,
+            on_export_menu_clicked));
+}
+
+// The file the canvas was loaded from or saved to.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct OpenedFile {
+    pub path: Option<PathBuf>,
+}
+
+pub(crate) fn encode_png(image: &RgbaImage) -> ImageResult<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, ImageFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+pub(crate) fn decode_png(bytes: &[u8]) -> ImageResult<RgbaImage> {
+    Ok(image::load_from_memory_with_format(bytes, ImageFormat::Png)?.to_rgba8())
+}
+
+pub(crate) fn load_png(path: &Path) -> ImageResult<RgbaImage> {
+    decode_png(&std::fs::read(path)?)
+}
+
+pub(crate) fn save_png(image: &RgbaImage, path: &Path) -> ImageResult<()> {
+    image.save_with_format(path, ImageFormat::Png)
+}
+
+fn png_dialog(opened_file: &OpenedFile) -> rfd::FileDialog {
+    let dialog = rfd::FileDialog::new().add_filter("PNG", &["png"]);
+    match &opened_file.path {
+        Some(path) => {
+            let dialog = match path.parent() {
+                Some(dir) => dialog.set_directory(dir),
+                None => dialog,
+            };
+            match path.file_name() {
+                Some(name) => dialog.set_file_name(name.to_string_lossy()),
+                None => dialog,
+            }
+        },
+        None => dialog.set_file_name("untitled.png"),
+    }
+}
+
+fn project_dialog(opened_file: &OpenedFile) -> rfd::FileDialog {
+    let dialog = rfd::FileDialog::new()
+        .add_filter("Pixelin project", &[project::PROJECT_EXTENSION]);
+    match opened_file.path.as_ref().and_then(|x| x.parent()) {
+        Some(dir) => dialog.set_directory(dir),
+        None => dialog,
+    }
+}
+
+fn project_from_editor(
+    layers: &LayerStack,
+    animation: &Animation,
+    app_config: &AppConfig,
+    indexed: &IndexedCanvas,
+    view: ViewTransform,
+) -> Project {
+    let tools_config = &app_config.tools_config;
+    let durations = animation.durations();
+    Project {
+        canvas_size: layers.size(),
+        frames: (0..animation.len()).map(|i| ProjectFrame {
+            duration_ms: durations[i],
+            layers: animation.layers(i, layers).expect("frame index out of range.").layers().iter()
+                .map(|x| ProjectLayer {
+                    info: x.info.clone(),
+                    image: x.image.clone(),
+                }).collect(),
+        }).collect(),
+        palette: tools_config.palette.colors().to_vec(),
+        indexed: indexed.enabled,
+        tools: ToolsSelection {
+            tool: tools_config.current_tool.clone(),
+            mix_method: tools_config.mix_method,
+            mask: tools_config.pressure_mask.name.clone(),
+            pattern: tools_config.pattern.name.clone(),
+        },
+        view,
+    }
+}
+
+fn on_project_menu_clicked(
+    mut clicked: EventReader<MenuClicked>,
+    mut opened_file: ResMut<OpenedFile>,
+    camera: Single<(&mut Transform, &mut OrthographicProjection), With<PanCam>>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+    mut animation: ResMut<Animation>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+    mut indexed: ResMut<IndexedCanvas>,
+    mut select_tool: EventWriter<SelectTool>,
+) {
+    let (mut camera_transform, mut projection) = camera.into_inner();
+    for ev in clicked.read() {
+        match ev.menu_name.as_str() {
+            "open_project" => {
+                let Some(path) = project_dialog(&opened_file).pick_file() else { continue; };
+                let project = match project::load_project(&path) {
+                    Ok(o) => o,
+                    Err(e) => {
+                        error!("Open {} failed: {}", path.display(), e);
+                        continue;
+                    }
+                };
+
+                let size = project.canvas_size;
+                app_config.default_canvas_size.width = size.x;
+                app_config.default_canvas_size.height = size.y;
+                app_config.tools_config.mix_method = project.tools.mix_method;
+                if !app_config.tools_config.select_mask(&project.tools.mask) {
+                    warn!("There is no mask named {}, the current one is kept.", project.tools.mask);
+                }
+                if !app_config.tools_config.select_brush_preset(&project.tools.pattern) {
+                    warn!("There is no brush named {}, the dot is used.", project.tools.pattern);
+                    app_config.tools_config.select_brush_preset("dot");
+                }
+                select_tool.send(SelectTool(project.tools.tool));
+                // older projects have no palette, the current one is kept.
+                if !project.palette.is_empty() {
+                    app_config.tools_config.palette = Palette::new(project.palette);
+                }
+                indexed.enabled = project.indexed;
+                let frames = project.frames.into_iter().map(|frame| (frame.duration_ms,
+                    LayerStack::from_layers(size, frame.layers.into_iter().map(|x| (x.info, x.image)))
+                )).collect();
+                *animation = Animation::from_frames(frames, &mut layers, &mut history);
+
+                camera_transform.translation = project.view.translation.extend(camera_transform.translation.z);
+                projection.scale = project.view.scale;
+                opened_file.path = Some(path);
+            },
+            "save_project" => {
+                let Some(path) = project_dialog(&opened_file)
+                    .set_file_name(format!("untitled.{}", project::PROJECT_EXTENSION))
+                    .save_file() else { continue; };
+                let view = ViewTransform {
+                    translation: camera_transform.translation.truncate(),
+                    scale: projection.scale,
+                };
+                match project::save_project(&project_from_editor(&layers, &animation, &app_config, &indexed, view), &path) {
+                    Ok(()) => {
+                        opened_file.path = Some(path);
+                    },
+                    Err(e) => {
+                        error!("Save {} failed: {}", path.display(), e);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn on_file_menu_clicked(
+    mut clicked: EventReader<MenuClicked>,
+    mut opened_file: ResMut<OpenedFile>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+    mut animation: ResMut<Animation>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+) {
+    for ev in clicked.read() {
+        match ev.menu_name.as_str() {
+            "open" => {
+                let Some(path) = png_dialog(&opened_file).pick_file() else { continue; };
+                match load_png(&path) {
+                    Ok(rgba) => {
+                        let (width, height) = rgba.dimensions();
+                        app_config.default_canvas_size.width = width;
+                        app_config.default_canvas_size.height = height;
+                        *layers = LayerStack::from_layers(UVec2::new(width, height), [(LayerInfo {
+                            name: "background".to_owned(),
+                            ..default()
+                        }, rgba)]);
+                        history.clear();
+                        *animation = Animation::default();
+                        opened_file.path = Some(path);
+                    },
+                    Err(e) => {
+                        error!("Open {} failed: {}", path.display(), e);
+                    }
+                }
+            },
+            "save" => {
+                let Some(path) = png_dialog(&opened_file).save_file() else { continue; };
+                match save_png(&layers.composite(), &path) {
+                    Ok(()) => {
+                        opened_file.path = Some(path);
+                    },
+                    Err(e) => {
+                        error!("Save {} failed: {}", path.display(), e);
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn palette_dialog() -> rfd::FileDialog {
+    let extensions = PaletteFormat::ALL.map(|x| x.extension());
+    rfd::FileDialog::new().add_filter("Palette", &extensions)
+}
+
+fn on_palette_menu_clicked(
+    mut clicked: EventReader<MenuClicked>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+) {
+    for ev in clicked.read() {
+        match ev.menu_name.as_str() {
+            "import_palette" => {
+                let Some(path) = palette_dialog().pick_file() else { continue; };
+                match palette_io::load_palette(&path) {
+                    Ok(palette) => {
+                        app_config.tools_config.palette = palette;
+                    },
+                    Err(e) => {
+                        error!("Import {} failed: {}", path.display(), e);
+                    }
+                }
+            },
+            "export_palette" => {
+                let Some(path) = palette_dialog().set_file_name("palette.gpl").save_file() else { continue; };
+                if let Err(e) = palette_io::save_palette(&app_config.tools_config.palette, &path) {
+                    error!("Export {} failed: {}", path.display(), e);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+fn export_dialog(opened_file: &OpenedFile, name: &str, extension: &str) -> rfd::FileDialog {
+    let stem = opened_file.path.as_ref().and_then(|x| x.file_stem())
+        .map_or("untitled".into(), |x| x.to_string_lossy());
+    let dialog = rfd::FileDialog::new()
+        .add_filter(name, &[extension])
+        .set_file_name(format!("{}.{}", stem, extension));
+    match opened_file.path.as_ref().and_then(|x| x.parent()) {
+        Some(dir) => dialog.set_directory(dir),
+        None => dialog,
+    }
+}
+
+// The palette is used for gif when the colours are kept to it, else they are quantized.
+fn on_export_menu_clicked(
+    mut clicked: EventReader<MenuClicked>,
+    opened_file: Res<OpenedFile>,
+    layers: Res<LayerStack>,
+    animation: Res<Animation>,
+    indexed: Res<IndexedCanvas>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    let frames = || export::upscale(animation.export_frames(&layers), app_config.export_scaler);
+    for ev in clicked.read() {
+        let result = match ev.menu_name.as_str() {
+            "export_gif" => {
+                let Some(path) = export_dialog(&opened_file, "GIF", "gif").save_file() else { continue; };
+                let palette = &app_config.tools_config.palette;
+                let fixed = (indexed.enabled || app_config.tools_config.palette_lock) && !palette.is_empty();
+                (frames().and_then(|x| export::save_gif(&x, fixed.then_some(palette.colors()), &path)), path)
+            },
+            "export_apng" => {
+                let Some(path) = export_dialog(&opened_file, "APNG", "png").save_file() else { continue; };
+                (frames().and_then(|x| export::save_apng(&x, &path)), path)
+            },
+            "export_sheet" => {
+                let Some(path) = export_dialog(&opened_file, "Sprite sheet", "png").save_file() else { continue; };
+                (frames().and_then(|x| export::save_sprite_sheet(&x, app_config.sheet_options, &path)), path)
+            },
+            _ => continue,
+        };
+        if let (Err(e), path) = result {
+            error!("Export {} failed: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_png_round_trip() {
+        let image = RgbaImage::from_fn(3, 5, |x, y| Rgba([x as u8 * 80, y as u8 * 60, 7, (x + y) as u8 * 30]));
+        let bytes = encode_png(&image).unwrap();
+        assert_eq!(&bytes[1..4], b"PNG");
+        let decoded = decode_png(&bytes).unwrap();
+        assert_eq!(decoded, image);
+
+        assert!(decode_png(&bytes[..10]).is_err());
+    }
+}
@@ -0,0 +1,463 @@
+// Pixels lifted off a layer that follow drags and arrow keys until they are
+// committed back, plus the clipboard of copy, cut and paste.
+use bevy::{
+    prelude::*,
+    sprite::Anchor,
+};
+use bevy_pancam::PanCam;
+use image::{GrayImage, ImageResult, Rgba, RgbaImage};
+
+use crate::canvas::{self, Canvas};
+use crate::config::AppConfig;
+use crate::file_io::{decode_png, encode_png};
+use crate::history::History;
+use crate::layers::LayerStack;
+use crate::menu_bar::MenuClicked;
+use crate::mix_methods::MixMethod;
+use crate::painting::window_to_canvas;
+use crate::pen_input::PenState;
+use crate::selection::{Selection, SelectionMode, SELECTED};
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<FloatingSelection>()
+        .init_resource::<Clipboard>()
+        .add_systems(Update, (clipboard_by_keys, on_paste_menu_clicked, move_floating).chain()
+            .before(crate::painting::paint_on_canvas))
+        .add_systems(PostUpdate, update_floating_sprite);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Floating {
+    pub layer: u64,
+    pub image: RgbaImage,
+    pub origin: IVec2, // the top left corner on the canvas, may lie outside.
+}
+
+impl Floating {
+    // Composites the pixels onto `image`, the ones off the image are dropped.
+    pub(crate) fn commit(&self, image: &mut RgbaImage, mix_method: &MixMethod) {
+        for (x, y, src) in self.image.enumerate_pixels() {
+            let (x, y) = (self.origin.x + x as i32, self.origin.y + y as i32);
+            if x < 0 || y < 0 { continue; }
+            if let Some(dst) = image.get_pixel_mut_checked(x as u32, y as u32) {
+                dst.0 = mix_method.perform_operation_4(&src.0, &dst.0);
+            }
+        }
+    }
+
+    // The opaque pixels of the floating selection on a canvas of `size`.
+    pub(crate) fn mask(&self, size: UVec2) -> GrayImage {
+        let mut ret = GrayImage::new(size.x, size.y);
+        for (x, y, src) in self.image.enumerate_pixels() {
+            let (x, y) = (self.origin.x + x as i32, self.origin.y + y as i32);
+            if x < 0 || y < 0 || src.0[3] == 0 { continue; }
+            if let Some(dst) = ret.get_pixel_mut_checked(x as u32, y as u32) {
+                *dst = SELECTED;
+            }
+        }
+        ret
+    }
+}
+
+// The selected pixels of `image` cut to the bounds of the selection,
+// the whole image without a selection.
+pub(crate) fn copy_selected(image: &RgbaImage, selection: &Selection) -> Option<(RgbaImage, UVec2)> {
+    let rect = match selection.mask() {
+        Some(_) => selection.bounds()?,
+        None => URect::new(0, 0, image.width(), image.height()),
+    };
+    let size = rect.size();
+    let ret = RgbaImage::from_fn(size.x, size.y, |x, y| {
+        let (x, y) = (rect.min.x + x, rect.min.y + y);
+        if selection.contains(x, y) { *image.get_pixel(x, y) } else { Rgba([0; 4]) }
+    });
+    Some((ret, rect.min))
+}
+
+pub(crate) fn clear_selected(image: &mut RgbaImage, selection: &Selection) {
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if selection.contains(x, y) {
+            *pixel = Rgba([0; 4]);
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub(crate) struct FloatingSelection(pub Option<Floating>);
+
+#[derive(Resource, Debug, Default)]
+pub(crate) struct Clipboard {
+    pub image: Option<RgbaImage>,
+}
+
+impl Clipboard {
+    pub(crate) fn set_png(&mut self, bytes: &[u8]) -> ImageResult<()> {
+        self.image = Some(decode_png(bytes)?);
+        Ok(())
+    }
+
+    pub(crate) fn to_png(&self) -> Option<ImageResult<Vec<u8>>> {
+        self.image.as_ref().map(encode_png)
+    }
+}
+
+// Lifts the selected pixels of the active layer, the lift and the later commit are one undo step.
+pub(crate) fn lift(layers: &mut LayerStack, selection: &Selection, history: &mut History) -> Option<Floating> {
+    if !layers.active().is_editable() { return None; }
+    let (image, origin) = copy_selected(&layers.active().image, selection)?;
+    history.begin(layers.active());
+    let layer = layers.active_mut();
+    clear_selected(&mut layer.image, selection);
+    Some(Floating {
+        layer: layer.id,
+        image,
+        origin: origin.as_ivec2(),
+    })
+}
+
+// Puts the floating pixels back and selects them.
+fn commit(floating: Floating, layers: &mut LayerStack, selection: &mut Selection,
+    history: &mut History, mix_method: &MixMethod) {
+    if !history.is_recording() {
+        if let Some(layer) = layers.layer_by_id(floating.layer) {
+            history.begin(layer);
+        }
+    }
+    if let Some(layer) = layers.layer_by_id_mut(floating.layer) {
+        floating.commit(&mut layer.image, mix_method);
+    }
+    history.end(layers);
+    if selection.size() == layers.size() {
+        selection.combine(floating.mask(layers.size()), SelectionMode::Replace);
+    }
+}
+
+// Clears the selected pixels of the active layer as an undo step of their own.
+fn cut_selected(layers: &mut LayerStack, selection: &Selection, history: &mut History) -> bool {
+    if !layers.active().is_editable() { return false; }
+    // a stroke being drawn keeps its undo step.
+    if history.is_recording() {
+        info!("Can't cut in the middle of another edit.");
+        return false;
+    }
+    history.begin(layers.active());
+    clear_selected(&mut layers.active_mut().image, selection);
+    history.end(layers);
+    true
+}
+
+// Pasted pixels float over the selection or the top left corner of the active layer.
+// Returns false in the middle of another edit.
+fn paste(image: RgbaImage, floating: &mut FloatingSelection, layers: &mut LayerStack,
+    selection: &mut Selection, history: &mut History, mix_method: &MixMethod) -> bool {
+    if let Some(o) = floating.0.take() {
+        commit(o, layers, selection, history, mix_method);
+    }
+    if history.is_recording() {
+        info!("Can't paste in the middle of another edit.");
+        return false;
+    }
+    let origin = selection.bounds().map(|x| x.min.as_ivec2()).unwrap_or_default();
+    history.begin(layers.active());
+    floating.0 = Some(Floating {
+        layer: layers.active().id,
+        image,
+        origin,
+    });
+    true
+}
+
+// Ctrl+C copies, Ctrl+X cuts and Ctrl+V pastes the active layer.
+fn clipboard_by_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut clipboard: ResMut<Clipboard>,
+    mut floating: ResMut<FloatingSelection>,
+    mut layers: ResMut<LayerStack>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) { return; }
+    let mix_method = &app_config.tools_config.mix_method;
+    if keys.just_pressed(KeyCode::KeyC) || keys.just_pressed(KeyCode::KeyX) {
+        // the floating pixels are copied as they are.
+        if let Some(o) = &floating.0 {
+            clipboard.image = Some(o.image.clone());
+            if keys.just_pressed(KeyCode::KeyX) {
+                floating.0 = None;
+                history.end(&layers);
+            }
+            return;
+        }
+        let Some((image, _)) = copy_selected(&layers.active().image, &selection) else { return; };
+        clipboard.image = Some(image);
+        if keys.just_pressed(KeyCode::KeyX) {
+            cut_selected(&mut layers, &selection, &mut history);
+        }
+    } else if keys.just_pressed(KeyCode::KeyV) {
+        let Some(image) = clipboard.image.clone() else { return; };
+        if !layers.active().is_editable() {
+            info!("The active layer is hidden or locked.");
+            return;
+        }
+        paste(image, &mut floating, &mut layers, &mut selection, &mut history, mix_method);
+    }
+}
+
+// Pastes a PNG file.
+fn on_paste_menu_clicked(
+    mut clicked: EventReader<MenuClicked>,
+    mut clipboard: ResMut<Clipboard>,
+    mut floating: ResMut<FloatingSelection>,
+    mut layers: ResMut<LayerStack>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    for ev in clicked.read() {
+        if ev.menu_name != "paste_file" { continue; }
+        let Some(path) = rfd::FileDialog::new().add_filter("PNG", &["png"]).pick_file() else { continue; };
+        let result = std::fs::read(&path).map_err(image::ImageError::from)
+            .and_then(|bytes| clipboard.set_png(&bytes));
+        if let Err(e) = result {
+            error!("Paste {} failed: {}", path.display(), e);
+            continue;
+        }
+        if !layers.active().is_editable() {
+            info!("The active layer is hidden or locked.");
+            continue;
+        }
+        let image = clipboard.image.clone().expect("the clipboard was just set.");
+        paste(image, &mut floating, &mut layers, &mut selection, &mut history,
+            &app_config.tools_config.mix_method);
+    }
+}
+
+// With the move tool a press lifts the selection and a drag moves it. The arrow
+// keys nudge it by one pixel, by ten with shift. Enter, escape or pressing
+// with another tool commits it.
+fn move_floating(
+    pen: Res<PenState>,
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Single<(&Camera, &GlobalTransform), With<PanCam>>,
+    canvas: Single<&GlobalTransform, With<Canvas>>,
+    interactions: Query<&Interaction>,
+    mut floating: ResMut<FloatingSelection>,
+    mut layers: ResMut<LayerStack>,
+    mut selection: ResMut<Selection>,
+    mut history: ResMut<History>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut drag: Local<Option<(Vec2, IVec2)>>,
+) {
+    let mix_method = &app_config.tools_config.mix_method;
+    let moving = app_config.tools_config.current_tool.as_deref() == Some("move");
+    let outside_ui = interactions.iter().all(|x| *x == Interaction::None);
+
+    if keys.any_just_pressed([KeyCode::Enter, KeyCode::Escape])
+        || (!moving && pen.just_pressed() && outside_ui) {
+        if let Some(o) = floating.0.take() {
+            commit(o, &mut layers, &mut selection, &mut history, mix_method);
+        }
+        *drag = None;
+        return;
+    }
+
+    let step = if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) { 10 } else { 1 };
+    let nudge = [
+        (KeyCode::ArrowLeft, IVec2::NEG_X),
+        (KeyCode::ArrowRight, IVec2::X),
+        (KeyCode::ArrowUp, IVec2::NEG_Y),
+        (KeyCode::ArrowDown, IVec2::Y),
+    ].into_iter().filter(|x| keys.just_pressed(x.0)).map(|x| x.1 * step).sum::<IVec2>();
+    if nudge != IVec2::ZERO && moving && floating.0.is_none() && !history.is_recording() {
+        floating.0 = lift(&mut layers, &selection, &mut history);
+    }
+    if nudge != IVec2::ZERO {
+        if let Some(o) = floating.0.as_mut() {
+            o.origin += nudge;
+        }
+    }
+
+    if !moving || !pen.pressed() {
+        *drag = None;
+        return;
+    }
+    let (camera, camera_transform) = camera.into_inner();
+    let Some(loc) = pen.sample.and_then(|x|
+        window_to_canvas(x.position, camera, camera_transform, *canvas, layers.size())) else { return; };
+    if pen.just_pressed() && outside_ui {
+        if floating.0.is_none() && !history.is_recording() {
+            floating.0 = lift(&mut layers, &selection, &mut history);
+        }
+        *drag = floating.0.as_ref().map(|x| (loc, x.origin));
+    }
+    let Some((from, origin)) = *drag else { return; };
+    let origin = origin + (loc - from).round().as_ivec2();
+    // only a real move marks the floating pixels changed.
+    if floating.0.as_ref().is_some_and(|x| x.origin != origin) {
+        if let Some(o) = floating.0.as_mut() {
+            o.origin = origin;
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct FloatingSprite;
+
+fn update_floating_sprite(
+    mut commands: Commands,
+    floating: Res<FloatingSelection>,
+    layers: Res<LayerStack>,
+    canvas: Single<Entity, With<Canvas>>,
+    sprite: Option<Single<(Entity, &Sprite), With<FloatingSprite>>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !floating.is_changed() { return; }
+    let Some(o) = &floating.0 else {
+        if let Some(sprite) = sprite {
+            commands.entity(sprite.0).despawn();
+        }
+        return;
+    };
+
+    let half = layers.size().as_vec2() / 2.;
+    let transform = Transform::from_xyz(o.origin.x as f32 - half.x, half.y - o.origin.y as f32, 0.05);
+    match sprite {
+        Some(sprite) => {
+            let (entity, sprite) = sprite.into_inner();
+            if let Some(image) = images.get_mut(&sprite.image) {
+                canvas::rgba_to_image(o.image.clone(), image);
+            }
+            commands.entity(entity).insert(transform);
+        },
+        None => {
+            let mut image = Image::default();
+            canvas::rgba_to_image(o.image.clone(), &mut image);
+            image.sampler = bevy::image::ImageSampler::nearest();
+            let sprite = commands.spawn((
+                    FloatingSprite,
+                    Sprite {
+                        image: images.add(image),
+                        anchor: Anchor::TopLeft,
+                        ..default()
+                    },
+                    transform,
+            )).id();
+            commands.entity(*canvas).add_child(sprite);
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::selection::SelectionShape;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    fn image() -> RgbaImage {
+        RgbaImage::from_fn(4, 3, |x, y| Rgba([x as u8 * 10, y as u8 * 10, 0, 255]))
+    }
+
+    #[test]
+    fn test_copy_and_clear() {
+        let mut selection = Selection::new(UVec2::new(4, 3));
+        assert_eq!(copy_selected(&image(), &selection).unwrap(), (image(), UVec2::ZERO));
+
+        selection.select(&SelectionShape::Rect(URect::new(1, 1, 3, 2)), SelectionMode::Replace);
+        selection.select(&SelectionShape::Rect(URect::new(2, 2, 3, 3)), SelectionMode::Add);
+        let (copied, origin) = copy_selected(&image(), &selection).unwrap();
+        assert_eq!(origin, UVec2::new(1, 1));
+        assert_eq!(copied.dimensions(), (2, 2));
+        assert_eq!(copied.get_pixel(1, 1).0, [20, 20, 0, 255]);
+        assert_eq!(copied.get_pixel(0, 1).0, [0; 4]);
+
+        let mut cleared = image();
+        clear_selected(&mut cleared, &selection);
+        assert_eq!(cleared.get_pixel(2, 2).0, [0; 4]);
+        assert_eq!(cleared.get_pixel(1, 2).0, image().get_pixel(1, 2).0);
+
+        selection.select(&SelectionShape::Rect(URect::new(0, 0, 0, 0)), SelectionMode::Replace);
+        assert!(copy_selected(&image(), &selection).is_none());
+    }
+
+    #[test]
+    fn test_commit() {
+        let floating = Floating {
+            layer: 0,
+            image: RgbaImage::from_fn(2, 2, |x, _| if x == 0 { Rgba(RED) } else { Rgba([0; 4]) }),
+            origin: IVec2::new(-1, 2),
+        };
+        let mut target = image();
+        floating.commit(&mut target, &MixMethod::Normal);
+        // only (-1, 2) and (-1, 3) are red, both off the image.
+        assert_eq!(target, image());
+
+        let floating = Floating {
+            origin: IVec2::new(3, 2),
+            ..floating
+        };
+        floating.commit(&mut target, &MixMethod::Darken);
+        assert_eq!(target.get_pixel(3, 2).0, [30, 0, 0, 255]);
+        let mask = floating.mask(UVec2::new(4, 3));
+        assert_eq!(mask.enumerate_pixels().filter(|x| *x.2 == SELECTED).count(), 1);
+    }
+
+    #[test]
+    fn test_lift_and_commit_is_one_edit() {
+        let mut layers = LayerStack::from_layers(UVec2::new(4, 3), [(default(), image())]);
+        let mut selection = Selection::new(UVec2::new(4, 3));
+        selection.select(&SelectionShape::Rect(URect::new(0, 0, 1, 1)), SelectionMode::Replace);
+        let mut history = History::new(usize::MAX);
+
+        let mut floating = lift(&mut layers, &selection, &mut history).unwrap();
+        assert_eq!(layers.active().image.get_pixel(0, 0).0, [0; 4]);
+        floating.origin = IVec2::new(3, 2);
+        commit(floating, &mut layers, &mut selection, &mut history, &MixMethod::Normal);
+        assert_eq!(layers.active().image.get_pixel(3, 2).0, [0, 0, 0, 255]);
+        assert!(selection.contains(3, 2) && !selection.contains(0, 0));
+
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.active().image, image());
+        assert!(!history.undo(&mut layers));
+    }
+
+    #[test]
+    fn test_cut_and_paste_keep_a_stroke_being_drawn() {
+        let mut layers = LayerStack::from_layers(UVec2::new(4, 3), [(default(), image())]);
+        let selection = Selection::new(UVec2::new(4, 3));
+        let mut history = History::new(usize::MAX);
+
+        // a stroke is being drawn.
+        history.begin(layers.active());
+        layers.active_mut().image.put_pixel(0, 0, Rgba(RED));
+        assert!(!cut_selected(&mut layers, &selection, &mut history));
+        assert_eq!(layers.active().image.get_pixel(1, 1).0, image().get_pixel(1, 1).0);
+
+        let mut floating = FloatingSelection::default();
+        let mut selection = selection;
+        assert!(!paste(image(), &mut floating, &mut layers, &mut selection, &mut history, &MixMethod::Normal));
+        assert!(floating.0.is_none());
+
+        history.end(&layers);
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.active().image, image());
+
+        assert!(cut_selected(&mut layers, &selection, &mut history));
+        assert!(layers.active().image.pixels().all(|x| x.0 == [0; 4]));
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.active().image, image());
+    }
+
+    #[test]
+    fn test_clipboard_png() {
+        let mut clipboard = Clipboard::default();
+        assert!(clipboard.to_png().is_none());
+        clipboard.image = Some(image());
+        let bytes = clipboard.to_png().unwrap().unwrap();
+        clipboard.image = None;
+        clipboard.set_png(&bytes).unwrap();
+        assert_eq!(clipboard.image, Some(image()));
+        assert!(clipboard.set_png(b"not a png").is_err());
+    }
+}
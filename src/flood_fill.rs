@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use image::{GrayImage, Luma, RgbaImage};
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Connectivity {
+    #[default]
+    Four,
+    Eight,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FillMode {
+    #[default]
+    Contiguous, // only the area connected to the seed.
+    Global, // every pixel of the seed's color.
+}
+
+pub(crate) const FILLED: Luma<u8> = Luma([u8::MAX]);
+
+pub(crate) fn colors_match(a: &[u8; 4], b: &[u8; 4], tolerance: &[u8; 4]) -> bool {
+    (0..4).all(|i| a[i].abs_diff(b[i]) <= tolerance[i])
+}
+
+// Finds the pixels to fill from `seed`, the filled ones are `FILLED` in the returned mask.
+pub(crate) fn fill_region(
+    image: &RgbaImage,
+    seed: UVec2,
+    tolerance: &[u8; 4],
+    connectivity: Connectivity,
+    fill_mode: FillMode,
+    ) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let mut region = GrayImage::new(width, height);
+    let Some(target) = image.get_pixel_checked(seed.x, seed.y) else { return region; };
+    let target = target.0;
+
+    if fill_mode == FillMode::Global {
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if colors_match(&pixel.0, &target, tolerance) {
+                region.put_pixel(x, y, FILLED);
+            }
+        }
+        return region;
+    }
+
+    let matches = |region: &GrayImage, x: u32, y: u32| {
+        region.get_pixel(x, y)[0] == 0 && colors_match(&image.get_pixel(x, y).0, &target, tolerance)
+    };
+
+    // scanline fill, every popped seed is extended into a horizontal span.
+    let mut stack = vec![(seed.x, seed.y)];
+    while let Some((x, y)) = stack.pop() {
+        if !matches(&region, x, y) { continue; }
+
+        let mut left = x;
+        while left > 0 && matches(&region, left - 1, y) { left -= 1; }
+        let mut right = x;
+        while right + 1 < width && matches(&region, right + 1, y) { right += 1; }
+        for i in left..=right {
+            region.put_pixel(i, y, FILLED);
+        }
+
+        let (from, to) = match connectivity {
+            Connectivity::Four => (left, right),
+            Connectivity::Eight => (left.saturating_sub(1), (right + 1).min(width - 1)),
+        };
+        let rows = [y.checked_sub(1), (y + 1 < height).then_some(y + 1)];
+        for ny in rows.into_iter().flatten() {
+            let mut in_span = false;
+            for nx in from..=to {
+                if matches(&region, nx, ny) {
+                    if !in_span {
+                        stack.push((nx, ny));
+                        in_span = true;
+                    }
+                } else {
+                    in_span = false;
+                }
+            }
+        }
+    }
+    region
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    fn filled_count(region: &GrayImage) -> usize {
+        region.pixels().filter(|x| **x == FILLED).count()
+    }
+
+    // a black diagonal splitting a 5x5 white image.
+    fn diagonal() -> RgbaImage {
+        RgbaImage::from_fn(5, 5, |x, y| if x == y { BLACK } else { WHITE })
+    }
+
+    #[test]
+    fn test_connectivity() {
+        let image = diagonal();
+        let four = fill_region(&image, UVec2::new(1, 0), &[0; 4], Connectivity::Four, FillMode::Contiguous);
+        assert_eq!(filled_count(&four), 10);
+        assert_eq!(four.get_pixel(0, 1)[0], 0);
+
+        // the diagonal itself leaks through 8-connectivity.
+        let eight = fill_region(&image, UVec2::new(0, 0), &[0; 4], Connectivity::Eight, FillMode::Contiguous);
+        assert_eq!(filled_count(&eight), 5);
+        let four = fill_region(&image, UVec2::new(0, 0), &[0; 4], Connectivity::Four, FillMode::Contiguous);
+        assert_eq!(filled_count(&four), 1);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let mut image = diagonal();
+        image.put_pixel(4, 4, Rgba([250, 250, 250, 255]));
+        image.put_pixel(3, 3, Rgba([252, 252, 252, 255]));
+        let region = fill_region(&image, UVec2::new(1, 0), &[4; 4], Connectivity::Four, FillMode::Contiguous);
+        assert_eq!(region.get_pixel(3, 3)[0], u8::MAX);
+        assert_eq!(region.get_pixel(4, 4)[0], 0);
+        assert_eq!(filled_count(&region), 21);
+    }
+
+    #[test]
+    fn test_global() {
+        let image = diagonal();
+        let region = fill_region(&image, UVec2::new(1, 0), &[0; 4], Connectivity::Four, FillMode::Global);
+        assert_eq!(filled_count(&region), 20);
+        let region = fill_region(&image, UVec2::new(9, 0), &[0; 4], Connectivity::Four, FillMode::Global);
+        assert_eq!(filled_count(&region), 0);
+    }
+
+    #[test]
+    fn test_spiral() {
+        // the scanline must turn back into spans it has passed.
+        let rows = [
+            "#######",
+            "#.....#",
+            "#.###.#",
+            "#.#.#.#",
+            "#.#...#",
+            "#.#####",
+            "#......",
+        ];
+        let image = RgbaImage::from_fn(7, 7, |x, y|
+            if rows[y as usize].as_bytes()[x as usize] == b'#' { BLACK } else { WHITE });
+        let region = fill_region(&image, UVec2::new(3, 3), &[0; 4], Connectivity::Four, FillMode::Contiguous);
+        assert_eq!(filled_count(&region), image.pixels().filter(|x| **x == WHITE).count());
+    }
+}
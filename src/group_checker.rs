@@ -31,6 +31,24 @@ pub fn init_me<CheckType>(app: &mut App)
         app.insert_resource(RemoveMarkedId(sid, PhantomData::<CheckType>));
 }
 
+// Checks or unchecks `entity` from code, like clicking it. The other checkables
+// of the group are left to the caller.
+pub fn set_checked<T: Clone + Send + Sync + CheckAction + 'static>(
+    commands: &mut Commands,
+    entity: Entity,
+    checkable: &mut Checkable<T>,
+    children: &[Entity],
+    checked: bool,
+) {
+    if checked {
+        commands.entity(entity).insert(CheckMarker::<T>(PhantomData::<T>));
+        checkable.0.do_check(commands, entity, children);
+    } else {
+        commands.entity(entity).remove::<CheckMarker<T>>();
+        checkable.0.do_uncheck(commands, entity, children);
+    }
+}
+
 fn remove_marked<T>(
     mut commands: Commands,
     marked: Option<Single<(Entity, &mut Checkable<T>, &Children), With<CheckMarker<T>>>>,
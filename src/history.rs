@@ -0,0 +1,371 @@
+use bevy::prelude::*;
+use image::{imageops, RgbaImage};
+use std::collections::VecDeque;
+
+use crate::animation::Animation;
+use crate::config::AppConfig;
+use crate::layers::{Layer, LayerStack};
+use crate::palette::Palette;
+
+pub fn init_me(app: &mut App) {
+    let byte_budget = app.world().resource::<AppConfig<'static, 'static>>().history_byte_budget;
+    app.insert_resource(History::new(byte_budget))
+        .add_systems(Update, undo_redo_by_keys);
+}
+
+// The pixels of a rectangle of a layer before and after an edit.
+#[derive(Debug, Clone)]
+pub(crate) struct Edit {
+    layer: u64,
+    origin: UVec2,
+    before: RgbaImage,
+    after: RgbaImage,
+}
+
+impl Edit {
+    // Returns None when nothing has changed.
+    pub(crate) fn diff(layer: u64, before: &RgbaImage, after: &RgbaImage) -> Option<Self> {
+        assert_eq!(before.dimensions(), after.dimensions(), "Edit::diff: image size mismatch");
+        let mut min = UVec2::MAX;
+        let mut max = UVec2::ZERO;
+        for ((x, y, a), b) in before.enumerate_pixels().zip(after.pixels()) {
+            if a != b {
+                min = min.min(UVec2::new(x, y));
+                max = max.max(UVec2::new(x, y));
+            }
+        }
+        if min.x > max.x { return None; }
+
+        let size = max - min + UVec2::ONE;
+        Some(Self {
+            layer,
+            origin: min,
+            before: imageops::crop_imm(before, min.x, min.y, size.x, size.y).to_image(),
+            after: imageops::crop_imm(after, min.x, min.y, size.x, size.y).to_image(),
+        })
+    }
+
+    pub(crate) fn bytes(&self) -> usize {
+        self.before.as_raw().len() + self.after.as_raw().len()
+    }
+
+    // The layer may have been removed since.
+    fn put(&self, layers: &mut LayerStack, pixels: &RgbaImage) {
+        if let Some(layer) = layers.layer_by_id_mut(self.layer) {
+            imageops::replace(&mut layer.image, pixels, self.origin.x as i64, self.origin.y as i64);
+        }
+    }
+
+    pub(crate) fn undo(&self, layers: &mut LayerStack) {
+        self.put(layers, &self.before);
+    }
+
+    pub(crate) fn redo(&self, layers: &mut LayerStack) {
+        self.put(layers, &self.after);
+    }
+}
+
+// A change of the whole stack, like resizing the canvas or adding a layer. The
+// frames changed together share the `group`.
+#[derive(Debug, Clone)]
+struct StackEdit {
+    group: Option<u64>,
+    before: LayerStack,
+    after: LayerStack,
+    palettes: Option<Box<(Palette, Palette)>>, // before and after a palette edit.
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Pixels(Edit),
+    Stack(Box<StackEdit>),
+}
+
+impl Step {
+    fn bytes(&self) -> usize {
+        match self {
+            Step::Pixels(edit) => edit.bytes(),
+            Step::Stack(edit) => [&edit.before, &edit.after].iter()
+                .flat_map(|x| x.layers())
+                .map(|x| x.image.as_raw().len())
+                .sum(),
+        }
+    }
+
+    fn group(&self) -> Option<u64> {
+        match self {
+            Step::Pixels(_) => None,
+            Step::Stack(edit) => edit.group,
+        }
+    }
+
+    fn undo(&self, layers: &mut LayerStack) {
+        match self {
+            Step::Pixels(edit) => edit.undo(layers),
+            Step::Stack(edit) => *layers = edit.before.clone(),
+        }
+    }
+
+    fn redo(&self, layers: &mut LayerStack) {
+        match self {
+            Step::Pixels(edit) => edit.redo(layers),
+            Step::Stack(edit) => *layers = edit.after.clone(),
+        }
+    }
+}
+
+// Undo and redo stacks of the canvas. The oldest edits are dropped when
+// the stacks use more than `byte_budget`, the latest edit is always kept.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct History {
+    undo: VecDeque<Step>,
+    redo: Vec<Step>,
+    bytes: usize,
+    byte_budget: usize,
+    recording: Option<(u64, RgbaImage)>,
+}
+
+impl History {
+    pub(crate) fn new(byte_budget: usize) -> Self {
+        Self {
+            byte_budget,
+            ..default()
+        }
+    }
+
+    // Starts an edit of `layer`, e.g. when the pointer is pressed.
+    pub(crate) fn begin(&mut self, layer: &Layer) {
+        self.recording = Some((layer.id, layer.image.clone()));
+    }
+
+    pub(crate) fn byte_budget(&self) -> usize {
+        self.byte_budget
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Finishes the edit started by `begin`, e.g. when the pointer is released.
+    pub(crate) fn end(&mut self, layers: &LayerStack) {
+        let Some((id, before)) = self.recording.take() else { return; };
+        let Some(layer) = layers.layer_by_id(id) else { return; };
+        if let Some(edit) = Edit::diff(id, &before, &layer.image) {
+            self.push(edit);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.bytes = 0;
+        self.recording = None;
+    }
+
+    pub(crate) fn push(&mut self, edit: Edit) {
+        self.push_step(Step::Pixels(edit));
+    }
+
+    // Records a change of the whole stack from `before` to `layers`, one of a
+    // `group` of frames or of this frame only.
+    pub(crate) fn push_stack(&mut self, group: Option<u64>, before: LayerStack, layers: &LayerStack) {
+        self.push_step(Step::Stack(Box::new(StackEdit {
+            group,
+            before,
+            after: layers.clone(),
+            palettes: None,
+        })));
+    }
+
+    // Makes the last step a palette edit, undo and redo bring the palettes back.
+    pub(crate) fn attach_palettes(&mut self, before: Palette, after: Palette) {
+        if let Some(Step::Stack(edit)) = self.undo.back_mut() {
+            edit.palettes = Some(Box::new((before, after)));
+        }
+    }
+
+    // The palette that `undo` would bring back.
+    pub(crate) fn undo_palette(&self) -> Option<&Palette> {
+        match self.undo.back()? {
+            Step::Stack(edit) => edit.palettes.as_ref().map(|x| &x.0),
+            Step::Pixels(_) => None,
+        }
+    }
+
+    pub(crate) fn redo_palette(&self) -> Option<&Palette> {
+        match self.redo.last()? {
+            Step::Stack(edit) => edit.palettes.as_ref().map(|x| &x.1),
+            Step::Pixels(_) => None,
+        }
+    }
+
+    // The group of the stack change that `undo` or `redo` would apply next.
+    pub(crate) fn undo_group(&self) -> Option<u64> {
+        self.undo.back().and_then(|x| x.group())
+    }
+
+    pub(crate) fn redo_group(&self) -> Option<u64> {
+        self.redo.last().and_then(|x| x.group())
+    }
+
+    fn push_step(&mut self, step: Step) {
+        self.bytes -= self.redo.drain(..).map(|x| x.bytes()).sum::<usize>();
+        self.bytes += step.bytes();
+        self.undo.push_back(step);
+        while self.bytes > self.byte_budget && self.undo.len() > 1 {
+            if let Some(dropped) = self.undo.pop_front() {
+                self.bytes -= dropped.bytes();
+            }
+        }
+    }
+
+    pub(crate) fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        let Some(edit) = self.undo.pop_back() else { return false; };
+        edit.undo(layers);
+        self.redo.push(edit);
+        true
+    }
+
+    pub(crate) fn redo(&mut self, layers: &mut LayerStack) -> bool {
+        let Some(edit) = self.redo.pop() else { return false; };
+        edit.redo(layers);
+        self.undo.push_back(edit);
+        true
+    }
+}
+
+fn undo_redo_by_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<History>,
+    mut layers: ResMut<LayerStack>,
+    mut animation: ResMut<Animation>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+        || !keys.just_pressed(KeyCode::KeyZ) { return; }
+    // don't break the stroke being drawn.
+    if history.is_recording() { return; }
+
+    let (palette, done) = if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        (history.redo_palette().cloned(), animation.redo(&mut layers, &mut history))
+    } else {
+        (history.undo_palette().cloned(), animation.undo(&mut layers, &mut history))
+    };
+    if let Some(palette) = palette.filter(|_| done) {
+        app_config.tools_config.palette = palette;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    fn stroke(history: &mut History, layers: &mut LayerStack, pixels: &[(u32, u32)], color: [u8; 4]) {
+        history.begin(layers.active());
+        for (x, y) in pixels {
+            layers.active_mut().image.put_pixel(*x, *y, Rgba(color));
+        }
+        history.end(layers);
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = RgbaImage::new(8, 8);
+        assert!(Edit::diff(0, &before, &before).is_none());
+
+        let mut after = before.clone();
+        after.put_pixel(2, 5, Rgba([1, 2, 3, 4]));
+        after.put_pixel(4, 3, Rgba([1, 2, 3, 4]));
+        let edit = Edit::diff(0, &before, &after).unwrap();
+        assert_eq!(edit.origin, UVec2::new(2, 3));
+        assert_eq!(edit.after.dimensions(), (3, 3));
+        assert_eq!(edit.bytes(), 2 * 3 * 3 * 4);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut layers = LayerStack::new(UVec2::new(4, 4), [0; 4]);
+        let mut history = History::new(usize::MAX);
+        let original = layers.composite();
+
+        stroke(&mut history, &mut layers, &[(1, 1), (2, 1)], [255; 4]);
+        let stroke1 = layers.composite();
+        stroke(&mut history, &mut layers, &[(3, 3)], [9; 4]);
+        let stroke2 = layers.composite();
+
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.composite(), stroke1);
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.composite(), original);
+        assert!(!history.undo(&mut layers));
+
+        assert!(history.redo(&mut layers));
+        assert!(history.redo(&mut layers));
+        assert_eq!(layers.composite(), stroke2);
+        assert!(!history.redo(&mut layers));
+
+        // a new edit drops the redo stack.
+        history.undo(&mut layers);
+        stroke(&mut history, &mut layers, &[(0, 0)], [7; 4]);
+        assert!(!history.redo(&mut layers));
+    }
+
+    #[test]
+    fn test_edits_follow_layers() {
+        let mut layers = LayerStack::new(UVec2::new(2, 2), [0; 4]);
+        let mut history = History::new(usize::MAX);
+        layers.add_layer("ink".to_owned());
+        stroke(&mut history, &mut layers, &[(0, 0)], [255; 4]);
+
+        // the edited layer is moved to the bottom, undo still finds it.
+        layers.move_layer(1, 0);
+        layers.set_active(1);
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.layers()[0].image.get_pixel(0, 0).0, [0; 4]);
+
+        // and skips it once it is removed.
+        assert!(history.redo(&mut layers));
+        layers.remove_layer(0);
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.layers()[0].image.get_pixel(0, 0).0, [0; 4]);
+    }
+
+    #[test]
+    fn test_stack_edits() {
+        let mut layers = LayerStack::new(UVec2::new(2, 2), [0; 4]);
+        let mut history = History::new(usize::MAX);
+        let before = layers.clone();
+        layers.add_layer("ink".to_owned());
+        history.push_stack(None, before, &layers);
+        stroke(&mut history, &mut layers, &[(1, 1)], [255; 4]);
+        assert_eq!(history.undo_group(), None);
+
+        assert!(history.undo(&mut layers));
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.len(), 1);
+        assert!(history.redo(&mut layers));
+        assert!(history.redo(&mut layers));
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers.active().image.get_pixel(1, 1).0, [255; 4]);
+        // one layer before, two after and the pixel.
+        assert_eq!(history.bytes, 16 + 2 * 16 + 8);
+    }
+
+    #[test]
+    fn test_byte_budget() {
+        let mut layers = LayerStack::new(UVec2::new(4, 4), [0; 4]);
+        // each one pixel edit takes 8 bytes.
+        let mut history = History::new(20);
+        for i in 0..4 {
+            stroke(&mut history, &mut layers, &[(i, 0)], [255; 4]);
+        }
+        assert_eq!(history.bytes, 16);
+        assert!(history.undo(&mut layers));
+        assert!(history.undo(&mut layers));
+        assert!(!history.undo(&mut layers));
+        let image = &layers.active().image;
+        assert_eq!(image.get_pixel(1, 0).0, [255; 4]);
+        assert_eq!(image.get_pixel(2, 0).0, [0; 4]);
+    }
+}
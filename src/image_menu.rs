@@ -0,0 +1,223 @@
+// The Image menu: scaling and rotating the selection, or the whole canvas of
+// every frame without one, and the size of the canvas. The changes of the canvas
+// are one undo step of every frame.
+use bevy::prelude::*;
+use image::RgbaImage;
+use pixelin::transform::{self, Scaler};
+
+use crate::animation::Animation;
+use crate::canvas_size_panel::CanvasSizeDialog;
+use crate::config::AppConfig;
+use crate::floating::{self, FloatingSelection};
+use crate::history::History;
+use crate::layers::LayerStack;
+use crate::menu_bar::MenuClicked;
+use crate::selection::Selection;
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, on_image_menu_clicked);
+}
+
+// The largest width or height of the canvas.
+pub(crate) const MAX_CANVAS_SIZE: u32 = 4096;
+
+// Where the old image stays when the canvas is resized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum CanvasAnchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl CanvasAnchor {
+    // Row by row from the top left.
+    pub(crate) const ALL: [Self; 9] = [
+        Self::TopLeft, Self::Top, Self::TopRight,
+        Self::Left, Self::Center, Self::Right,
+        Self::BottomLeft, Self::Bottom, Self::BottomRight,
+    ];
+
+    // The offset of the old image in the resized canvas.
+    pub(crate) fn offset(&self, old: UVec2, new: UVec2) -> IVec2 {
+        let i = Self::ALL.iter().position(|x| x == self).expect("anchor not listed.") as i32;
+        let d = new.as_ivec2() - old.as_ivec2();
+        IVec2::new(d.x * (i % 3) / 2, d.y * (i / 3) / 2)
+    }
+}
+
+// Changes the layers of every frame and makes the new size the default one.
+pub(crate) fn change_canvas(
+    animation: &mut Animation,
+    layers: &mut LayerStack,
+    history: &mut History,
+    app_config: &mut AppConfig,
+    f: impl FnMut(&mut LayerStack),
+) -> bool {
+    if !animation.map_layers(layers, history, f) {
+        info!("The canvas can't change in the middle of an edit.");
+        return false;
+    }
+    let size = layers.size();
+    app_config.default_canvas_size.width = size.x;
+    app_config.default_canvas_size.height = size.y;
+    info!("The canvas is {}x{} now.", size.x, size.y);
+    true
+}
+
+// The rect of the pixels that aren't transparent in any layer of any frame.
+fn opaque_bounds(animation: &Animation, layers: &LayerStack) -> Option<URect> {
+    (0..animation.len())
+        .filter_map(|i| animation.layers(i, layers)?.opaque_bounds())
+        .reduce(|a, b| a.union(b))
+}
+
+fn crop(rect: URect) -> impl FnMut(&mut LayerStack) {
+    move |x| x.resize_canvas(rect.size(), -rect.min.as_ivec2(), [0; 4])
+}
+
+fn menu_scaler(menu_name: &str) -> Option<Scaler> {
+    match menu_name {
+        "scale_nearest" => Some(Scaler::Nearest(2)),
+        "scale2x" => Some(Scaler::Scale2x),
+        "scale3x" => Some(Scaler::Scale3x),
+        "hq2x" => Some(Scaler::Hq2x),
+        _ => None,
+    }
+}
+
+fn image_transform(menu_name: &str, degrees: f32) -> Option<Box<dyn Fn(&RgbaImage) -> RgbaImage>> {
+    match menu_scaler(menu_name) {
+        Some(scaler) => Some(Box::new(move |x: &RgbaImage| transform::scale(x, scaler))),
+        None if menu_name == "rotsprite" => Some(Box::new(move |x: &RgbaImage| transform::rotsprite(x, degrees))),
+        None => None,
+    }
+}
+
+// Whether an image of `size` scaled by the menu still fits in a canvas.
+fn fits_scaled(menu_name: &str, size: UVec2) -> bool {
+    let Some(scaler) = menu_scaler(menu_name) else { return true; };
+    transform::scaled_size(size.into(), scaler)
+        .is_some_and(|(width, height)| width.max(height) <= MAX_CANVAS_SIZE)
+}
+
+// A selection is lifted and transformed about its centre, it is committed like a
+// moved one.
+fn on_image_menu_clicked(
+    mut clicked: EventReader<MenuClicked>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+    mut floating: ResMut<FloatingSelection>,
+    mut layers: ResMut<LayerStack>,
+    mut animation: ResMut<Animation>,
+    selection: Res<Selection>,
+    mut history: ResMut<History>,
+    mut dialog: ResMut<CanvasSizeDialog>,
+) {
+    for ev in clicked.read() {
+        match ev.menu_name.as_str() {
+            "canvas_size" => {
+                dialog.open = true;
+                dialog.size = layers.size();
+                continue;
+            },
+            "crop_to_selection" => {
+                let Some(rect) = selection.bounds() else {
+                    info!("Nothing is selected.");
+                    continue;
+                };
+                change_canvas(&mut animation, &mut layers, &mut history, &mut app_config, crop(rect));
+                continue;
+            },
+            "trim" => {
+                let Some(rect) = opaque_bounds(&animation, &layers) else {
+                    info!("Every frame is transparent.");
+                    continue;
+                };
+                if rect.size() == layers.size() {
+                    info!("No transparent borders to trim.");
+                    continue;
+                }
+                change_canvas(&mut animation, &mut layers, &mut history, &mut app_config, crop(rect));
+                continue;
+            },
+            _ => {},
+        }
+        let Some(f) = image_transform(&ev.menu_name, app_config.rotate_degrees) else { continue; };
+
+        if floating.0.is_some() || selection.is_active() {
+            if floating.0.is_none() && !history.is_recording() {
+                floating.0 = floating::lift(&mut layers, &selection, &mut history);
+            }
+            let Some(o) = floating.0.as_mut() else {
+                info!("The active layer is hidden or locked.");
+                continue;
+            };
+            if !fits_scaled(&ev.menu_name, UVec2::from(o.image.dimensions())) {
+                info!("The scaled selection would be larger than {} pixels.", MAX_CANVAS_SIZE);
+                continue;
+            }
+            let size = UVec2::from(o.image.dimensions()).as_ivec2();
+            o.image = f(&o.image);
+            o.origin -= (UVec2::from(o.image.dimensions()).as_ivec2() - size) / 2;
+            continue;
+        }
+
+        if !fits_scaled(&ev.menu_name, layers.size()) {
+            info!("The scaled canvas would be larger than {} pixels.", MAX_CANVAS_SIZE);
+            continue;
+        }
+        change_canvas(&mut animation, &mut layers, &mut history, &mut app_config, |x| x.map_images(&f));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_anchor_offset() {
+        let (old, new) = (UVec2::new(4, 4), UVec2::new(8, 6));
+        assert_eq!(CanvasAnchor::TopLeft.offset(old, new), IVec2::ZERO);
+        assert_eq!(CanvasAnchor::Center.offset(old, new), IVec2::new(2, 1));
+        assert_eq!(CanvasAnchor::Right.offset(old, new), IVec2::new(4, 1));
+        assert_eq!(CanvasAnchor::BottomLeft.offset(old, new), IVec2::new(0, 2));
+        // shrinking cuts the far side.
+        assert_eq!(CanvasAnchor::BottomRight.offset(new, old), IVec2::new(-4, -2));
+    }
+
+    #[test]
+    fn test_fits_scaled() {
+        assert!(fits_scaled("scale3x", UVec2::new(320, 320)));
+        assert!(!fits_scaled("scale3x", UVec2::new(2880, 10)));
+        assert!(fits_scaled("scale_nearest", UVec2::splat(MAX_CANVAS_SIZE / 2)));
+        assert!(!fits_scaled("scale_nearest", UVec2::splat(u32::MAX)));
+        assert!(fits_scaled("rotsprite", UVec2::splat(MAX_CANVAS_SIZE)));
+    }
+
+    #[test]
+    fn test_trim_every_frame() {
+        let size = UVec2::new(6, 6);
+        let frame = |x: u32, y: u32| {
+            let mut layers = LayerStack::new(size, [0; 4]);
+            layers.active_mut().image.put_pixel(x, y, Rgba([255; 4]));
+            (100, layers)
+        };
+        let mut live = LayerStack::new(size, [0; 4]);
+        let mut history = History::new(usize::MAX);
+        let mut animation = Animation::from_frames(vec![frame(1, 2), frame(3, 4)], &mut live, &mut history);
+        let rect = opaque_bounds(&animation, &live).unwrap();
+        assert_eq!(rect, URect::new(1, 2, 4, 5));
+
+        assert!(animation.map_layers(&mut live, &mut history, crop(rect)));
+        assert_eq!(live.size(), UVec2::new(3, 3));
+        assert_eq!(live.composite().get_pixel(0, 0).0, [255; 4]);
+        assert_eq!(animation.layers(1, &live).unwrap().composite().get_pixel(2, 2).0, [255; 4]);
+    }
+}
@@ -0,0 +1,377 @@
+use bevy::prelude::*;
+use image::{imageops, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::canvas::{self, Canvas};
+use crate::config::AppConfig;
+use crate::mix_methods::MixMethod;
+
+pub fn init_me(app: &mut App) {
+    let app_config = app.world().resource::<AppConfig<'static, 'static>>();
+    let size = UVec2::new(app_config.default_canvas_size.width, app_config.default_canvas_size.height);
+    let stack = LayerStack::new(size, app_config.default_clear_color.to_u8_array());
+    app.insert_resource(stack)
+        .add_systems(PostUpdate, composite_to_canvas);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub(crate) struct LayerInfo {
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+    pub opacity: f32,
+    pub blend: MixMethod,
+}
+
+impl Default for LayerInfo {
+    fn default() -> Self {
+        Self {
+            name: "layer".to_owned(),
+            visible: true,
+            locked: false,
+            opacity: 1.,
+            blend: MixMethod::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Layer {
+    pub id: u64, // stays the same when the layers are reordered.
+    pub info: LayerInfo,
+    pub image: RgbaImage,
+}
+
+impl Layer {
+    pub(crate) fn is_editable(&self) -> bool {
+        self.info.visible && !self.info.locked
+    }
+}
+
+// The layers of the canvas from bottom to top, there is always at least one.
+#[derive(Resource, Debug, Clone)]
+pub(crate) struct LayerStack {
+    size: UVec2,
+    layers: Vec<Layer>,
+    active: usize,
+    next_id: u64,
+}
+
+impl LayerStack {
+    // A stack with one background layer filled by `fill`.
+    pub(crate) fn new(size: UVec2, fill: [u8; 4]) -> Self {
+        let mut ret = Self {
+            size,
+            layers: Vec::new(),
+            active: 0,
+            next_id: 0,
+        };
+        ret.push(LayerInfo {
+            name: "background".to_owned(),
+            ..default()
+        }, RgbaImage::from_pixel(size.x, size.y, Rgba(fill)));
+        ret
+    }
+
+    // Builds a stack from images of the same size, the first one is the bottom.
+    pub(crate) fn from_layers(size: UVec2, layers: impl IntoIterator<Item = (LayerInfo, RgbaImage)>) -> Self {
+        let mut ret = Self {
+            size,
+            layers: Vec::new(),
+            active: 0,
+            next_id: 0,
+        };
+        for (info, image) in layers {
+            assert_eq!(UVec2::from(image.dimensions()), size, "LayerStack::from_layers: layer size mismatch");
+            ret.push(info, image);
+        }
+        if ret.layers.is_empty() {
+            ret.push(LayerInfo::default(), RgbaImage::new(size.x, size.y));
+        }
+        ret.active = ret.layers.len() - 1;
+        ret
+    }
+
+    fn push(&mut self, info: LayerInfo, image: RgbaImage) {
+        self.layers.push(Layer {
+            id: self.next_id,
+            info,
+            image,
+        });
+        self.next_id += 1;
+    }
+
+    pub(crate) fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub(crate) fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub(crate) fn layer_mut(&mut self, index: usize) -> Option<&mut Layer> {
+        self.layers.get_mut(index)
+    }
+
+    pub(crate) fn layer_by_id(&self, id: u64) -> Option<&Layer> {
+        self.layers.iter().find(|x| x.id == id)
+    }
+
+    pub(crate) fn layer_by_id_mut(&mut self, id: u64) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|x| x.id == id)
+    }
+
+    pub(crate) fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub(crate) fn active(&self) -> &Layer {
+        &self.layers[self.active]
+    }
+
+    pub(crate) fn active_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.active]
+    }
+
+    pub(crate) fn set_active(&mut self, index: usize) {
+        if index < self.layers.len() {
+            self.active = index;
+        }
+    }
+
+    // Adds a transparent layer above the active one and makes it active.
+    pub(crate) fn add_layer(&mut self, name: String) -> usize {
+        let index = self.active + 1;
+        self.layers.insert(index, Layer {
+            id: self.next_id,
+            info: LayerInfo {
+                name,
+                ..default()
+            },
+            image: RgbaImage::new(self.size.x, self.size.y),
+        });
+        self.next_id += 1;
+        self.active = index;
+        index
+    }
+
+    // The last layer can't be removed.
+    pub(crate) fn remove_layer(&mut self, index: usize) -> Option<Layer> {
+        if self.layers.len() <= 1 || index >= self.layers.len() { return None; }
+        let ret = self.layers.remove(index);
+        if self.active >= self.layers.len() || self.active > index {
+            self.active -= 1;
+        }
+        Some(ret)
+    }
+
+    // Moves a layer to `to`, the active layer follows its move.
+    pub(crate) fn move_layer(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.layers.len() || to >= self.layers.len() { return false; }
+        let active_id = self.active().id;
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+        self.active = self.layers.iter().position(|x| x.id == active_id).unwrap_or(0);
+        true
+    }
+
+    // Composites the layer at `index` onto the one below it by its blend and opacity,
+    // a hidden one adds nothing. Not onto a hidden or locked layer.
+    pub(crate) fn merge_down(&mut self, index: usize) -> bool {
+        if index == 0 || index >= self.layers.len() || !self.layers[index - 1].is_editable() { return false; }
+        let upper = self.layers.remove(index);
+        let lower = &mut self.layers[index - 1];
+        if upper.info.visible {
+            for (dst, src) in lower.image.pixels_mut().zip(upper.image.pixels()) {
+                dst.0 = upper.info.blend.composite(&src.0, &dst.0, upper.info.opacity);
+            }
+        }
+        if self.active >= index {
+            self.active -= 1;
+        }
+        true
+    }
+
+    // Replaces every image by `f` of it, all the new images are of the new canvas size.
+    pub(crate) fn map_images(&mut self, mut f: impl FnMut(&RgbaImage) -> RgbaImage) {
+        for layer in self.layers.iter_mut() {
+            layer.image = f(&layer.image);
+        }
+        self.size = UVec2::from(self.layers[0].image.dimensions());
+        assert!(self.layers.iter().all(|x| UVec2::from(x.image.dimensions()) == self.size),
+            "LayerStack::map_images: layer size mismatch");
+    }
+
+    // Resizes the canvas, the old images go to `offset` of the new ones, which may be
+    // negative to cut them. The new area of the bottom layer is filled by `fill`.
+    pub(crate) fn resize_canvas(&mut self, size: UVec2, offset: IVec2, fill: [u8; 4]) {
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            let mut image = RgbaImage::from_pixel(size.x, size.y, Rgba(if i == 0 { fill } else { [0; 4] }));
+            imageops::replace(&mut image, &layer.image, offset.x as i64, offset.y as i64);
+            layer.image = image;
+        }
+        self.size = size;
+    }
+
+    // The smallest rect holding the pixels that aren't transparent, of every layer.
+    pub(crate) fn opaque_bounds(&self) -> Option<URect> {
+        let mut ret: Option<URect> = None;
+        for layer in &self.layers {
+            for (x, y, _) in layer.image.enumerate_pixels().filter(|x| x.2.0[3] > 0) {
+                let pixel = URect::new(x, y, x + 1, y + 1);
+                ret = Some(ret.map_or(pixel, |r| r.union(pixel)));
+            }
+        }
+        ret
+    }
+
+    // Flattens the visible layers.
+    pub(crate) fn composite(&self) -> RgbaImage {
+        self.composite_rect(URect::from_corners(UVec2::ZERO, self.size))
+    }
+
+    // The composite of `rect` only, the part outside of the canvas is dropped.
+    pub(crate) fn composite_rect(&self, rect: URect) -> RgbaImage {
+        let rect = rect.intersect(URect::from_corners(UVec2::ZERO, self.size));
+        let size = rect.size();
+        let mut ret = RgbaImage::new(size.x, size.y);
+        for layer in self.layers.iter().filter(|x| x.info.visible) {
+            let src = imageops::crop_imm(&layer.image, rect.min.x, rect.min.y, size.x, size.y).to_image();
+            for (dst, src) in ret.pixels_mut().zip(src.pixels()) {
+                dst.0 = layer.info.blend.composite(&src.0, &dst.0, layer.info.opacity);
+            }
+        }
+        ret
+    }
+}
+
+pub(crate) fn composite_to_canvas(
+    layers: Res<LayerStack>,
+    canvas: Single<&Sprite, With<Canvas>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !layers.is_changed() { return; }
+    if let Some(image) = images.get_mut(&canvas.image) {
+        canvas::rgba_to_image(layers.composite(), image);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn names(stack: &LayerStack) -> Vec<&str> {
+        stack.layers().iter().map(|x| x.info.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_composite() {
+        let mut stack = LayerStack::new(UVec2::new(2, 1), BLUE);
+        stack.add_layer("ink".to_owned());
+        stack.active_mut().image.put_pixel(0, 0, Rgba(RED));
+        let image = stack.composite();
+        assert_eq!(image.get_pixel(0, 0).0, RED);
+        assert_eq!(image.get_pixel(1, 0).0, BLUE);
+
+        stack.active_mut().info.opacity = 0.5;
+        assert_eq!(stack.composite().get_pixel(0, 0).0, [128, 0, 128, 255]);
+
+        let rect = stack.composite_rect(URect::new(1, 0, 5, 5));
+        assert_eq!(rect.dimensions(), (1, 1));
+        assert_eq!(rect.get_pixel(0, 0).0, BLUE);
+
+        stack.active_mut().info.visible = false;
+        assert_eq!(stack.composite().get_pixel(0, 0).0, BLUE);
+    }
+
+    #[test]
+    fn test_add_remove_move() {
+        let mut stack = LayerStack::new(UVec2::new(1, 1), BLUE);
+        stack.add_layer("a".to_owned());
+        stack.add_layer("b".to_owned());
+        stack.set_active(1);
+        stack.add_layer("c".to_owned());
+        assert_eq!(names(&stack), vec!["background", "a", "c", "b"]);
+        assert_eq!(stack.active().info.name, "c");
+
+        assert!(stack.move_layer(2, 0));
+        assert_eq!(names(&stack), vec!["c", "background", "a", "b"]);
+        assert_eq!(stack.active_index(), 0);
+
+        assert_eq!(stack.remove_layer(3).unwrap().info.name, "b");
+        assert_eq!(stack.remove_layer(0).unwrap().info.name, "c");
+        assert_eq!(stack.active().info.name, "background");
+        assert!(stack.remove_layer(1).is_some());
+        assert!(stack.remove_layer(0).is_none());
+        assert_eq!(stack.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_down() {
+        let mut stack = LayerStack::new(UVec2::new(2, 1), BLUE);
+        stack.add_layer("ink".to_owned());
+        stack.active_mut().image.put_pixel(1, 0, Rgba(RED));
+        stack.active_mut().info.blend = MixMethod::Lighten;
+        let expected = stack.composite();
+
+        assert!(!stack.merge_down(0));
+        assert!(stack.merge_down(1));
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.active_index(), 0);
+        assert_eq!(stack.active().image, expected);
+        assert_eq!(stack.active().info.name, "background");
+
+        // a hidden layer goes away, a locked one isn't merged into.
+        stack.add_layer("hidden".to_owned());
+        stack.active_mut().image.put_pixel(0, 0, Rgba(BLUE));
+        stack.active_mut().info.visible = false;
+        stack.layer_mut(0).unwrap().info.locked = true;
+        assert!(!stack.merge_down(1));
+        stack.layer_mut(0).unwrap().info.locked = false;
+        assert!(stack.merge_down(1));
+        assert_eq!(stack.active().image, expected);
+    }
+
+    #[test]
+    fn test_map_images() {
+        let mut stack = LayerStack::new(UVec2::new(2, 1), BLUE);
+        stack.add_layer("ink".to_owned());
+        let ids: Vec<u64> = stack.layers().iter().map(|x| x.id).collect();
+        stack.map_images(|x| image::imageops::rotate90(x));
+        assert_eq!(stack.size(), UVec2::new(1, 2));
+        assert_eq!(stack.layers()[0].image.get_pixel(0, 1).0, BLUE);
+        assert_eq!(stack.layers().iter().map(|x| x.id).collect::<Vec<_>>(), ids);
+    }
+
+    #[test]
+    fn test_resize_canvas() {
+        let mut stack = LayerStack::new(UVec2::new(2, 2), BLUE);
+        stack.add_layer("ink".to_owned());
+        stack.active_mut().image.put_pixel(1, 1, Rgba(RED));
+        assert_eq!(stack.opaque_bounds(), Some(URect::new(0, 0, 2, 2)));
+
+        stack.resize_canvas(UVec2::new(4, 3), IVec2::new(2, 1), RED);
+        assert_eq!(stack.size(), UVec2::new(4, 3));
+        let background = &stack.layers()[0].image;
+        assert_eq!(background.get_pixel(0, 0).0, RED);
+        assert_eq!(background.get_pixel(2, 1).0, BLUE);
+        assert_eq!(stack.layers()[1].image.get_pixel(0, 0).0, [0; 4]);
+        assert_eq!(stack.layers()[1].image.get_pixel(3, 2).0, RED);
+
+        // cropped to the ink.
+        stack.remove_layer(0);
+        assert_eq!(stack.opaque_bounds(), Some(URect::new(3, 2, 4, 3)));
+        stack.resize_canvas(UVec2::ONE, IVec2::new(-3, -2), [0; 4]);
+        assert_eq!(stack.composite().get_pixel(0, 0).0, RED);
+        stack.active_mut().image.put_pixel(0, 0, Rgba([0; 4]));
+        assert_eq!(stack.opaque_bounds(), None);
+    }
+}
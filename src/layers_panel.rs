@@ -0,0 +1,176 @@
+use bevy::{
+    prelude::*,
+    color::palettes::css,
+};
+use my_fluent_rs_helper::build_language_0;
+
+use crate::config::AppConfig;
+use crate::history::History;
+use crate::layers::LayerStack;
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, (layer_button_clicked, rebuild_layers_panel).chain());
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct LayersPanel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LayerAction {
+    Select(usize),
+    ToggleVisible(usize),
+    ToggleLock(usize),
+    Add,
+    Remove,
+    Raise,
+    Lower,
+    MergeDown,
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct LayerButton(pub LayerAction);
+
+pub(crate) fn build_layers_panel<'a, 'b>(parent: &'a mut ChildBuilder<'b>) {
+    parent.spawn((
+            LayersPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(8.),
+                top: Val::Percent(8.),
+                width: Val::Px(150.),
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.),
+                padding: UiRect::all(Val::Px(4.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.6)),
+    ));
+}
+
+// The changes of the stack are undo steps, the flags and the active layer aren't.
+fn layer_button_clicked(
+    query: Query<(&Interaction, &LayerButton), (Changed<Interaction>, With<Button>)>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        let active = layers.active_index();
+        match button.0 {
+            LayerAction::Select(i) => { layers.set_active(i); continue; },
+            LayerAction::ToggleVisible(i) => {
+                if let Some(layer) = layers.layer_mut(i) {
+                    layer.info.visible = !layer.info.visible;
+                }
+                continue;
+            },
+            LayerAction::ToggleLock(i) => {
+                if let Some(layer) = layers.layer_mut(i) {
+                    layer.info.locked = !layer.info.locked;
+                }
+                continue;
+            },
+            _ => {},
+        }
+
+        if history.is_recording() {
+            info!("The layers can't change in the middle of an edit.");
+            continue;
+        }
+        let before = layers.clone();
+        let changed = match button.0 {
+            LayerAction::Add => {
+                let name = format!("layer {}", layers.len());
+                layers.add_layer(name);
+                true
+            },
+            LayerAction::Remove => layers.remove_layer(active).is_some(),
+            LayerAction::Raise => layers.move_layer(active, active + 1),
+            LayerAction::Lower => active > 0 && layers.move_layer(active, active - 1),
+            LayerAction::MergeDown => {
+                let merged = layers.merge_down(active);
+                if !merged && active > 0 {
+                    info!("The layer below is hidden or locked.");
+                }
+                merged
+            },
+            _ => false,
+        };
+        if changed {
+            history.push_stack(None, before, &layers);
+        }
+    }
+}
+
+fn spawn_text_button(builder: &mut ChildBuilder, action: LayerAction, text: String, font: &TextFont, color: Srgba) {
+    builder.spawn((
+            Button,
+            LayerButton(action),
+            Node {
+                padding: UiRect::horizontal(Val::Px(3.)),
+                ..default()
+            },
+    ))
+        .with_child((Text::new(text), font.clone(), TextColor(color.into())));
+}
+
+// Rebuilds the rows when the layers, their flags or the active one change,
+// painting alone doesn't rebuild.
+fn rebuild_layers_panel(
+    mut commands: Commands,
+    panel: Single<Entity, With<LayersPanel>>,
+    layers: Res<LayerStack>,
+    asset_server: Res<AssetServer>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut last: Local<Option<(usize, Vec<(u64, String, bool, bool)>)>>,
+) {
+    let current = (layers.active_index(), layers.layers().iter()
+        .map(|x| (x.id, x.info.name.clone(), x.info.visible, x.info.locked))
+        .collect::<Vec<_>>());
+    if last.as_ref() == Some(&current) { return; }
+    *last = Some(current);
+
+    let tools_config = &app_config.tools_config;
+    let font = TextFont {
+        font: asset_server.load(&tools_config.default_font),
+        font_size: tools_config.default_text_size * 2.,
+        ..default()
+    };
+
+    commands.entity(*panel).despawn_descendants().with_children(|builder| {
+        builder.spawn(Node {
+            display: Display::Flex,
+            flex_direction: FlexDirection::Row,
+            flex_wrap: FlexWrap::Wrap,
+            ..default()
+        }).with_children(|builder| {
+            for (action, name) in [
+                (LayerAction::Add, "layer_add"),
+                (LayerAction::Remove, "layer_remove"),
+                (LayerAction::Raise, "layer_raise"),
+                (LayerAction::Lower, "layer_lower"),
+                (LayerAction::MergeDown, "layer_merge_down"),
+            ] {
+                spawn_text_button(builder, action, build_language_0(name), &font, css::LIME);
+            }
+        });
+
+        // the top layer is shown first.
+        for (i, layer) in layers.layers().iter().enumerate().rev() {
+            builder.spawn(Node {
+                display: Display::Flex,
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(4.),
+                ..default()
+            }).with_children(|builder| {
+                let visible = if layer.info.visible { "o" } else { "-" };
+                let locked = if layer.info.locked { "#" } else { "." };
+                spawn_text_button(builder, LayerAction::ToggleVisible(i), visible.to_owned(), &font, css::WHITE);
+                spawn_text_button(builder, LayerAction::ToggleLock(i), locked.to_owned(), &font, css::WHITE);
+                let color = if i == layers.active_index() { css::RED } else { css::WHITE };
+                spawn_text_button(builder, LayerAction::Select(i), layer.info.name.clone(), &font, color);
+            });
+        }
+    });
+}
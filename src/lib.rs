@@ -0,0 +1,3 @@
+// The parts of pixelin that run without the editor, for build pipelines.
+pub mod export;
+pub mod transform;
@@ -10,6 +10,30 @@ mod tools_bar;
 mod config;
 mod group_checker;
 mod menu_bar;
+mod painting;
+mod stroke;
+mod flood_fill;
+mod history;
+mod file_io;
+mod project;
+mod layers;
+mod layers_panel;
+mod pen_input;
+mod masks_panel;
+mod captured_brush;
+mod selection;
+mod floating;
+mod shapes;
+mod eyedropper;
+mod color_panel;
+mod palette;
+mod palette_panel;
+mod palette_io;
+mod cli;
+mod image_menu;
+mod canvas_size_panel;
+mod animation;
+mod timeline_panel;
 
 use bevy_pancam::*;
 use bevy::{
@@ -22,6 +46,10 @@ use std::borrow::BorrowMut;
 
 #[bevy_main]
 fn main() {
+    // a subcommand runs without a window.
+    if let Some(code) = cli::run(&std::env::args().skip(1).collect::<Vec<_>>()) {
+        std::process::exit(code);
+    }
     let mut app = App::new();
 
     app.add_plugins((DefaultPlugins.set( {
@@ -38,12 +66,33 @@ fn main() {
         ;
 
     tools_bar::init_me(&mut app);
+    layers::init_me(&mut app);
+    layers_panel::init_me(&mut app);
+    masks_panel::init_me(&mut app);
+    captured_brush::init_me(&mut app);
+    selection::init_me(&mut app);
+    floating::init_me(&mut app);
+    shapes::init_me(&mut app);
+    eyedropper::init_me(&mut app);
+    color_panel::init_me(&mut app);
+    palette::init_me(&mut app);
+    palette_panel::init_me(&mut app);
+    animation::init_me(&mut app);
+    timeline_panel::init_me(&mut app);
+    image_menu::init_me(&mut app);
+    canvas_size_panel::init_me(&mut app);
+    pen_input::init_me(&mut app);
+    painting::init_me(&mut app);
+    history::init_me(&mut app);
+    menu_bar::init_me(&mut app);
+    file_io::init_me(&mut app);
     app.run();
 }
 
 fn generates_ui(mut commands: Commands,
     mut asset_server: ResMut<AssetServer>,
     mut app_config: ResMut<config::AppConfig<'static, 'static>>,
+    mut images: ResMut<Assets<Image>>,
 ) {
     commands.spawn(Node {
         width: Val::Percent(100.),
@@ -69,7 +118,19 @@ Node {
         }
     )
     .with_children(|b|
-        tools_bar::build_tools_bar(&mut app_config.tools_config, asset_server.borrow_mut(), b));
+        tools_bar::build_tools_bar(&mut app_config.tools_config, asset_server.borrow_mut(), b))
+    .with_children(|b|
+        layers_panel::build_layers_panel(b))
+    .with_children(|b|
+        masks_panel::build_masks_panel(b))
+    .with_children(|b|
+        color_panel::build_color_panel(b, &mut images))
+    .with_children(|b|
+        palette_panel::build_palette_panel(b))
+    .with_children(|b|
+        timeline_panel::build_timeline_panel(b))
+    .with_children(|b|
+        canvas_size_panel::build_canvas_size_panel(b));
 
 }
 
@@ -94,7 +155,11 @@ fn setup(mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     app_config: Res<config::AppConfig<'static, 'static>>, ) {
 
-    commands.spawn(PanCam::default());
+    // the left button is reserved for painting.
+    commands.spawn(PanCam {
+        grab_buttons: vec![MouseButton::Right, MouseButton::Middle],
+        ..default()
+    });
 
     let canvas_image = images.add(canvas::make_canvas_image_by_config(&app_config));
 
@@ -0,0 +1,103 @@
+use bevy::{
+    prelude::*,
+    color::palettes::css,
+};
+use my_fluent_rs_helper::build_language_0;
+
+use crate::config::AppConfig;
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, (mask_button_clicked, rebuild_masks_panel).chain());
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct MasksPanel;
+
+#[derive(Component, Debug)]
+pub(crate) enum MaskButton {
+    Mask(String),
+    BrushPreset(String),
+}
+
+pub(crate) fn build_masks_panel<'a, 'b>(parent: &'a mut ChildBuilder<'b>) {
+    parent.spawn((
+            MasksPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.),
+                top: Val::Percent(8.),
+                width: Val::Px(100.),
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.),
+                padding: UiRect::all(Val::Px(4.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.6)),
+    ));
+}
+
+fn mask_button_clicked(
+    query: Query<(&Interaction, &MaskButton), (Changed<Interaction>, With<Button>)>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        let tools_config = &mut app_config.tools_config;
+        match button {
+            MaskButton::Mask(name) => if !tools_config.select_mask(name) {
+                warn!("There is no mask named {}.", name);
+            },
+            MaskButton::BrushPreset(name) => if !tools_config.select_brush_preset(name) {
+                warn!("There is no brush preset named {}.", name);
+            },
+        }
+    }
+}
+
+// Rebuilds the rows when the presets or the selected ones change.
+fn rebuild_masks_panel(
+    mut commands: Commands,
+    panel: Single<Entity, With<MasksPanel>>,
+    asset_server: Res<AssetServer>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut last: Local<Option<[(String, Vec<String>); 2]>>,
+) {
+    let tools_config = &app_config.tools_config;
+    let current = [
+        (tools_config.pressure_mask.name.clone(),
+            tools_config.mask_library.presets().iter().map(|x| x.name.clone()).collect::<Vec<_>>()),
+        (tools_config.pattern.name.clone(),
+            tools_config.brush_presets.iter().map(|x| x.name.clone()).collect::<Vec<_>>()),
+    ];
+    if last.as_ref() == Some(&current) { return; }
+
+    let font = TextFont {
+        font: asset_server.load(&tools_config.default_font),
+        font_size: tools_config.default_text_size * 2.,
+        ..default()
+    };
+
+    commands.entity(*panel).despawn_descendants().with_children(|builder| {
+        for (title, (selected, names)) in ["masks", "brush_presets"].into_iter().zip(&current) {
+            builder.spawn((Text::new(build_language_0(title)), font.clone(), TextColor(css::LIME.into())));
+            for name in names {
+                let color = if name == selected { css::RED } else { css::WHITE };
+                let button = match title {
+                    "masks" => MaskButton::Mask(name.clone()),
+                    _ => MaskButton::BrushPreset(name.clone()),
+                };
+                builder.spawn((
+                        Button,
+                        button,
+                        Node {
+                            padding: UiRect::horizontal(Val::Px(3.)),
+                            ..default()
+                        },
+                ))
+                    .with_child((Text::new(name.clone()), font.clone(), TextColor(color.into())));
+            }
+        }
+    });
+    *last = Some(current);
+}
@@ -6,7 +6,31 @@ use crate::config::MenuConfig;
 use my_fluent_rs_helper::build_language_0;
 
 #[derive(Component, Debug)]
-pub(crate) struct TopMenu;
+pub(crate) struct TopMenu {
+    pub menu_name: String,
+}
+
+// Sent when a top menu entry is pressed.
+#[derive(Event, Debug, Clone)]
+pub(crate) struct MenuClicked {
+    pub menu_name: String,
+}
+
+pub fn init_me(app: &mut App) {
+    app.add_event::<MenuClicked>()
+        .add_systems(Update, top_menu_clicked);
+}
+
+fn top_menu_clicked(
+    query: Query<(&Interaction, &TopMenu), (Changed<Interaction>, With<Button>)>,
+    mut clicked: EventWriter<MenuClicked>,
+) {
+    for (interaction, top_menu) in &query {
+        if *interaction == Interaction::Pressed {
+            clicked.send(MenuClicked { menu_name: top_menu.menu_name.clone() });
+        }
+    }
+}
 
 pub(crate) fn build_menu_bar<'a, 'b>(menu_config: &MenuConfig,
     asset_server: &mut AssetServer,
@@ -30,14 +54,17 @@ fn build_top_menu<'a, 'b>(menu_config: &MenuConfig,
     )
         .with_children(|builder| {
             for x in &menu_config.menu_info {
-                builder.spawn(Node {
-                    height: Val::Percent(100.),
-                    display: Display::Flex,
-                    flex_direction: FlexDirection::Column,
-                    align_items: AlignItems::Center,
-                    justify_content: JustifyContent::FlexStart,
-                    ..default()
-                })
+                builder.spawn((
+                        Button,
+                        TopMenu { menu_name: x.name.clone() },
+                        Node {
+                            height: Val::Percent(100.),
+                            display: Display::Flex,
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::FlexStart,
+                            ..default()
+                        }))
                 .with_child(ImageNode::new(
                         match &x.icon_handle {
                             None => { panic!("Didn't load assets.") },
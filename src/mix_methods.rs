@@ -1,4 +1,9 @@
-#[derive(Default, Debug, )]
+use serde::{Deserialize, Serialize};
+
+// How a source color `a` is mixed onto a backdrop color `b`.
+// The formulas follow the W3C "Compositing and Blending" spec, the blend
+// result is composited source-over with straight (not premultiplied) alpha.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub(crate) enum MixMethod {
     #[default]
     Normal,
@@ -8,135 +13,257 @@ pub(crate) enum MixMethod {
     Darken,
     Screen,
     Addition,
-    Substraction,
-    RatioAdd(f32),
+    Substraction, // backdrop - source
+    RatioAdd(f32), // source * (1 - ratio) + backdrop * ratio
+    Overlay,
+    SoftLight,
+    HardLight,
+    ColorDodge,
+    ColorBurn,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
 }
 
-impl MixMethod {
+fn to_unit(c: u8) -> f32 {
+    c as f32 / 255.
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c * 255.).round().clamp(0., 255.) as u8
+}
 
+fn multiply(cb: f32, cs: f32) -> f32 {
+    cb * cs
+}
 
-    pub fn perform_operation_4(&self, a: &[u8; 4], b: &[u8; 4]) -> [u8; 4] {
+fn screen(cb: f32, cs: f32) -> f32 {
+    cb + cs - cb * cs
+}
+
+fn hard_light(cb: f32, cs: f32) -> f32 {
+    if cs <= 0.5 {
+        multiply(cb, 2. * cs)
+    } else {
+        screen(cb, 2. * cs - 1.)
+    }
+}
+
+fn soft_light(cb: f32, cs: f32) -> f32 {
+    if cs <= 0.5 {
+        cb - (1. - 2. * cs) * cb * (1. - cb)
+    } else {
+        let d = if cb <= 0.25 {
+            ((16. * cb - 12.) * cb + 4.) * cb
+        } else {
+            cb.sqrt()
+        };
+        cb + (2. * cs - 1.) * (d - cb)
+    }
+}
+
+fn color_dodge(cb: f32, cs: f32) -> f32 {
+    if cb <= 0. {
+        0.
+    } else if cs >= 1. {
+        1.
+    } else {
+        (cb / (1. - cs)).min(1.)
+    }
+}
+
+fn color_burn(cb: f32, cs: f32) -> f32 {
+    if cb >= 1. {
+        1.
+    } else if cs <= 0. {
+        0.
+    } else {
+        1. - ((1. - cb) / cs).min(1.)
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn clip_color(c: [f32; 3]) -> [f32; 3] {
+    let l = lum(c);
+    let n = c[0].min(c[1]).min(c[2]);
+    let x = c[0].max(c[1]).max(c[2]);
+    let mut ret = c;
+    if n < 0. {
+        ret = ret.map(|v| l + (v - l) * l / (l - n));
+    }
+    if x > 1. {
+        ret = ret.map(|v| l + (v - l) * (1. - l) / (x - l));
+    }
+    ret
+}
+
+fn set_lum(c: [f32; 3], l: f32) -> [f32; 3] {
+    let d = l - lum(c);
+    clip_color(c.map(|v| v + d))
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let mut index = [0usize, 1, 2];
+    index.sort_by(|i, j| c[*i].total_cmp(&c[*j]));
+    let [min, mid, max] = index;
+
+    let mut ret = [0.; 3];
+    if c[max] > c[min] {
+        ret[mid] = (c[mid] - c[min]) * s / (c[max] - c[min]);
+        ret[max] = s;
+    }
+    ret
+}
+
+impl MixMethod {
+    // B(cb, cs) of the spec, all the channels are in 0..=1.
+    pub fn blend(&self, cs: [f32; 3], cb: [f32; 3]) -> [f32; 3] {
+        let separable = |f: &dyn Fn(f32, f32) -> f32| [f(cb[0], cs[0]), f(cb[1], cs[1]), f(cb[2], cs[2])];
         match self {
-            MixMethod::Normal => { return b.clone(); }
-            MixMethod::Average => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = a[i] / 2 + b[i] / 2;
-                }
-                return ret;
-            },
-            MixMethod::Multiply => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = ((a[i] as f32 / 255. * b[i] as f32 / 255.) * 255.) as u8;
-                }
-                return ret;
-            }
-            MixMethod::Lighten => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = a[i].max(b[i]);
-                }
-                return ret;
-            }
-            MixMethod::Darken => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = a[i].min(b[i]);
-                }
-                return ret;
-            }
-            MixMethod::Screen => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = ((1. - ((1. - (a[0] as f32 / 255.)) * (1. - (b[0] as f32 / 255.)))) * u8::MAX as f32) as u8;
-                }
-                return ret;
-            }
-            MixMethod::Addition => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = (a[0] as u16 + b[0] as u16).clamp(0, u8::MAX as u16) as u8;
-                }
-                return ret;
-            }
-            MixMethod::Substraction => {
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = (a[0] as i16 - b[0] as i16).clamp(0, u8::MAX as i16) as u8;
-                }
-                return ret;
-            }
-            MixMethod::RatioAdd(ratio) => { 
-                let mut ret = [0u8; 4];
-                for i in 0..a.len() {
-                    ret[i] = (a[i] as f32 * (1.-ratio) + b[i] as f32 * ratio).round() as u8;
-                }
-                return ret;
-            },
+            MixMethod::Normal => cs,
+            MixMethod::Average => separable(&|b, s| (b + s) / 2.),
+            MixMethod::Multiply => separable(&multiply),
+            MixMethod::Lighten => separable(&f32::max),
+            MixMethod::Darken => separable(&f32::min),
+            MixMethod::Screen => separable(&screen),
+            MixMethod::Addition => separable(&|b, s| (b + s).min(1.)),
+            MixMethod::Substraction => separable(&|b, s| (b - s).max(0.)),
+            MixMethod::RatioAdd(ratio) => separable(&|b, s| s * (1. - ratio) + b * ratio),
+            MixMethod::Overlay => separable(&|b, s| hard_light(s, b)),
+            MixMethod::SoftLight => separable(&soft_light),
+            MixMethod::HardLight => separable(&hard_light),
+            MixMethod::ColorDodge => separable(&color_dodge),
+            MixMethod::ColorBurn => separable(&color_burn),
+            MixMethod::Difference => separable(&|b, s| (b - s).abs()),
+            MixMethod::Exclusion => separable(&|b, s| b + s - 2. * b * s),
+            MixMethod::Hue => set_lum(set_sat(cs, sat(cb)), lum(cb)),
+            MixMethod::Saturation => set_lum(set_sat(cb, sat(cs)), lum(cb)),
+            MixMethod::Color => set_lum(cs, lum(cb)),
+            MixMethod::Luminosity => set_lum(cb, lum(cs)),
         }
     }
 
-    pub fn perform_operation_3(&self, a: &[u8; 3], b: &[u8; 3]) -> [u8; 3] {
-        match self {
-            MixMethod::Normal => { return b.clone(); }
-            MixMethod::Average => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = a[i] / 2 + b[i] / 2;
-                }
-                return ret;
-            },
-            MixMethod::Multiply => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = ((a[i] as f32 / 255. * b[i] as f32 / 255.) * 255.) as u8;
-                }
-                return ret;
-            }
-            MixMethod::Lighten => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = a[i].max(b[i]);
-                }
-                return ret;
-            }
-            MixMethod::Darken => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = a[i].min(b[i]);
-                }
-                return ret;
-            }
-            MixMethod::Screen => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = ((1. - ((1. - (a[0] as f32 / 255.)) * (1. - (b[0] as f32 / 255.)))) * u8::MAX as f32) as u8;
-                }
-                return ret;
-            }
-            MixMethod::Addition => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = (a[0] as u16 + b[0] as u16).clamp(0, u8::MAX as u16) as u8;
-                }
-                return ret;
-            }
-            MixMethod::Substraction => {
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = (a[0] as i16 - b[0] as i16).clamp(0, u8::MAX as i16) as u8;
-                }
-                return ret;
-            }
-            MixMethod::RatioAdd(ratio) => { 
-                let mut ret = [0u8; 3];
-                for i in 0..a.len() {
-                    ret[i] = (a[i] as f32 * (1.-ratio) + b[i] as f32 * ratio).round() as u8;
-                }
-                return ret;
-            },
+    // Composites `a` over `b` with the alpha of `a` scaled by `opacity`.
+    pub fn composite(&self, a: &[u8; 4], b: &[u8; 4], opacity: f32) -> [u8; 4] {
+        let sa = to_unit(a[3]) * opacity;
+        let ba = to_unit(b[3]);
+        let oa = sa + ba * (1. - sa);
+        if oa <= 0. { return [0; 4]; }
+
+        let cs = [to_unit(a[0]), to_unit(a[1]), to_unit(a[2])];
+        let cb = [to_unit(b[0]), to_unit(b[1]), to_unit(b[2])];
+        let mixed = self.blend(cs, cb);
+
+        let mut ret = [0u8; 4];
+        for i in 0..3 {
+            // the blend only applies where the backdrop is.
+            let cs = (1. - ba) * cs[i] + ba * mixed[i].clamp(0., 1.);
+            ret[i] = to_u8((sa * cs + (1. - sa) * ba * cb[i]) / oa);
         }
+        ret[3] = to_u8(oa);
+        ret
+    }
+
+    pub fn perform_operation_4(&self, a: &[u8; 4], b: &[u8; 4]) -> [u8; 4] {
+        self.composite(a, b, 1.)
+    }
+
+    // Both colors are opaque, so this is the bare blend.
+    pub fn perform_operation_3(&self, a: &[u8; 3], b: &[u8; 3]) -> [u8; 3] {
+        self.blend(a.map(to_unit), b.map(to_unit)).map(to_u8)
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 51, 102, 153 and 204 are 0.2, 0.4, 0.6 and 0.8.
+    fn rgb(method: MixMethod, source: u8, backdrop: u8) -> u8 {
+        method.perform_operation_3(&[source; 3], &[backdrop; 3])[0]
+    }
+
+    #[test]
+    fn test_source_over() {
+        let normal = MixMethod::Normal;
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        assert_eq!(normal.perform_operation_4(&red, &blue), red);
+        assert_eq!(normal.perform_operation_4(&[0; 4], &blue), blue);
+        assert_eq!(normal.perform_operation_4(&red, &[0; 4]), red);
+        assert_eq!(normal.perform_operation_4(&[255, 0, 0, 128], &blue), [128, 0, 127, 255]);
+        assert_eq!(normal.perform_operation_4(&[255, 0, 0, 128], &[0; 4]), [255, 0, 0, 128]);
+        assert_eq!(normal.composite(&red, &blue, 0.5), [128, 0, 128, 255]);
+        assert_eq!(normal.composite(&red, &blue, 0.), blue);
+        // 0.6 over 0.2, both half transparent.
+        assert_eq!(normal.perform_operation_4(&[153, 153, 153, 128], &[51, 51, 51, 128]), [119, 119, 119, 192]);
+
+        // the blend is ignored where there is no backdrop.
+        let multiply = MixMethod::Multiply;
+        assert_eq!(multiply.perform_operation_4(&[51, 102, 153, 255], &[0; 4]), [51, 102, 153, 255]);
+        assert_eq!(multiply.perform_operation_4(&[51, 102, 153, 255], &[153, 153, 153, 255]), [31, 61, 92, 255]);
+    }
+
+    #[test]
+    fn test_channels_are_independent() {
+        assert_eq!(MixMethod::Screen.perform_operation_3(&[51, 0, 255], &[153, 102, 0]), [173, 102, 255]);
+        assert_eq!(MixMethod::Addition.perform_operation_3(&[51, 0, 255], &[153, 102, 0]), [204, 102, 255]);
+        assert_eq!(MixMethod::Substraction.perform_operation_3(&[51, 0, 255], &[153, 102, 0]), [102, 102, 0]);
+    }
+
+    #[test]
+    fn test_separable() {
+        assert_eq!(rgb(MixMethod::Normal, 51, 153), 51);
+        assert_eq!(rgb(MixMethod::Average, 51, 153), 102);
+        assert_eq!(rgb(MixMethod::Multiply, 51, 153), 31);
+        assert_eq!(rgb(MixMethod::Lighten, 51, 153), 153);
+        assert_eq!(rgb(MixMethod::Darken, 51, 153), 51);
+        assert_eq!(rgb(MixMethod::Screen, 51, 153), 173);
+        assert_eq!(rgb(MixMethod::Addition, 51, 102), 153);
+        assert_eq!(rgb(MixMethod::Addition, 204, 153), 255);
+        assert_eq!(rgb(MixMethod::Substraction, 51, 153), 102);
+        assert_eq!(rgb(MixMethod::Substraction, 153, 51), 0);
+        assert_eq!(rgb(MixMethod::RatioAdd(0.25), 0, 204), 51);
+        assert_eq!(rgb(MixMethod::Overlay, 51, 153), 92);
+        assert_eq!(rgb(MixMethod::Overlay, 153, 51), 61);
+        assert_eq!(rgb(MixMethod::HardLight, 51, 153), 61);
+        assert_eq!(rgb(MixMethod::HardLight, 153, 51), 92);
+        assert_eq!(rgb(MixMethod::SoftLight, 51, 153), 116);
+        assert_eq!(rgb(MixMethod::SoftLight, 204, 153), 180);
+        assert_eq!(rgb(MixMethod::SoftLight, 204, 51), 89);
+        assert_eq!(rgb(MixMethod::ColorDodge, 51, 153), 191);
+        assert_eq!(rgb(MixMethod::ColorDodge, 255, 153), 255);
+        assert_eq!(rgb(MixMethod::ColorDodge, 255, 0), 0);
+        assert_eq!(rgb(MixMethod::ColorBurn, 204, 102), 64);
+        assert_eq!(rgb(MixMethod::ColorBurn, 0, 102), 0);
+        assert_eq!(rgb(MixMethod::ColorBurn, 0, 255), 255);
+        assert_eq!(rgb(MixMethod::Difference, 51, 153), 102);
+        assert_eq!(rgb(MixMethod::Difference, 153, 51), 102);
+        assert_eq!(rgb(MixMethod::Exclusion, 51, 153), 143);
+    }
+
+    #[test]
+    fn test_non_separable() {
+        let red = [255, 0, 0];
+        let green = [0, 255, 0];
+        let blue = [0, 0, 255];
+        let gray = [128, 128, 128];
+        let pink = [204, 102, 102];
+        assert_eq!(MixMethod::Luminosity.perform_operation_3(&green, &red), [255, 106, 106]);
+        assert_eq!(MixMethod::Color.perform_operation_3(&red, &gray), [255, 74, 74]);
+        assert_eq!(MixMethod::Hue.perform_operation_3(&blue, &pink), [121, 121, 223]);
+        assert_eq!(MixMethod::Saturation.perform_operation_3(&gray, &pink), [133, 133, 133]);
+        // a gray source keeps the hue and saturation of the backdrop.
+        assert_eq!(MixMethod::Luminosity.perform_operation_3(&[133; 3], &pink), pink);
+    }
+}
@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use bevy_pancam::PanCam;
+
+use crate::canvas::Canvas;
+use crate::config::AppConfig;
+use crate::eyedropper::picking_by_alt;
+use crate::history::History;
+use crate::layers::LayerStack;
+use crate::pen_input::PenState;
+use crate::selection::{Clipped, Selection};
+use crate::tools::{Brush, Bucket, PointTool};
+use crate::stroke::Stroke;
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, (toggle_pixel_perfect, paint_on_canvas));
+}
+
+// Converts a cursor position in the window into pixel coordinates of the canvas image,
+// (0, 0) is the top left corner of the image. The result may lie outside of the image.
+pub(crate) fn window_to_canvas(
+    cursor: Vec2,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    canvas_transform: &GlobalTransform,
+    canvas_size: UVec2,
+    ) -> Option<Vec2> {
+    let world = camera.viewport_to_world_2d(camera_transform, cursor).ok()?;
+    let local = canvas_transform.affine().inverse().transform_point3(world.extend(0.));
+    let half = canvas_size.as_vec2() / 2.;
+    Some(Vec2::new(local.x + half.x, half.y - local.y))
+}
+
+// The inverse of `window_to_canvas` without the camera, gives world coordinates.
+pub(crate) fn canvas_to_world(loc: Vec2, canvas_transform: &GlobalTransform, canvas_size: UVec2) -> Vec2 {
+    let half = canvas_size.as_vec2() / 2.;
+    canvas_transform.transform_point(Vec3::new(loc.x - half.x, half.y - loc.y, 0.)).truncate()
+}
+
+fn toggle_pixel_perfect(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyP) { return; }
+    let tools_config = &mut app_config.tools_config;
+    tools_config.pixel_perfect = !tools_config.pixel_perfect;
+    info!("Pixel perfect pencil {}.", if tools_config.pixel_perfect { "on" } else { "off" });
+}
+
+pub(crate) fn paint_on_canvas(
+    pen: Res<PenState>,
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Single<(&Camera, &GlobalTransform), With<PanCam>>,
+    canvas: Single<&GlobalTransform, With<Canvas>>,
+    interactions: Query<&Interaction>,
+    mut layers: ResMut<LayerStack>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut history: ResMut<History>,
+    selection: Res<Selection>,
+    mut stroke: Local<Option<Stroke>>,
+) {
+    let tools_config = &app_config.tools_config;
+    let tool = tools_config.current_tool.as_deref();
+    if !pen.pressed() || !matches!(tool, Some("pencil") | Some("bucket")) {
+        // one press to release is one undo step, other tools may be recording too.
+        if let Some(mut stroke) = stroke.take() {
+            let brush = Brush::from_config(tools_config);
+            stroke.finish(&Clipped::new(&brush, &selection), &mut layers.active_mut().image);
+            if history.is_recording() {
+                history.end(&layers);
+            }
+        }
+        return;
+    }
+    // strokes only begin outside of the ui.
+    let just_pressed = pen.just_pressed();
+    // alt clicks pick a colour instead.
+    if just_pressed && interactions.iter().all(|x| *x == Interaction::None) && !picking_by_alt(&keys, tool) {
+        if !layers.active().is_editable() {
+            info!("The active layer is hidden or locked.");
+            return;
+        }
+        *stroke = Some(Stroke::new(tools_config.brush_spacing).with_pixel_perfect(tools_config.pixel_perfect));
+        // the press may come without a sample, the stroke is recorded from here anyway.
+        history.begin(layers.active());
+    }
+    let Some(stroke) = stroke.as_mut() else { return; };
+    // the bucket fills once per press.
+    if tool == Some("bucket") && !just_pressed { return; }
+
+    let Some(sample) = pen.sample else { return; };
+    let (camera, camera_transform) = camera.into_inner();
+    let Some(loc) = window_to_canvas(sample.position, camera, camera_transform, *canvas, layers.size())
+        else { return; };
+
+    let layer_image = &mut layers.active_mut().image;
+    if tool == Some("bucket") {
+        Clipped::new(&Bucket::from_config(tools_config), &selection).apply(layer_image, loc);
+    } else {
+        let brush_size = *tools_config.brush_size.read().expect("read brush size failed.");
+        let brush = Brush::from_config(tools_config);
+        stroke.stroke_to(&Clipped::new(&brush, &selection), layer_image, loc, sample.pressure, brush_size);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::sync::{Arc, RwLock};
+
+    use crate::mix_methods::MixMethod;
+    use crate::patterns::PatternGeneratingFunc;
+    use crate::pressure_mask::{MaskGeneratingFunc, PressureDynamics};
+
+    #[test]
+    fn test_normal_stamp_paints_the_source() {
+        let mask = MaskGeneratingFunc::new(None, |_, _, _| 1.);
+        let pattern = PatternGeneratingFunc::new(None, |_, _, _| Srgba::rgb_u8(200, 10, 20));
+        let dynamics = PressureDynamics::default();
+        let size = Arc::new(RwLock::new(UVec2::splat(3)));
+        let mut image = RgbaImage::from_pixel(5, 5, Rgba([0, 0, 255, 255]));
+
+        // a 3x3 stamp centered on the pixel (2, 2).
+        Brush::new(&mask, &pattern, size, &MixMethod::Normal, &dynamics).apply(&mut image, Vec2::new(2.5, 2.5));
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let inside = (1..4).contains(&x) && (1..4).contains(&y);
+            assert_eq!(pixel.0, if inside { [200, 10, 20, 255] } else { [0, 0, 255, 255] }, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    fn test_canvas_to_world() {
+        let canvas = GlobalTransform::from_xyz(10., 20., 0.);
+        let size = UVec2::new(4, 2);
+        // (0, 0) is the top left corner of the image.
+        assert_eq!(canvas_to_world(Vec2::ZERO, &canvas, size), Vec2::new(8., 21.));
+        assert_eq!(canvas_to_world(Vec2::new(4., 2.), &canvas, size), Vec2::new(12., 19.));
+    }
+}
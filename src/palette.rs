@@ -0,0 +1,296 @@
+// An ordered list of colours, and the indexed mode where every pixel of the
+// layers keeps a palette index, so that editing an entry recolours the image.
+use bevy::prelude::*;
+use image::RgbaImage;
+use std::collections::HashMap;
+
+use crate::config::AppConfig;
+use crate::layers::LayerStack;
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<IndexedCanvas>()
+        .add_systems(PostUpdate, sync_indexed_canvas.before(crate::layers::composite_to_canvas));
+}
+
+pub(crate) const MAX_COLORS: usize = 256;
+
+// Transparent pixels have no index and are stored as [0, 0, 0, 0].
+const TRANSPARENT: [u8; 4] = [0; 4];
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Palette {
+    colors: Vec<[u8; 4]>,
+}
+
+impl Palette {
+    // The colours after `MAX_COLORS` are dropped.
+    pub(crate) fn new(mut colors: Vec<[u8; 4]>) -> Self {
+        colors.truncate(MAX_COLORS);
+        Self {
+            colors,
+        }
+    }
+
+    // The 16 colours of PICO-8.
+    pub(crate) fn pico8() -> Self {
+        Self::new([
+            0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+            0xff004d, 0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa,
+        ].into_iter().map(|x: u32| {
+            let [_, r, g, b] = x.to_be_bytes();
+            [r, g, b, 255]
+        }).collect())
+    }
+
+    pub(crate) fn colors(&self) -> &[[u8; 4]] {
+        &self.colors
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<[u8; 4]> {
+        self.colors.get(index).copied()
+    }
+
+    // Returns false when the palette is full.
+    pub(crate) fn push(&mut self, color: [u8; 4]) -> bool {
+        if self.colors.len() >= MAX_COLORS { return false; }
+        self.colors.push(color);
+        true
+    }
+
+    pub(crate) fn remove(&mut self, index: usize) -> Option<[u8; 4]> {
+        (index < self.colors.len()).then(|| self.colors.remove(index))
+    }
+
+    pub(crate) fn set(&mut self, index: usize, color: [u8; 4]) {
+        if let Some(o) = self.colors.get_mut(index) {
+            *o = color;
+        }
+    }
+
+    // The closest entry by the distance in rgba, none for transparent colours or an empty palette.
+    pub(crate) fn nearest(&self, color: [u8; 4]) -> Option<u8> {
+        if color[3] == 0 || self.is_empty() { return None; }
+        self.colors.iter().enumerate().min_by_key(|(_, x)| {
+            x.iter().zip(color).map(|(a, b)| (*a as i32 - b as i32).pow(2)).sum::<i32>()
+        }).map(|(i, _)| i as u8)
+    }
+
+    // An empty palette leaves the colours as they are.
+    pub(crate) fn snap(&self, color: [u8; 4]) -> [u8; 4] {
+        if color[3] == 0 { return TRANSPARENT; }
+        self.nearest(color).map_or(color, |i| self.colors[i as usize])
+    }
+
+    fn color_of(&self, index: Option<u8>) -> [u8; 4] {
+        index.and_then(|x| self.get(x as usize)).unwrap_or(TRANSPARENT)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IndexedImage {
+    size: UVec2,
+    indices: Vec<Option<u8>>,
+}
+
+impl IndexedImage {
+    pub(crate) fn quantize(image: &RgbaImage, palette: &Palette) -> Self {
+        Self {
+            size: UVec2::from(image.dimensions()),
+            indices: image.pixels().map(|x| palette.nearest(x.0)).collect(),
+        }
+    }
+
+    pub(crate) fn render(&self, palette: &Palette) -> RgbaImage {
+        let mut ret = RgbaImage::new(self.size.x, self.size.y);
+        for (pixel, index) in ret.pixels_mut().zip(&self.indices) {
+            pixel.0 = palette.color_of(*index);
+        }
+        ret
+    }
+
+    // Takes the pixels painted since the last update, they are snapped to the palette.
+    // Returns true when `image` is changed.
+    pub(crate) fn update(&mut self, image: &mut RgbaImage, palette: &Palette) -> bool {
+        let mut ret = false;
+        for (pixel, index) in image.pixels_mut().zip(self.indices.iter_mut()) {
+            if pixel.0 == palette.color_of(*index) { continue; }
+            *index = palette.nearest(pixel.0);
+            let snapped = palette.color_of(*index);
+            if pixel.0 != snapped {
+                pixel.0 = snapped;
+                ret = true;
+            }
+        }
+        ret
+    }
+}
+
+// The indices of every layer while the indexed mode is on.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct IndexedCanvas {
+    pub enabled: bool,
+    images: HashMap<u64, IndexedImage>,
+    palette: Palette, // the one the indices refer to.
+}
+
+impl IndexedCanvas {
+    // An edit of the entries recolours the layers by their indices, when entries are
+    // added or removed the layers are quantized again. Returns true when a layer is changed.
+    pub(crate) fn sync(&mut self, layers: &mut LayerStack, palette: &Palette) -> bool {
+        // an empty palette would clear the layers.
+        if !self.enabled || palette.is_empty() {
+            self.images.clear();
+            return false;
+        }
+        if self.palette.len() != palette.len() {
+            self.images.clear();
+        }
+        let recolour = self.palette != *palette;
+        self.palette = palette.clone();
+
+        let mut ret = false;
+        for i in 0..layers.len() {
+            let layer = layers.layer_mut(i).expect("layer index out of range.");
+            let size = UVec2::from(layer.image.dimensions());
+            let indexed = self.images.entry(layer.id)
+                .and_modify(|x| if x.size != size { *x = IndexedImage::quantize(&layer.image, palette); })
+                .or_insert_with(|| IndexedImage::quantize(&layer.image, palette));
+            if recolour {
+                let image = indexed.render(palette);
+                if image != layer.image {
+                    layer.image = image;
+                    ret = true;
+                }
+            } else {
+                ret |= indexed.update(&mut layer.image, palette);
+            }
+        }
+        let ids: Vec<u64> = layers.layers().iter().map(|x| x.id).collect();
+        self.images.retain(|id, _| ids.contains(id));
+        ret
+    }
+}
+
+// Recolours layers snapped to `from` entry by entry with `to`, e.g. for an undoable
+// palette edit. When entries are added or removed they are snapped to `to` instead.
+pub(crate) fn recolour(layers: &mut LayerStack, from: &Palette, to: &Palette) {
+    if to.is_empty() { return; }
+    let by = if from.len() == to.len() { from } else { to };
+    for i in 0..layers.len() {
+        let layer = layers.layer_mut(i).expect("layer index out of range.");
+        layer.image = IndexedImage::quantize(&layer.image, by).render(to);
+    }
+}
+
+fn sync_indexed_canvas(
+    mut indexed: ResMut<IndexedCanvas>,
+    mut layers: ResMut<LayerStack>,
+    app_config: Res<AppConfig<'static, 'static>>,
+) {
+    let palette = &app_config.tools_config.palette;
+    let palette_changed = indexed.enabled && indexed.palette != *palette;
+    if !layers.is_changed() && !indexed.is_changed() && !palette_changed { return; }
+    // only real changes reach the canvas.
+    if indexed.bypass_change_detection().sync(layers.bypass_change_detection(), palette) {
+        layers.set_changed();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn test_nearest_and_snap() {
+        let palette = Palette::new(vec![RED, BLUE]);
+        assert_eq!(palette.nearest([200, 30, 40, 255]), Some(0));
+        assert_eq!(palette.nearest([10, 0, 180, 200]), Some(1));
+        assert_eq!(palette.nearest([10, 0, 180, 0]), None);
+        assert_eq!(palette.snap([10, 0, 180, 200]), BLUE);
+        assert_eq!(palette.snap([10, 0, 180, 0]), [0; 4]);
+        assert_eq!(Palette::default().snap([1, 2, 3, 4]), [1, 2, 3, 4]);
+        assert_eq!(Palette::pico8().get(8), Some([255, 0, 77, 255]));
+    }
+
+    #[test]
+    fn test_indexed_image() {
+        let palette = Palette::new(vec![RED, BLUE]);
+        let mut image = RgbaImage::from_fn(3, 1, |x, _| match x {
+            0 => Rgba([250, 10, 0, 255]),
+            1 => Rgba(BLUE),
+            _ => Rgba([0; 4]),
+        });
+        let mut indexed = IndexedImage::quantize(&image, &palette);
+        assert_eq!(indexed.indices[0], Some(0));
+        assert_eq!(indexed.indices[2], None);
+
+        // a painted pixel is snapped and indexed.
+        image.put_pixel(2, 0, Rgba([20, 20, 240, 255]));
+        assert!(indexed.update(&mut image, &palette));
+        assert_eq!(image.get_pixel(2, 0).0, BLUE);
+        assert_eq!(image.get_pixel(0, 0).0, RED);
+        assert_eq!(indexed.indices[2], Some(1));
+        assert!(!indexed.update(&mut image, &palette));
+        assert_eq!(indexed.render(&palette), image);
+    }
+
+    #[test]
+    fn test_palette_edits_recolour_the_layers() {
+        let mut layers = LayerStack::new(UVec2::new(2, 1), RED);
+        layers.active_mut().image.put_pixel(1, 0, Rgba(BLUE));
+        let mut palette = Palette::new(vec![RED, BLUE]);
+        let mut indexed = IndexedCanvas {
+            enabled: true,
+            ..default()
+        };
+        assert!(!indexed.sync(&mut layers, &palette));
+
+        let green = [0, 255, 0, 255];
+        palette.set(0, green);
+        assert!(indexed.sync(&mut layers, &palette));
+        assert_eq!(layers.active().image.get_pixel(0, 0).0, green);
+        assert_eq!(layers.active().image.get_pixel(1, 0).0, BLUE);
+
+        // the pixels of a removed entry go to the nearest one left.
+        palette.remove(0);
+        assert!(indexed.sync(&mut layers, &palette));
+        assert_eq!(layers.active().image.get_pixel(0, 0).0, BLUE);
+        assert_eq!(indexed.images[&layers.active().id].indices[0], Some(0));
+
+        indexed.enabled = false;
+        assert!(!indexed.sync(&mut layers, &palette));
+        assert!(indexed.images.is_empty());
+    }
+
+    #[test]
+    fn test_recolour() {
+        let mut layers = LayerStack::new(UVec2::new(2, 1), BLUE);
+        layers.active_mut().image.put_pixel(1, 0, Rgba(RED));
+        let palette = Palette::new(vec![RED, BLUE]);
+
+        // an edited entry keeps its pixels.
+        let green = [0, 255, 0, 255];
+        let mut edited = palette.clone();
+        edited.set(0, green);
+        recolour(&mut layers, &palette, &edited);
+        assert_eq!(layers.active().image.get_pixel(0, 0).0, BLUE);
+        assert_eq!(layers.active().image.get_pixel(1, 0).0, green);
+
+        let mut removed = edited.clone();
+        removed.remove(1);
+        recolour(&mut layers, &edited, &removed);
+        assert!(layers.active().image.pixels().all(|x| x.0 == green));
+    }
+}
@@ -0,0 +1,474 @@
+// Palette files: GIMP `.gpl`, JASC `.pal`, plain `.hex` lists and Adobe `.ase`.
+use std::fmt;
+use std::path::Path;
+
+use crate::palette::{Palette, MAX_COLORS};
+
+#[derive(Debug)]
+pub(crate) enum PaletteError {
+    Io(std::io::Error),
+    UnknownFormat(String),
+    // lines count from 1.
+    Parse { line: usize, message: String },
+    // ase is binary, its errors are at a byte offset.
+    Binary { offset: usize, message: String },
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::Io(e) => write!(f, "io error: {}", e),
+            PaletteError::UnknownFormat(e) => write!(f, "unknown palette format: {}", e),
+            PaletteError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            PaletteError::Binary { offset, message } => write!(f, "byte {}: {}", offset, message),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(e: std::io::Error) -> Self { PaletteError::Io(e) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PaletteFormat {
+    Gpl,
+    Pal,
+    Hex,
+    Ase,
+}
+
+impl PaletteFormat {
+    pub(crate) const ALL: [PaletteFormat; 4] = [PaletteFormat::Gpl, PaletteFormat::Pal, PaletteFormat::Hex, PaletteFormat::Ase];
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Gpl => "gpl",
+            PaletteFormat::Pal => "pal",
+            PaletteFormat::Hex => "hex",
+            PaletteFormat::Ase => "ase",
+        }
+    }
+
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        Self::ALL.into_iter().find(|x| x.extension() == extension)
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> PaletteError {
+    PaletteError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+fn push_color(colors: &mut Vec<[u8; 4]>, color: [u8; 4], line: usize) -> Result<(), PaletteError> {
+    if colors.len() >= MAX_COLORS {
+        return Err(parse_error(line, format!("more than {} colours", MAX_COLORS)));
+    }
+    colors.push(color);
+    Ok(())
+}
+
+// `count` channels of 0 to 255 from the start of `fields`, a missing alpha is opaque.
+fn parse_channels<'a>(fields: &mut impl Iterator<Item = &'a str>, count: usize, line: usize) -> Result<[u8; 4], PaletteError> {
+    let mut ret = [255; 4];
+    for o in ret.iter_mut().take(count) {
+        let field = fields.next().ok_or_else(|| parse_error(line, format!("expected {} channels", count)))?;
+        *o = field.parse().map_err(|_| parse_error(line, format!("{} is not a channel of 0 to 255", field)))?;
+    }
+    Ok(ret)
+}
+
+fn color_name(color: &[u8; 4]) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+fn is_opaque(palette: &Palette) -> bool {
+    palette.colors().iter().all(|x| x[3] == 255)
+}
+
+fn read_gpl(text: &str) -> Result<Palette, PaletteError> {
+    let mut lines = text.lines().enumerate().map(|(i, x)| (i + 1, x.trim()));
+    match lines.next() {
+        Some((_, "GIMP Palette")) => {},
+        _ => return Err(parse_error(1, "expected \"GIMP Palette\"")),
+    }
+    let mut channels = 3;
+    let mut colors = Vec::new();
+    for (line, text) in lines {
+        if text.is_empty() || text.starts_with('#')
+            || text.starts_with("Name:") || text.starts_with("Columns:") {
+            continue;
+        }
+        if let Some(value) = text.strip_prefix("Channels:") {
+            channels = match value.trim() {
+                "RGB" => 3,
+                "RGBA" => 4,
+                o => return Err(parse_error(line, format!("unknown channels {}", o))),
+            };
+            continue;
+        }
+        // the rest of the line is the colour name.
+        let color = parse_channels(&mut text.split_whitespace(), channels, line)?;
+        push_color(&mut colors, color, line)?;
+    }
+    Ok(Palette::new(colors))
+}
+
+fn write_gpl(palette: &Palette, name: &str) -> String {
+    let mut ret = format!("GIMP Palette\nName: {}\n", name);
+    let opaque = is_opaque(palette);
+    if !opaque {
+        ret += "Channels: RGBA\n";
+    }
+    ret += "#\n";
+    for x in palette.colors() {
+        ret += &format!("{:>3} {:>3} {:>3}", x[0], x[1], x[2]);
+        if !opaque {
+            ret += &format!(" {:>3}", x[3]);
+        }
+        ret += &format!("\t{}\n", color_name(x));
+    }
+    ret
+}
+
+fn read_pal(text: &str) -> Result<Palette, PaletteError> {
+    let mut lines = text.lines().enumerate().map(|(i, x)| (i + 1, x.trim()));
+    // the header is the first three lines, a missing one is reported at its line.
+    let mut next_line = 0;
+    let mut header = |expected: &str| {
+        next_line += 1;
+        match lines.next() {
+            Some((_, o)) if o == expected || expected.is_empty() => Ok(o),
+            Some((line, _)) => Err(parse_error(line, format!("expected \"{}\"", expected))),
+            None => Err(parse_error(next_line, "the header is cut short")),
+        }
+    };
+    header("JASC-PAL")?;
+    header("0100")?;
+    let count = header("")?;
+    let count: usize = count.parse().map_err(|_| parse_error(3, format!("{} is not a colour count", count)))?;
+
+    let mut colors = Vec::new();
+    for (line, text) in lines.filter(|x| !x.1.is_empty()) {
+        let mut fields = text.split_whitespace();
+        let channels = if text.split_whitespace().count() == 4 { 4 } else { 3 };
+        let color = parse_channels(&mut fields, channels, line)?;
+        if fields.next().is_some() {
+            return Err(parse_error(line, "too many channels"));
+        }
+        push_color(&mut colors, color, line)?;
+    }
+    if colors.len() != count {
+        return Err(parse_error(3, format!("{} colours are declared, {} are given", count, colors.len())));
+    }
+    Ok(Palette::new(colors))
+}
+
+fn write_pal(palette: &Palette) -> String {
+    let mut ret = format!("JASC-PAL\r\n0100\r\n{}\r\n", palette.len());
+    for x in palette.colors() {
+        match x[3] {
+            255 => ret += &format!("{} {} {}\r\n", x[0], x[1], x[2]),
+            a => ret += &format!("{} {} {} {}\r\n", x[0], x[1], x[2], a),
+        }
+    }
+    ret
+}
+
+fn read_hex(text: &str) -> Result<Palette, PaletteError> {
+    let mut colors = Vec::new();
+    for (i, text) in text.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() { continue; }
+        let digits = text.strip_prefix('#').unwrap_or(text);
+        let value = u32::from_str_radix(digits, 16).ok()
+            .filter(|_| digits.chars().all(|x| x.is_ascii_hexdigit()));
+        let color = match (digits.len(), value) {
+            (6, Some(v)) => {
+                let [_, r, g, b] = v.to_be_bytes();
+                [r, g, b, 255]
+            },
+            (8, Some(v)) => v.to_be_bytes(),
+            _ => return Err(parse_error(i + 1, format!("{} is not a rrggbb colour", text))),
+        };
+        push_color(&mut colors, color, i + 1)?;
+    }
+    Ok(Palette::new(colors))
+}
+
+fn write_hex(palette: &Palette) -> String {
+    palette.colors().iter().map(|x| match x[3] {
+        255 => format!("{:02x}{:02x}{:02x}\n", x[0], x[1], x[2]),
+        _ => format!("{:02x}{:02x}{:02x}{:02x}\n", x[0], x[1], x[2], x[3]),
+    }).collect()
+}
+
+const ASE_SIGNATURE: &[u8; 4] = b"ASEF";
+const ASE_GROUP_START: u16 = 0xc001;
+const ASE_GROUP_END: u16 = 0xc002;
+const ASE_COLOR: u16 = 0x0001;
+const ASE_NORMAL: u16 = 2;
+
+// Big endian reading that knows where it is.
+struct AseReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl <'a> AseReader<'a> {
+    fn error(&self, message: impl Into<String>) -> PaletteError {
+        PaletteError::Binary {
+            offset: self.offset,
+            message: message.into(),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PaletteError> {
+        let ret = self.bytes.get(self.offset..self.offset + len)
+            .ok_or_else(|| self.error(format!("expected {} more bytes", len)))?;
+        self.offset += len;
+        Ok(ret)
+    }
+
+    fn u16(&mut self) -> Result<u16, PaletteError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().expect("two bytes.")))
+    }
+
+    fn u32(&mut self) -> Result<u32, PaletteError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().expect("four bytes.")))
+    }
+
+    fn f32(&mut self) -> Result<f32, PaletteError> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into().expect("four bytes.")))
+    }
+}
+
+fn to_channel(x: f32) -> u8 {
+    (x.clamp(0., 1.) * 255.).round() as u8
+}
+
+// Only the colours are kept, the groups and names are dropped.
+fn read_ase(bytes: &[u8]) -> Result<Palette, PaletteError> {
+    let mut reader = AseReader {
+        bytes,
+        offset: 0,
+    };
+    if reader.take(4)? != ASE_SIGNATURE {
+        return Err(PaletteError::Binary { offset: 0, message: "expected \"ASEF\"".to_owned() });
+    }
+    let version = reader.u16()?;
+    if version != 1 {
+        return Err(PaletteError::Binary { offset: 4, message: format!("unknown version {}", version) });
+    }
+    reader.u16()?;
+    let blocks = reader.u32()?;
+
+    let mut colors = Vec::new();
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let len = reader.u32()? as usize;
+        let start = reader.offset;
+        match kind {
+            ASE_COLOR => {
+                let name_len = reader.u16()? as usize;
+                reader.take(name_len * 2)?;
+                let model_offset = reader.offset;
+                let color = match reader.take(4)? {
+                    b"RGB " => [to_channel(reader.f32()?), to_channel(reader.f32()?), to_channel(reader.f32()?), 255],
+                    b"Gray" => {
+                        let v = to_channel(reader.f32()?);
+                        [v, v, v, 255]
+                    },
+                    b"CMYK" => {
+                        let [c, m, y, k] = [reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?];
+                        [to_channel((1. - c) * (1. - k)), to_channel((1. - m) * (1. - k)), to_channel((1. - y) * (1. - k)), 255]
+                    },
+                    o => return Err(PaletteError::Binary {
+                        offset: model_offset,
+                        message: format!("unsupported colour model {}", String::from_utf8_lossy(o).trim()),
+                    }),
+                };
+                if colors.len() >= MAX_COLORS {
+                    return Err(reader.error(format!("more than {} colours", MAX_COLORS)));
+                }
+                colors.push(color);
+            },
+            ASE_GROUP_START | ASE_GROUP_END => {},
+            o => return Err(PaletteError::Binary { offset: start - 6, message: format!("unknown block type {:#06x}", o) }),
+        }
+        // the rest of the block, e.g. the colour type, is skipped.
+        let end = start + len;
+        if reader.offset > end {
+            return Err(reader.error("the block is longer than its length"));
+        }
+        reader.take(end - reader.offset)?;
+    }
+    Ok(Palette::new(colors))
+}
+
+// The alpha can't be kept in ase.
+fn write_ase(palette: &Palette) -> Vec<u8> {
+    let mut ret = ASE_SIGNATURE.to_vec();
+    ret.extend(1u16.to_be_bytes());
+    ret.extend(0u16.to_be_bytes());
+    ret.extend((palette.len() as u32).to_be_bytes());
+    for x in palette.colors() {
+        let name: Vec<u16> = color_name(x).encode_utf16().chain([0]).collect();
+        let mut block = (name.len() as u16).to_be_bytes().to_vec();
+        block.extend(name.iter().flat_map(|x| x.to_be_bytes()));
+        block.extend(b"RGB ");
+        for c in &x[..3] {
+            block.extend((*c as f32 / 255.).to_be_bytes());
+        }
+        block.extend(ASE_NORMAL.to_be_bytes());
+
+        ret.extend(ASE_COLOR.to_be_bytes());
+        ret.extend((block.len() as u32).to_be_bytes());
+        ret.extend(block);
+    }
+    ret
+}
+
+pub(crate) fn read_palette(bytes: &[u8], format: PaletteFormat) -> Result<Palette, PaletteError> {
+    match format {
+        PaletteFormat::Gpl => read_gpl(&String::from_utf8_lossy(bytes)),
+        PaletteFormat::Pal => read_pal(&String::from_utf8_lossy(bytes)),
+        PaletteFormat::Hex => read_hex(&String::from_utf8_lossy(bytes)),
+        PaletteFormat::Ase => read_ase(bytes),
+    }
+}
+
+// `name` is written to the formats that have one.
+pub(crate) fn write_palette(palette: &Palette, format: PaletteFormat, name: &str) -> Vec<u8> {
+    match format {
+        PaletteFormat::Gpl => write_gpl(palette, name).into_bytes(),
+        PaletteFormat::Pal => write_pal(palette).into_bytes(),
+        PaletteFormat::Hex => write_hex(palette).into_bytes(),
+        PaletteFormat::Ase => write_ase(palette),
+    }
+}
+
+fn format_of(path: &Path) -> Result<PaletteFormat, PaletteError> {
+    PaletteFormat::from_path(path).ok_or_else(|| PaletteError::UnknownFormat(path.display().to_string()))
+}
+
+// The format is given by the extension.
+pub(crate) fn load_palette(path: &Path) -> Result<Palette, PaletteError> {
+    let format = format_of(path)?;
+    read_palette(&std::fs::read(path)?, format)
+}
+
+pub(crate) fn save_palette(palette: &Palette, path: &Path) -> Result<(), PaletteError> {
+    let format = format_of(path)?;
+    let name = path.file_stem().map_or("pixelin".into(), |x| x.to_string_lossy());
+    std::fs::write(path, write_palette(palette, format, &name))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture(format: PaletteFormat) -> &'static [u8] {
+        match format {
+            PaletteFormat::Gpl => include_bytes!("../assets/palettes/pico8.gpl"),
+            PaletteFormat::Pal => include_bytes!("../assets/palettes/pico8.pal"),
+            PaletteFormat::Hex => include_bytes!("../assets/palettes/pico8.hex"),
+            PaletteFormat::Ase => include_bytes!("../assets/palettes/pico8.ase"),
+        }
+    }
+
+    fn line_of(result: Result<Palette, PaletteError>) -> usize {
+        match result {
+            Err(PaletteError::Parse { line, .. }) => line,
+            o => panic!("expected a parse error, got {:?}", o),
+        }
+    }
+
+    #[test]
+    fn test_fixtures_round_trip() {
+        for format in PaletteFormat::ALL {
+            let palette = read_palette(fixture(format), format).unwrap();
+            assert_eq!(palette, Palette::pico8(), "{:?}", format);
+            assert_eq!(write_palette(&palette, format, "pico8"), fixture(format), "{:?}", format);
+        }
+    }
+
+    // names, groups and a column count are skipped, only the colours are read.
+    #[test]
+    fn test_named_and_grouped_fixtures() {
+        let sweetie16 = Palette::new([
+            0x1a1c2c, 0x5d275d, 0xb13e53, 0xef7d57, 0xffcd75, 0xa7f070, 0x38b764, 0x257179,
+            0x29366f, 0x3b5dc9, 0x41a6f6, 0x73eff7, 0xf4f4f4, 0x94b0c2, 0x566c86, 0x333c57u32,
+        ].map(|x| {
+            let [_, r, g, b] = x.to_be_bytes();
+            [r, g, b, 255]
+        }).to_vec());
+        let gpl = read_palette(include_bytes!("../assets/palettes/sweetie16.gpl"), PaletteFormat::Gpl).unwrap();
+        assert_eq!(gpl, sweetie16);
+        let ase = read_palette(include_bytes!("../assets/palettes/sweetie16.ase"), PaletteFormat::Ase).unwrap();
+        assert_eq!(ase, sweetie16);
+    }
+
+    #[test]
+    fn test_alpha_round_trip() {
+        let palette = Palette::new(vec![[1, 2, 3, 255], [200, 100, 50, 128]]);
+        for format in [PaletteFormat::Gpl, PaletteFormat::Pal, PaletteFormat::Hex] {
+            let bytes = write_palette(&palette, format, "alpha");
+            assert_eq!(read_palette(&bytes, format).unwrap(), palette, "{:?}", format);
+        }
+        let ase = read_palette(&write_palette(&palette, PaletteFormat::Ase, "alpha"), PaletteFormat::Ase).unwrap();
+        assert_eq!(ase.get(1), Some([200, 100, 50, 255]));
+    }
+
+    #[test]
+    fn test_text_errors_have_line_numbers() {
+        let gpl = "GIMP Palette\nName: x\n#\n  0   0   0\tblack\n255 300   0\tbad\n";
+        assert_eq!(line_of(read_palette(gpl.as_bytes(), PaletteFormat::Gpl)), 5);
+        assert_eq!(line_of(read_palette(b"Paint Palette\n", PaletteFormat::Gpl)), 1);
+
+        let pal = "JASC-PAL\n0100\n2\n0 0 0\n";
+        assert_eq!(line_of(read_palette(pal.as_bytes(), PaletteFormat::Pal)), 3);
+        let pal = "JASC-PAL\n0100\n1\n0 0\n";
+        assert_eq!(line_of(read_palette(pal.as_bytes(), PaletteFormat::Pal)), 4);
+
+        assert_eq!(line_of(read_palette(b"", PaletteFormat::Pal)), 1);
+        assert_eq!(line_of(read_palette(b"JASC-PAL\n0100\n", PaletteFormat::Pal)), 3);
+
+        let hex = "ff0000\n\n#00ff00\nnot hex\n";
+        assert_eq!(line_of(read_palette(hex.as_bytes(), PaletteFormat::Hex)), 4);
+    }
+
+    #[test]
+    fn test_ase_errors_and_models() {
+        assert!(matches!(read_palette(b"ASEX", PaletteFormat::Ase), Err(PaletteError::Binary { offset: 0, .. })));
+        let bytes = write_palette(&Palette::pico8(), PaletteFormat::Ase, "pico8");
+        assert!(matches!(read_palette(&bytes[..30], PaletteFormat::Ase), Err(PaletteError::Binary { .. })));
+
+        // one gray and one cmyk entry.
+        let mut bytes = b"ASEF\0\x01\0\0\0\0\0\x02".to_vec();
+        for (model, values) in [(b"Gray", vec![0.5f32]), (b"CMYK", vec![0., 1., 1., 0.])] {
+            let mut block = vec![0, 1, 0, 0];
+            block.extend(model);
+            block.extend(values.iter().flat_map(|x| x.to_be_bytes()));
+            block.extend(ASE_NORMAL.to_be_bytes());
+            bytes.extend(ASE_COLOR.to_be_bytes());
+            bytes.extend((block.len() as u32).to_be_bytes());
+            bytes.extend(block);
+        }
+        let palette = read_palette(&bytes, PaletteFormat::Ase).unwrap();
+        assert_eq!(palette.colors(), &[[128, 128, 128, 255], [255, 0, 0, 255]]);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(PaletteFormat::from_path(Path::new("a/b.GPL")), Some(PaletteFormat::Gpl));
+        assert_eq!(PaletteFormat::from_path(Path::new("b.ase")), Some(PaletteFormat::Ase));
+        assert_eq!(PaletteFormat::from_path(Path::new("b.png")), None);
+        assert!(matches!(load_palette(Path::new("b.txt")), Err(PaletteError::UnknownFormat(_))));
+    }
+}
@@ -0,0 +1,236 @@
+use bevy::{
+    prelude::*,
+    color::palettes::css,
+};
+use my_fluent_rs_helper::build_language_0;
+
+use crate::animation::Animation;
+use crate::config::AppConfig;
+use crate::history::History;
+use crate::layers::LayerStack;
+use crate::palette::{self, IndexedCanvas, Palette};
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<SelectedEntry>()
+        .add_systems(Update, (palette_button_clicked, rebuild_palette_panel).chain());
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct PalettePanel;
+
+// The palette entry picked last, it is the one replaced or removed.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct SelectedEntry(pub Option<usize>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PaletteAction {
+    Pick(usize),
+    Add,
+    Remove,
+    Replace,
+    ToggleIndexed,
+    ToggleLock,
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct PaletteButton(pub PaletteAction);
+
+pub(crate) fn build_palette_panel<'a, 'b>(parent: &'a mut ChildBuilder<'b>) {
+    parent.spawn((
+            PalettePanel,
+            Node {
+                position_type: PositionType::Absolute,
+                right: Val::Px(8.),
+                bottom: Val::Percent(8.),
+                width: Val::Px(150.),
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.),
+                padding: UiRect::all(Val::Px(4.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.6)),
+    ));
+}
+
+// Edits the palette by `f`. In the indexed mode every frame is recoloured as one undo
+// step, which is refused in the middle of another edit.
+fn edit_palette(palette: &mut Palette, indexed: bool, animation: &mut Animation, layers: &mut LayerStack,
+    history: &mut History, f: impl FnOnce(&mut Palette)) -> bool {
+    if !indexed {
+        f(palette);
+        return true;
+    }
+    if history.is_recording() {
+        info!("The palette can't change in the middle of an edit.");
+        return false;
+    }
+    let before = palette.clone();
+    f(palette);
+    if *palette != before {
+        animation.map_layers(layers, history, |x| palette::recolour(x, &before, palette));
+        history.attach_palettes(before, palette.clone());
+    }
+    true
+}
+
+fn palette_button_clicked(
+    query: Query<(&Interaction, &PaletteButton), (Changed<Interaction>, With<Button>)>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+    mut indexed: ResMut<IndexedCanvas>,
+    mut selected: ResMut<SelectedEntry>,
+    mut animation: ResMut<Animation>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        let tools_config = &mut app_config.tools_config;
+        let primary = tools_config.primary_color.read().expect("read lock failed.").to_u8_array();
+        let palette = &mut tools_config.palette;
+        let mut edit = |f: &mut dyn FnMut(&mut Palette)|
+            edit_palette(palette, indexed.enabled, &mut animation, &mut layers, &mut history, f);
+        match button.0 {
+            PaletteAction::Pick(i) => {
+                if let Some(color) = palette.get(i) {
+                    *tools_config.primary_color.write().expect("write lock failed.") = Srgba::from_u8_array(color);
+                    selected.0 = Some(i);
+                }
+            },
+            PaletteAction::Add => {
+                let mut added = false;
+                edit(&mut |x| added = x.push(primary));
+                if added {
+                    selected.0 = Some(palette.len() - 1);
+                } else {
+                    warn!("The palette is full.");
+                }
+            },
+            PaletteAction::Remove => {
+                if let Some(i) = selected.0 {
+                    if edit(&mut |x| { x.remove(i); }) {
+                        selected.0 = None;
+                    }
+                }
+            },
+            // recolours the canvas in the indexed mode.
+            PaletteAction::Replace => {
+                if let Some(i) = selected.0 {
+                    edit(&mut |x| x.set(i, primary));
+                }
+            },
+            PaletteAction::ToggleIndexed => indexed.enabled = !indexed.enabled,
+            PaletteAction::ToggleLock => tools_config.palette_lock = !tools_config.palette_lock,
+        }
+    }
+}
+
+fn text_button(builder: &mut ChildBuilder, font: &TextFont, action: PaletteAction, name: &str, on: bool) {
+    builder.spawn((
+            Button,
+            PaletteButton(action),
+            Node {
+                padding: UiRect::horizontal(Val::Px(3.)),
+                ..default()
+            },
+    ))
+        .with_child((Text::new(build_language_0(name)), font.clone(),
+            TextColor(if on { css::RED } else { css::WHITE }.into())));
+}
+
+// Rebuilds the swatches when the palette, the selection or the modes change.
+fn rebuild_palette_panel(
+    mut commands: Commands,
+    panel: Single<Entity, With<PalettePanel>>,
+    asset_server: Res<AssetServer>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    indexed: Res<IndexedCanvas>,
+    selected: Res<SelectedEntry>,
+    mut last: Local<Option<(Vec<[u8; 4]>, Option<usize>, bool, bool)>>,
+) {
+    let tools_config = &app_config.tools_config;
+    let current = (tools_config.palette.colors().to_vec(), selected.0, indexed.enabled, tools_config.palette_lock);
+    if last.as_ref() == Some(&current) { return; }
+
+    let font = TextFont {
+        font: asset_server.load(&tools_config.default_font),
+        font_size: tools_config.default_text_size * 2.,
+        ..default()
+    };
+    commands.entity(*panel).despawn_descendants().with_children(|builder| {
+        builder.spawn((Text::new(build_language_0("palette")), font.clone(), TextColor(css::LIME.into())));
+        builder.spawn(Node {
+            display: Display::Flex,
+            flex_wrap: FlexWrap::Wrap,
+            column_gap: Val::Px(4.),
+            ..default()
+        }).with_children(|row| {
+            text_button(row, &font, PaletteAction::ToggleIndexed, "indexed", current.2);
+            text_button(row, &font, PaletteAction::ToggleLock, "palette_lock", current.3);
+            text_button(row, &font, PaletteAction::Add, "palette_add", false);
+            text_button(row, &font, PaletteAction::Remove, "palette_remove", false);
+            text_button(row, &font, PaletteAction::Replace, "palette_replace", false);
+        });
+        builder.spawn(Node {
+            display: Display::Flex,
+            flex_wrap: FlexWrap::Wrap,
+            ..default()
+        }).with_children(|row| {
+            for (i, color) in current.0.iter().enumerate() {
+                row.spawn((
+                        Button,
+                        PaletteButton(PaletteAction::Pick(i)),
+                        Node {
+                            width: Val::Px(14.),
+                            height: Val::Px(14.),
+                            border: UiRect::all(Val::Px(1.)),
+                            ..default()
+                        },
+                        BorderColor(if current.1 == Some(i) { css::RED } else { css::BLACK }.into()),
+                        BackgroundColor(Srgba::from_u8_array(*color).into()),
+                ));
+            }
+        });
+    });
+    *last = Some(current);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+
+    #[test]
+    fn test_indexed_palette_edits_are_undone() {
+        let frame = |color| (100, LayerStack::new(UVec2::ONE, color));
+        let mut live = LayerStack::new(UVec2::ONE, [0; 4]);
+        let mut history = History::new(usize::MAX);
+        let mut animation = Animation::from_frames(vec![frame(RED), frame(BLUE)], &mut live, &mut history);
+        let original = Palette::new(vec![RED, BLUE]);
+        let mut palette = original.clone();
+        let color_of = |animation: &Animation, live: &LayerStack, i| {
+            animation.layers(i, live).unwrap().active().image.get_pixel(0, 0).0
+        };
+
+        assert!(edit_palette(&mut palette, false, &mut animation, &mut live, &mut history, |x| x.set(0, GREEN)));
+        assert_eq!(color_of(&animation, &live, 0), RED);
+        assert!(history.undo_palette().is_none());
+        palette = original.clone();
+
+        history.begin(live.active());
+        assert!(!edit_palette(&mut palette, true, &mut animation, &mut live, &mut history, |x| x.set(1, GREEN)));
+        assert_eq!(palette, original);
+        history.end(&live);
+
+        // every frame is recoloured, undo brings back the pixels and the palette.
+        assert!(edit_palette(&mut palette, true, &mut animation, &mut live, &mut history, |x| x.set(1, GREEN)));
+        assert_eq!((color_of(&animation, &live, 0), color_of(&animation, &live, 1)), (RED, GREEN));
+        assert_eq!(history.undo_palette(), Some(&original));
+        assert!(animation.undo(&mut live, &mut history));
+        assert_eq!(color_of(&animation, &live, 1), BLUE);
+        assert_eq!(history.redo_palette(), Some(&palette));
+    }
+}
@@ -1,7 +1,7 @@
 
 use std::collections::HashMap;
 
-use std::sync::{RwLock, };
+use std::sync::{Arc, RwLock};
 use std::fmt;
 use std::f64::consts::{SQRT_2, FRAC_1_SQRT_2};
 use bevy::color::palettes::css;
@@ -50,19 +50,22 @@ pub(crate) enum ColorMap {
     Image(RgbaImage),
     CoordColor(HashMap<UVec2, Srgba>),
     Color(Srgba),
+    // read at every stamp, e.g. the primary colour.
+    Shared(Arc<RwLock<Srgba>>),
 }
 
 fn coord_to_index(coord: UVec2, width: u32) -> usize {
-    (coord.x * width + coord.y) as usize
+    (coord.y * width + coord.x) as usize
 }
 
 fn coord_in_size(coord: &UVec2, size: &UVec2) -> bool {
     coord.x < size.x && coord.y < size.y
 }
 
+#[derive(Clone, Debug)]
 pub(crate) struct ComplexColor {
     size: UVec2, //  
-    pattern: Vec<u8>, // has color or not.
+    pattern: Vec<u8>, // has color or not, row by row.
     default_color: Srgba, 
     color_map: ColorMap,
 }
@@ -78,7 +81,28 @@ impl <'a> Default for ComplexColor {
     }
 }
 
+// Where a `ComplexColor` lies under the stamp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PatternPlacement {
+    Tile, // repeated from the top left corner of the stamp.
+    Center, // once in the middle of the stamp, transparent around.
+}
+
 impl ComplexColor {
+    pub(crate) fn new(size: UVec2, pattern: Vec<u8>, default_color: Srgba, color_map: ColorMap) -> Self {
+        assert_eq!(pattern.len(), (size.x * size.y) as usize, "ComplexColor::new: pattern size mismatch");
+        Self {
+            size,
+            pattern,
+            default_color,
+            color_map,
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
     pub fn center(&self) -> UVec2 {
         self.size / 2
     }
@@ -107,10 +131,28 @@ impl ComplexColor {
 
                 },
                 ColorMap::CoordColor(cc) => { cc.get(&coord).cloned() },
-                ColorMap::Color(cl) => { Some(cl.clone()) }
+                ColorMap::Color(cl) => { Some(cl.clone()) },
+                ColorMap::Shared(cl) => { Some(*cl.read().expect("read lock failed.")) },
             }
         }
     }
+
+    // The pixels outside of the pattern are transparent.
+    pub(crate) fn into_pattern(self, name: Option<String>, placement: PatternPlacement) -> PatternGeneratingFunc<'static> {
+        PatternGeneratingFunc::new(name, move |x, y, stamp_size| {
+            if self.size.x == 0 || self.size.y == 0 { return Srgba::NONE; }
+            let coord = match placement {
+                PatternPlacement::Tile => UVec2::new(x % self.size.x, y % self.size.y),
+                PatternPlacement::Center => {
+                    let offset = (*stamp_size / 2).as_ivec2() - self.center().as_ivec2();
+                    let coord = UVec2::new(x, y).as_ivec2() - offset;
+                    if coord.x < 0 || coord.y < 0 { return Srgba::NONE; }
+                    coord.as_uvec2()
+                },
+            };
+            self.get_color_at(coord).unwrap_or(Srgba::NONE)
+        })
+    }
 }
 
 
@@ -180,20 +222,57 @@ pub(crate) fn cake_generate<T, F>(radius: f64, defau: T, f: F) -> Vec<Vec<T>>
     ret
 }
 
-// The examples of pattern
-// pub(crate) const PENCIL: LazyCell<Arc<Pattern>> = LazyCell::new(|| Arc::new(Pattern::SimpleColor::default()));
-// pub(crate) const DOT_BRUSH6: LazyCell<Arc<Pattern>> = LazyCell::new(|| { Arc::new({
-//     let v = cake_generate(6.0, 0u8, |_, _| 1u8);
-//     let size = UVec2::new(v.len() as u32, if v.len() != 0 {v.first().unwrap().len() as u32} else {0u32} );
-//     let pattern: Vec<u8> = v.iter().flatten().cloned().collect();
-//     let p = Pattern::SimpleColor {
-//         size,
-//         pattern,
-//         default_color: Srgba::new(0., 0., 0., 0.),
-//         color_map: ColorMap::Color(css::BLACK.into()),
-//     };
-//     p
-// }) });
+// A round dot of `color` on transparency.
+pub(crate) fn dot_brush(radius: f64, color: Srgba) -> ComplexColor {
+    let v = cake_generate(radius, 0u8, |_, _| 1u8);
+    let size = UVec2::new(v.len() as u32, v.first().map(|x| x.len() as u32).unwrap_or(0));
+    let pattern: Vec<u8> = v.iter().flatten().cloned().collect();
+    ComplexColor::new(size, pattern, Srgba::NONE, ColorMap::Color(color))
+}
+
+// A pattern that brings its own stamp size.
+#[derive(Clone, Debug)]
+pub(crate) struct BrushPreset {
+    pub name: String,
+    pub color: ComplexColor,
+    pub placement: PatternPlacement,
+}
+
+impl BrushPreset {
+    pub(crate) fn size(&self) -> UVec2 {
+        self.color.size().max(UVec2::ONE)
+    }
+
+    // The single colour presets paint with `primary`.
+    pub(crate) fn make(&self, primary: &Arc<RwLock<Srgba>>) -> PatternGeneratingFunc<'static> {
+        let mut color = self.color.clone();
+        if let ColorMap::Color(_) = color.color_map {
+            color.color_map = ColorMap::Shared(primary.clone());
+        }
+        color.into_pattern(Some(self.name.clone()), self.placement)
+    }
+}
+
+// The first one is the default pattern, the primary colour all over the stamp.
+pub(crate) fn brush_presets() -> Vec<BrushPreset> {
+    let black: Srgba = css::BLACK.into();
+    let mut ret = vec![BrushPreset {
+        name: "dot".to_owned(),
+        color: ComplexColor::default(),
+        placement: PatternPlacement::Tile,
+    }];
+    ret.extend([2., 4., 6.].into_iter().map(|radius| BrushPreset {
+        name: format!("dot_brush{}", radius),
+        color: dot_brush(radius, black),
+        placement: PatternPlacement::Center,
+    }));
+    ret.push(BrushPreset {
+        name: "checker".to_owned(),
+        color: ComplexColor::new(UVec2::splat(2), vec![1, 0, 0, 1], Srgba::NONE, ColorMap::Color(black)),
+        placement: PatternPlacement::Tile,
+    });
+    ret
+}
 
 #[cfg(test)]
 mod test {
@@ -231,4 +310,65 @@ mod test {
             assert_eq!(v[3], expected);
         }
     }
+    fn red_green() -> ComplexColor {
+        // a 3x2 pattern, green where it is set.
+        // . x x
+        // . . x
+        ComplexColor::new(UVec2::new(3, 2), vec![0, 1, 1, 0, 0, 1], css::RED.into(),
+            ColorMap::Color(css::GREEN.into()))
+    }
+
+    #[test]
+    fn test_non_square_complex_color() {
+        let color = red_green();
+        let green: Srgba = css::GREEN.into();
+        let red: Srgba = css::RED.into();
+        assert_eq!(color.get_color_at(UVec2::new(1, 0)), Some(green));
+        assert_eq!(color.get_color_at(UVec2::new(1, 1)), Some(red));
+        assert_eq!(color.get_color_at(UVec2::new(2, 1)), Some(green));
+        assert_eq!(color.get_color_at(UVec2::new(0, 2)), None);
+        assert_eq!(color.get_color_at(UVec2::new(3, 0)), None);
+
+        let image = RgbaImage::from_fn(3, 2, |x, y| image::Rgba([x as u8, y as u8, 0, 255]));
+        let color = ComplexColor::new(UVec2::new(3, 2), vec![1; 6], red, ColorMap::Image(image));
+        assert_eq!(color.get_color_at(UVec2::new(2, 1)).unwrap().to_u8_array(), [2, 1, 0, 255]);
+    }
+
+    #[test]
+    fn test_pattern_placement() {
+        let green: Srgba = css::GREEN.into();
+        let red: Srgba = css::RED.into();
+        let stamp = UVec2::new(7, 4);
+
+        let tiled = red_green().into_pattern(None, PatternPlacement::Tile);
+        assert_eq!((tiled.fun)(4, 0, &stamp), green);
+        assert_eq!((tiled.fun)(3, 3, &stamp), red);
+        assert_eq!((tiled.fun)(5, 3, &stamp), green);
+
+        // the center of the pattern (1, 1) lies on the center of the stamp (3, 2).
+        let centered = red_green().into_pattern(None, PatternPlacement::Center);
+        assert_eq!((centered.fun)(4, 2, &stamp), green);
+        assert_eq!((centered.fun)(3, 2, &stamp), red);
+        assert_eq!((centered.fun)(0, 0, &stamp), Srgba::NONE);
+        assert_eq!((centered.fun)(5, 2, &stamp), Srgba::NONE);
+    }
+
+    #[test]
+    fn test_dot_brush_presets() {
+        let presets = brush_presets();
+        let primary = Arc::new(RwLock::new(css::BLACK.into()));
+        let dot6 = presets.iter().find(|x| x.name == "dot_brush6").unwrap();
+        assert_eq!(dot6.size(), UVec2::splat(12));
+        let pattern = dot6.make(&primary);
+        assert_eq!(pattern.name, "dot_brush6");
+        assert_eq!((pattern.fun)(6, 6, &dot6.size()), css::BLACK.into());
+        assert_eq!((pattern.fun)(0, 0, &dot6.size()), Srgba::NONE);
+
+        // the primary colour is read when stamping.
+        *primary.write().unwrap() = css::RED.into();
+        assert_eq!((pattern.fun)(6, 6, &dot6.size()), css::RED.into());
+        let dot = presets[0].make(&primary);
+        assert_eq!(dot.name, "dot");
+        assert_eq!((dot.fun)(3, 5, &UVec2::splat(8)), css::RED.into());
+    }
 }
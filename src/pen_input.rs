@@ -0,0 +1,185 @@
+use bevy::{
+    prelude::*,
+    input::touch::{ForceTouch, Touches},
+    window::PrimaryWindow,
+};
+use std::f32::consts::FRAC_PI_2;
+
+use crate::pressure_mask::Pressure;
+
+pub fn init_me(app: &mut App) {
+    app.init_resource::<PenState>()
+        .add_systems(PreUpdate, update_pen_state.after(bevy::input::InputSystem));
+}
+
+// One sample of the pen, the position is in window coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PenSample {
+    pub position: Vec2,
+    pub pressure: Pressure,
+}
+
+// The pen of this frame, fed by a tablet, a touch screen or the mouse.
+#[derive(Resource, Debug, Default)]
+pub(crate) struct PenState {
+    pub sample: Option<PenSample>,
+    pressed: bool,
+    just_pressed: bool,
+}
+
+impl PenState {
+    pub(crate) fn pressed(&self) -> bool {
+        self.pressed
+    }
+
+    pub(crate) fn just_pressed(&self) -> bool {
+        self.just_pressed
+    }
+
+    fn update(&mut self, sample: Option<PenSample>, pressed: bool) {
+        self.just_pressed = pressed && !self.pressed;
+        self.pressed = pressed;
+        self.sample = sample;
+    }
+}
+
+// Gives one sample per frame, `None` lifts the pen.
+pub(crate) trait PressureSource: Send + Sync {
+    fn next_sample(&mut self) -> Option<PenSample>;
+}
+
+// Replays recorded samples, for tests and for reproducing strokes.
+#[derive(Debug, Clone)]
+pub(crate) struct ReplaySource {
+    samples: Vec<PenSample>,
+    next: usize,
+}
+
+impl ReplaySource {
+    pub(crate) fn new(samples: Vec<PenSample>) -> Self {
+        Self {
+            samples,
+            next: 0,
+        }
+    }
+}
+
+impl PressureSource for ReplaySource {
+    fn next_sample(&mut self) -> Option<PenSample> {
+        let ret = self.samples.get(self.next).copied();
+        self.next += 1;
+        ret
+    }
+}
+
+// When present it replaces the real devices.
+#[derive(Resource)]
+pub(crate) struct SyntheticPen(pub Box<dyn PressureSource>);
+
+fn force_to_pressure(force: Option<ForceTouch>) -> Pressure {
+    match force {
+        Some(ForceTouch::Calibrated { force, max_possible_force, altitude_angle }) => Pressure {
+            pressure: (force / max_possible_force.max(f64::EPSILON)) as f32,
+            // the altitude is pi/2 when the pen is perpendicular to the surface.
+            tilt: altitude_angle.map(|x| 1. - (x as f32 / FRAC_PI_2).clamp(0., 1.)),
+        },
+        Some(ForceTouch::Normalized(force)) => Pressure {
+            pressure: force as f32,
+            tilt: None,
+        },
+        None => Pressure::FULL,
+    }
+}
+
+fn update_pen_state(
+    mut pen: ResMut<PenState>,
+    synthetic: Option<ResMut<SyntheticPen>>,
+    touches: Res<Touches>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    window: Single<&Window, With<PrimaryWindow>>,
+) {
+    if let Some(mut synthetic) = synthetic {
+        let sample = synthetic.0.next_sample();
+        pen.update(sample, sample.is_some());
+        return;
+    }
+
+    // a pen or a finger wins over the mouse.
+    if let Some(touch) = touches.iter().next() {
+        pen.update(Some(PenSample {
+            position: touch.position(),
+            pressure: force_to_pressure(touch.force()),
+        }), true);
+        return;
+    }
+
+    let sample = window.cursor_position().map(|position| PenSample {
+        position,
+        pressure: Pressure::FULL,
+    });
+    pen.update(sample, buttons.pressed(MouseButton::Left));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::RgbaImage;
+    use std::sync::{Arc, RwLock};
+
+    use crate::mix_methods::MixMethod;
+    use crate::patterns::PatternGeneratingFunc;
+    use crate::pressure_mask::{MaskGeneratingFunc, PressureDynamics};
+    use crate::stroke::Stroke;
+    use crate::tools::Brush;
+
+    fn sample(x: f32, pressure: f32) -> PenSample {
+        PenSample {
+            position: Vec2::new(x, 8.),
+            pressure: Pressure { pressure, tilt: None },
+        }
+    }
+
+    fn painted_rows(image: &RgbaImage, xs: std::ops::Range<u32>) -> u32 {
+        (0..image.height()).filter(|y| xs.clone().any(|x| image.get_pixel(x, *y).0[3] > 0)).count() as u32
+    }
+
+    #[test]
+    fn test_replayed_stroke_follows_pressure() {
+        let mut pen = PenState::default();
+        let mut source = ReplaySource::new(vec![sample(2., 0.2), sample(30., 1.)]);
+        let mask = MaskGeneratingFunc::new(None, |_, _, _| 1.);
+        let pattern = PatternGeneratingFunc::new(None, |_, _, _| Srgba::BLACK);
+        let dynamics = PressureDynamics::default();
+        let brush = Brush::new(&mask, &pattern, Arc::new(RwLock::new(UVec2::splat(10))),
+            &MixMethod::Normal, &dynamics);
+
+        let mut image = RgbaImage::new(32, 16);
+        let mut stroke = Stroke::new(0.1);
+        loop {
+            let sample = source.next_sample();
+            pen.update(sample, sample.is_some());
+            let Some(sample) = pen.sample else { break; };
+            if pen.just_pressed() {
+                assert_eq!(sample.position.x, 2.);
+            }
+            stroke.stroke_to(&brush, &mut image, sample.position, sample.pressure, UVec2::splat(10));
+        }
+        assert!(!pen.pressed());
+
+        // the stamps grow with the pressure.
+        assert_eq!(painted_rows(&image, 1..2), 2);
+        assert_eq!(painted_rows(&image, 29..30), 10);
+    }
+
+    #[test]
+    fn test_force_to_pressure() {
+        assert_eq!(force_to_pressure(None), Pressure::FULL);
+        assert_eq!(force_to_pressure(Some(ForceTouch::Normalized(0.5))).pressure, 0.5);
+        let pressure = force_to_pressure(Some(ForceTouch::Calibrated {
+            force: 1.,
+            max_possible_force: 4.,
+            altitude_angle: Some(0.),
+        }));
+        assert_eq!(pressure, Pressure { pressure: 0.25, tilt: Some(1.) });
+    }
+}
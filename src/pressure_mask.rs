@@ -1,6 +1,7 @@
 use std::fmt;
 use std::sync::RwLock;
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub(crate) struct MaskGeneratingFunc<'a> {
     pub name: String,
@@ -38,3 +39,331 @@ impl fmt::Debug for MaskGeneratingFunc<'_> {
             .finish()
     }
 }
+
+// The shape of a built-in mask, `size` of the stamp is given when generating.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum MaskKind {
+    Square,
+    HardCircle,
+    // gaussian falloff, `sigma` is relative to the radius.
+    SoftCircle { sigma: f32 },
+    Diamond,
+    // ordered dither, `order` is 2, 4 or 8 and `level` the covered part in 0..=1.
+    Bayer { order: u32, level: f32 },
+    // covers about `density` of the pixels.
+    Noise { seed: u64, density: f32 },
+    // the alpha of a captured brush, row by row, centered in the stamp.
+    Alpha { size: UVec2, alpha: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct MaskPreset {
+    pub name: String,
+    pub kind: MaskKind,
+}
+
+fn bayer(order: u32, x: u32, y: u32) -> u32 {
+    // interleaves the bits of x ^ y and y, reversed.
+    let bits = order.trailing_zeros();
+    let (x, y) = (x % order, y % order);
+    let mut ret = 0;
+    for bit in 0..bits {
+        let xb = (x >> bit) & 1;
+        let yb = (y >> bit) & 1;
+        ret |= ((xb ^ yb) << 1 | yb) << (2 * (bits - 1 - bit));
+    }
+    ret
+}
+
+fn noise(seed: u64, x: u32, y: u32) -> f32 {
+    // splitmix64 of the coordinates.
+    let mut z = seed ^ ((x as u64) << 32 | y as u64);
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// distance of the pixel center from the stamp center, 1 on the inscribed ellipse.
+fn relative_distance(x: u32, y: u32, size: &UVec2) -> Vec2 {
+    let half = size.as_vec2() / 2.;
+    (Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - half) / half
+}
+
+impl MaskPreset {
+    pub(crate) fn new(name: &str, kind: MaskKind) -> Self {
+        Self {
+            name: name.to_owned(),
+            kind,
+        }
+    }
+
+    pub(crate) fn make(&self) -> MaskGeneratingFunc<'static> {
+        let name = Some(self.name.clone());
+        match self.kind.clone() {
+            MaskKind::Square => MaskGeneratingFunc::new(name, |_x, _y, _size| 1.),
+            MaskKind::HardCircle => {
+                // the disc of the last size is kept, the brush size rarely changes.
+                let cache: RwLock<Option<(UVec2, Vec<Vec<u8>>)>> = RwLock::new(None);
+                MaskGeneratingFunc::new(name, move |x, y, size| {
+                    if size.min_element() <= 1 { return 1.; }
+                    let cached = cache.read().expect("read circle cache failed.")
+                        .as_ref().is_some_and(|x| x.0 == *size);
+                    if !cached {
+                        let radius = size.max_element() as f64 / 2.;
+                        let disc = crate::patterns::cake_generate(radius, 0u8, |_, _| 1u8);
+                        *cache.write().expect("write circle cache failed.") = Some((*size, disc));
+                    }
+                    let cache = cache.read().expect("read circle cache failed.");
+                    let disc = &cache.as_ref().expect("circle cache is empty.").1;
+                    let n = disc.len() as u32;
+                    if n == 0 { return 0.; }
+                    disc[(x * n / size.x) as usize][(y * n / size.y) as usize] as f32
+                })
+            },
+            MaskKind::SoftCircle { sigma } => MaskGeneratingFunc::new(name, move |x, y, size| {
+                let d = relative_distance(x, y, size).length_squared();
+                if d > 1. { 0. } else { (-d / (2. * sigma * sigma)).exp() }
+            }),
+            MaskKind::Diamond => MaskGeneratingFunc::new(name, |x, y, size| {
+                let d = relative_distance(x, y, size).abs();
+                if d.x + d.y <= 1. { 1. } else { 0. }
+            }),
+            MaskKind::Bayer { order, level } => MaskGeneratingFunc::new(name, move |x, y, _size| {
+                let threshold = (bayer(order, x, y) as f32 + 0.5) / (order * order) as f32;
+                if threshold < level { 1. } else { 0. }
+            }),
+            MaskKind::Noise { seed, density } => MaskGeneratingFunc::new(name, move |x, y, _size| {
+                if noise(seed, x, y) < density { 1. } else { 0. }
+            }),
+            MaskKind::Alpha { size: alpha_size, alpha } => MaskGeneratingFunc::new(name, move |x, y, size| {
+                let coord = UVec2::new(x, y).as_ivec2() - (*size / 2).as_ivec2() + (alpha_size / 2).as_ivec2();
+                if coord.x < 0 || coord.y < 0 || coord.x >= alpha_size.x as i32 || coord.y >= alpha_size.y as i32 {
+                    return 0.;
+                }
+                alpha[(coord.y as u32 * alpha_size.x + coord.x as u32) as usize] as f32 / 255.
+            }),
+        }
+    }
+}
+
+// The masks to choose from by name.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MaskLibrary {
+    presets: Vec<MaskPreset>,
+}
+
+impl Default for MaskLibrary {
+    fn default() -> Self {
+        Self {
+            presets: vec![
+                MaskPreset::new("overwrite", MaskKind::Square),
+                MaskPreset::new("hard_round", MaskKind::HardCircle),
+                MaskPreset::new("soft_round", MaskKind::SoftCircle { sigma: 0.4 }),
+                MaskPreset::new("square", MaskKind::Square),
+                MaskPreset::new("diamond", MaskKind::Diamond),
+                MaskPreset::new("bayer2", MaskKind::Bayer { order: 2, level: 0.5 }),
+                MaskPreset::new("bayer4", MaskKind::Bayer { order: 4, level: 0.5 }),
+                MaskPreset::new("bayer8", MaskKind::Bayer { order: 8, level: 0.5 }),
+                MaskPreset::new("noise", MaskKind::Noise { seed: 0, density: 0.5 }),
+            ],
+        }
+    }
+}
+
+impl MaskLibrary {
+    pub(crate) fn presets(&self) -> &[MaskPreset] {
+        &self.presets
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&MaskPreset> {
+        self.presets.iter().find(|x| x.name == name)
+    }
+
+    // A preset of the same name is replaced.
+    pub(crate) fn insert(&mut self, preset: MaskPreset) {
+        match self.presets.iter_mut().find(|x| x.name == preset.name) {
+            Some(o) => *o = preset,
+            None => self.presets.push(preset),
+        }
+    }
+
+    pub(crate) fn make(&self, name: &str) -> Option<MaskGeneratingFunc<'static>> {
+        self.get(name).map(|x| x.make())
+    }
+}
+
+// The pressure of a pen in 0..=1 and its tilt in 0..=1, 0 is upright.
+// Devices without pressure give full pressure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Pressure {
+    pub pressure: f32,
+    pub tilt: Option<f32>,
+}
+
+impl Pressure {
+    pub const FULL: Self = Self {
+        pressure: 1.,
+        tilt: None,
+    };
+
+    pub(crate) fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            pressure: self.pressure + (other.pressure - self.pressure) * t,
+            tilt: match (self.tilt, other.tilt) {
+                (Some(a), Some(b)) => Some(a + (b - a) * t),
+                (a, b) => b.or(a),
+            },
+        }
+    }
+}
+
+// Maps the pressure to a factor in 0..=1.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ResponseCurve {
+    Off, // always 1.
+    Linear { min: f32 },
+    Gamma { min: f32, gamma: f32 },
+    // (input, output) pairs sorted by input, linear in between.
+    Points(Vec<(f32, f32)>),
+}
+
+impl ResponseCurve {
+    pub(crate) fn evaluate(&self, input: f32) -> f32 {
+        let input = input.clamp(0., 1.);
+        let ret = match self {
+            ResponseCurve::Off => 1.,
+            ResponseCurve::Linear { min } => min + (1. - min) * input,
+            ResponseCurve::Gamma { min, gamma } => min + (1. - min) * input.powf(*gamma),
+            ResponseCurve::Points(points) => {
+                match points.iter().position(|x| x.0 >= input) {
+                    None => points.last().map(|x| x.1).unwrap_or(1.),
+                    Some(0) => points[0].1,
+                    Some(i) => {
+                        let (a, b) = (points[i - 1], points[i]);
+                        a.1 + (b.1 - a.1) * (input - a.0) / (b.0 - a.0)
+                    }
+                }
+            }
+        };
+        ret.clamp(0., 1.)
+    }
+}
+
+// What the pressure and tilt change of a brush stamp.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct PressureDynamics {
+    pub size: ResponseCurve,
+    pub opacity: ResponseCurve,
+    // 1 applies the mask fully, 0 ignores it.
+    pub mask_strength: ResponseCurve,
+    // scales the size by the tilt.
+    pub tilt_size: ResponseCurve,
+}
+
+impl Default for PressureDynamics {
+    fn default() -> Self {
+        Self {
+            size: ResponseCurve::Linear { min: 0. },
+            opacity: ResponseCurve::Off,
+            mask_strength: ResponseCurve::Off,
+            tilt_size: ResponseCurve::Off,
+        }
+    }
+}
+
+impl PressureDynamics {
+    pub(crate) fn size_scale(&self, pressure: &Pressure) -> f32 {
+        self.size.evaluate(pressure.pressure)
+            * pressure.tilt.map(|x| self.tilt_size.evaluate(x)).unwrap_or(1.)
+    }
+
+    pub(crate) fn opacity(&self, pressure: &Pressure) -> f32 {
+        self.opacity.evaluate(pressure.pressure)
+    }
+
+    pub(crate) fn mask_strength(&self, pressure: &Pressure) -> f32 {
+        self.mask_strength.evaluate(pressure.pressure)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_response_curves() {
+        assert_eq!(ResponseCurve::Off.evaluate(0.2), 1.);
+        assert_eq!(ResponseCurve::Linear { min: 0. }.evaluate(0.25), 0.25);
+        assert_eq!(ResponseCurve::Linear { min: 0.5 }.evaluate(0.5), 0.75);
+        assert_eq!(ResponseCurve::Linear { min: 0. }.evaluate(3.), 1.);
+        assert_eq!(ResponseCurve::Gamma { min: 0., gamma: 2. }.evaluate(0.5), 0.25);
+
+        let points = ResponseCurve::Points(vec![(0.25, 0.), (0.75, 1.)]);
+        assert_eq!(points.evaluate(0.), 0.);
+        assert_eq!(points.evaluate(0.5), 0.5);
+        assert_eq!(points.evaluate(0.9), 1.);
+    }
+
+    #[test]
+    fn test_dynamics() {
+        let dynamics = PressureDynamics {
+            tilt_size: ResponseCurve::Linear { min: 0.5 },
+            ..default()
+        };
+        assert_eq!(dynamics.size_scale(&Pressure::FULL), 1.);
+        assert_eq!(dynamics.size_scale(&Pressure { pressure: 0.5, tilt: Some(0.) }), 0.25);
+        assert_eq!(dynamics.opacity(&Pressure { pressure: 0.5, tilt: None }), 1.);
+
+        let a = Pressure { pressure: 0., tilt: None };
+        let b = Pressure { pressure: 1., tilt: Some(0.5) };
+        assert_eq!(a.lerp(&b, 0.25), Pressure { pressure: 0.25, tilt: Some(0.5) });
+    }
+
+    fn sample(mask: &MaskGeneratingFunc, size: UVec2) -> Vec<Vec<f32>> {
+        (0..size.y).map(|y| (0..size.x).map(|x| (mask.fun)(x, y, &size)).collect()).collect()
+    }
+
+    #[test]
+    fn test_mask_library() {
+        let library = MaskLibrary::default();
+        assert!(library.make("missing").is_none());
+        let names: Vec<&str> = library.presets().iter().map(|x| x.name.as_str()).collect();
+        for name in ["hard_round", "soft_round", "square", "diamond", "bayer2", "bayer4", "bayer8", "noise"] {
+            assert!(names.contains(&name), "{} is missing", name);
+            assert_eq!(library.make(name).unwrap().name, name);
+        }
+
+        let size = UVec2::splat(8);
+        let hard = sample(&library.make("hard_round").unwrap(), size);
+        assert_eq!(hard[4][4], 1.);
+        assert_eq!(hard[0][0], 0.);
+        assert_eq!((library.make("hard_round").unwrap().fun)(0, 0, &UVec2::ONE), 1.);
+
+        let soft = sample(&library.make("soft_round").unwrap(), size);
+        assert!(soft[4][4] > soft[4][6] && soft[4][6] > 0.);
+        assert_eq!(soft[0][0], 0.);
+
+        let diamond = sample(&library.make("diamond").unwrap(), size);
+        assert_eq!(diamond[4][0], 1.);
+        assert_eq!(diamond[0][1], 0.);
+    }
+
+    #[test]
+    fn test_bayer_and_noise() {
+        assert_eq!((0..4).map(|i| bayer(2, i % 2, i / 2)).collect::<Vec<_>>(), vec![0, 2, 3, 1]);
+        let mut values: Vec<u32> = (0..64).map(|i| bayer(8, i % 8, i / 8)).collect();
+        values.sort();
+        assert_eq!(values, (0..64).collect::<Vec<_>>());
+
+        // half of the pixels are covered by the 50% dither and about half by the noise.
+        let size = UVec2::splat(16);
+        let library = MaskLibrary::default();
+        let covered = |name: &str| sample(&library.make(name).unwrap(), size)
+            .into_iter().flatten().filter(|x| *x == 1.).count();
+        assert_eq!(covered("bayer4"), 128);
+        assert!((96..160).contains(&covered("noise")));
+        assert_eq!(sample(&library.make("noise").unwrap(), size), sample(&library.make("noise").unwrap(), size));
+    }
+}
@@ -0,0 +1,402 @@
+// The native `.pixelin` project file: a zip container with a RON manifest
+// named `project.ron` and one PNG blob per layer of every animation frame.
+use bevy::prelude::*;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::animation::DEFAULT_DURATION_MS;
+use crate::file_io::{decode_png, encode_png};
+use crate::layers::LayerInfo;
+use crate::mix_methods::MixMethod;
+
+pub(crate) const PROJECT_VERSION: u32 = 2;
+pub(crate) const PROJECT_EXTENSION: &str = "pixelin";
+const MANIFEST_NAME: &str = "project.ron";
+
+#[derive(Debug)]
+pub(crate) enum ProjectError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    Manifest(String),
+    Image(image::ImageError),
+    // written by a newer pixelin.
+    UnsupportedVersion(u32),
+    BadLayer(String),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Io(e) => write!(f, "io error: {}", e),
+            ProjectError::Zip(e) => write!(f, "zip error: {}", e),
+            ProjectError::Manifest(e) => write!(f, "bad manifest: {}", e),
+            ProjectError::Image(e) => write!(f, "image error: {}", e),
+            ProjectError::UnsupportedVersion(v) =>
+                write!(f, "project version {} is newer than the supported {}", v, PROJECT_VERSION),
+            ProjectError::BadLayer(e) => write!(f, "bad layer: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+impl From<std::io::Error> for ProjectError {
+    fn from(e: std::io::Error) -> Self { ProjectError::Io(e) }
+}
+
+impl From<zip::result::ZipError> for ProjectError {
+    fn from(e: zip::result::ZipError) -> Self { ProjectError::Zip(e) }
+}
+
+impl From<image::ImageError> for ProjectError {
+    fn from(e: image::ImageError) -> Self { ProjectError::Image(e) }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub(crate) struct ToolsSelection {
+    pub tool: Option<String>,
+    pub mix_method: MixMethod,
+    pub mask: String,
+    pub pattern: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub(crate) struct ViewTransform {
+    pub translation: Vec2,
+    pub scale: f32,
+}
+
+impl Default for ViewTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vec2::ZERO,
+            scale: 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ProjectLayer {
+    pub info: LayerInfo,
+    pub image: RgbaImage,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ProjectFrame {
+    pub duration_ms: u32,
+    pub layers: Vec<ProjectLayer>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Project {
+    pub canvas_size: UVec2,
+    pub frames: Vec<ProjectFrame>,
+    pub palette: Vec<[u8; 4]>,
+    pub indexed: bool,
+    pub tools: ToolsSelection,
+    pub view: ViewTransform,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LayerEntry {
+    info: LayerInfo,
+    file: String,
+}
+
+fn default_duration() -> u32 {
+    DEFAULT_DURATION_MS
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct FrameEntry {
+    #[serde(default = "default_duration")]
+    duration_ms: u32,
+    layers: Vec<LayerEntry>,
+}
+
+// Fields unknown to this version are ignored and missing fields take their
+// default, so that minor additions don't need a new version.
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    version: u32,
+    canvas_size: UVec2,
+    // the only frame of version 1.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<LayerEntry>,
+    #[serde(default)]
+    frames: Vec<FrameEntry>,
+    #[serde(default)]
+    palette: Vec<[u8; 4]>,
+    #[serde(default)]
+    indexed: bool,
+    #[serde(default)]
+    tools: ToolsSelection,
+    #[serde(default)]
+    view: ViewTransform,
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: u32,
+}
+
+// Each step upgrades a manifest of one version to the next, the first one is of
+// version 1. The fields of older versions stay in `Manifest` for their steps.
+const MIGRATIONS: [fn(&mut Manifest); PROJECT_VERSION as usize - 1] = [frames_of_v1];
+
+// Version 1 has one frame of the default duration.
+fn frames_of_v1(manifest: &mut Manifest) {
+    manifest.frames = vec![FrameEntry {
+        duration_ms: DEFAULT_DURATION_MS,
+        layers: std::mem::take(&mut manifest.layers),
+    }];
+}
+
+// Brings the manifest of an older version up to `PROJECT_VERSION`.
+fn migrate(text: &str) -> Result<Manifest, ProjectError> {
+    let bad_manifest = |e: ron::error::SpannedError| ProjectError::Manifest(e.to_string());
+    let VersionOnly { version } = ron::from_str(text).map_err(bad_manifest)?;
+    if version > PROJECT_VERSION {
+        return Err(ProjectError::UnsupportedVersion(version));
+    }
+    if version == 0 {
+        return Err(ProjectError::Manifest("unknown project version 0".to_owned()));
+    }
+    let mut manifest: Manifest = ron::from_str(text).map_err(bad_manifest)?;
+    for step in &MIGRATIONS[version as usize - 1..] {
+        step(&mut manifest);
+    }
+    manifest.version = PROJECT_VERSION;
+    Ok(manifest)
+}
+
+// the first frame keeps the paths of the projects without frames.
+fn layer_file(frame: usize, index: usize) -> String {
+    match frame {
+        0 => format!("layers/{}.png", index),
+        _ => format!("frames/{}/{}.png", frame, index),
+    }
+}
+
+pub(crate) fn write_project<W: Write + Seek>(project: &Project, writer: W) -> Result<(), ProjectError> {
+    let mut zip = ZipWriter::new(writer);
+    let frames: Vec<FrameEntry> = project.frames.iter().enumerate().map(|(f, frame)| FrameEntry {
+        duration_ms: frame.duration_ms,
+        layers: frame.layers.iter().enumerate().map(|(i, x)| LayerEntry {
+            info: x.info.clone(),
+            file: layer_file(f, i),
+        }).collect(),
+    }).collect();
+    let manifest = Manifest {
+        version: PROJECT_VERSION,
+        canvas_size: project.canvas_size,
+        layers: Vec::new(),
+        frames,
+        palette: project.palette.clone(),
+        indexed: project.indexed,
+        tools: project.tools.clone(),
+        view: project.view,
+    };
+    let text = ron::ser::to_string_pretty(&manifest, ron::ser::PrettyConfig::default())
+        .map_err(|e| ProjectError::Manifest(e.to_string()))?;
+    zip.start_file(MANIFEST_NAME, SimpleFileOptions::default())?;
+    zip.write_all(text.as_bytes())?;
+
+    // png is compressed already.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (entry, frame) in manifest.frames.iter().zip(&project.frames) {
+        for (entry, layer) in entry.layers.iter().zip(&frame.layers) {
+            zip.start_file(entry.file.as_str(), stored)?;
+            zip.write_all(&encode_png(&layer.image)?)?;
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+pub(crate) fn read_project<R: Read + Seek>(reader: R) -> Result<Project, ProjectError> {
+    let mut zip = ZipArchive::new(reader)?;
+    let mut text = String::new();
+    zip.by_name(MANIFEST_NAME)?.read_to_string(&mut text)?;
+    let manifest = migrate(&text)?;
+    if manifest.frames.is_empty() {
+        return Err(ProjectError::Manifest("no frames".to_owned()));
+    }
+
+    let mut frames = Vec::with_capacity(manifest.frames.len());
+    for frame in manifest.frames {
+        let mut layers = Vec::with_capacity(frame.layers.len());
+        for entry in frame.layers {
+            let mut bytes = Vec::new();
+            zip.by_name(&entry.file)?.read_to_end(&mut bytes)?;
+            let image = decode_png(&bytes)?;
+            if UVec2::from(image.dimensions()) != manifest.canvas_size {
+                return Err(ProjectError::BadLayer(
+                        format!("{} isn't of the canvas size {}", entry.file, manifest.canvas_size)));
+            }
+            layers.push(ProjectLayer {
+                info: entry.info,
+                image,
+            });
+        }
+        frames.push(ProjectFrame {
+            duration_ms: frame.duration_ms.max(1),
+            layers,
+        });
+    }
+
+    Ok(Project {
+        canvas_size: manifest.canvas_size,
+        frames,
+        palette: manifest.palette,
+        indexed: manifest.indexed,
+        tools: manifest.tools,
+        view: manifest.view,
+    })
+}
+
+pub(crate) fn save_project(project: &Project, path: &Path) -> Result<(), ProjectError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_project(project, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub(crate) fn load_project(path: &Path) -> Result<Project, ProjectError> {
+    read_project(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgba;
+    use std::io::Cursor;
+
+    fn sample_project() -> Project {
+        Project {
+            canvas_size: UVec2::new(4, 3),
+            frames: vec![
+                ProjectFrame {
+                    duration_ms: 80,
+                    layers: vec![
+                        ProjectLayer {
+                            info: LayerInfo::default(),
+                            image: RgbaImage::from_pixel(4, 3, Rgba([255, 255, 255, 255])),
+                        },
+                        ProjectLayer {
+                            info: LayerInfo {
+                                name: "ink".to_owned(),
+                                visible: false,
+                                locked: true,
+                                opacity: 0.5,
+                                blend: MixMethod::RatioAdd(0.25),
+                            },
+                            image: RgbaImage::from_fn(4, 3, |x, y| Rgba([x as u8, y as u8, 0, 128])),
+                        },
+                    ],
+                },
+                ProjectFrame {
+                    duration_ms: 120,
+                    layers: vec![ProjectLayer {
+                        info: LayerInfo::default(),
+                        image: RgbaImage::from_pixel(4, 3, Rgba([0, 0, 255, 255])),
+                    }],
+                },
+            ],
+            palette: vec![[0, 0, 0, 255], [255, 0, 77, 255]],
+            indexed: true,
+            tools: ToolsSelection {
+                tool: Some("pencil".to_owned()),
+                mix_method: MixMethod::Multiply,
+                mask: "overwrite".to_owned(),
+                pattern: "dot".to_owned(),
+            },
+            view: ViewTransform {
+                translation: Vec2::new(12., -3.5),
+                scale: 0.25,
+            },
+        }
+    }
+
+    fn zip_with_manifest(text: &str) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(MANIFEST_NAME, SimpleFileOptions::default()).unwrap();
+        zip.write_all(text.as_bytes()).unwrap();
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let project = sample_project();
+        let mut bytes = Cursor::new(Vec::new());
+        write_project(&project, &mut bytes).unwrap();
+        bytes.set_position(0);
+        assert_eq!(read_project(bytes).unwrap(), project);
+    }
+
+    #[test]
+    fn test_unknown_and_missing_fields() {
+        let cursor = zip_with_manifest("(version: 2, canvas_size: (2, 2), frames: [(layers: [])], from_the_future: true)");
+        let project = read_project(cursor).unwrap();
+        assert_eq!(project.canvas_size, UVec2::new(2, 2));
+        assert_eq!(project.frames.len(), 1);
+        assert!(project.frames[0].layers.is_empty());
+        assert_eq!(project.frames[0].duration_ms, DEFAULT_DURATION_MS);
+        assert_eq!(project.view, ViewTransform::default());
+    }
+
+    #[test]
+    fn test_version_1() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(MANIFEST_NAME, SimpleFileOptions::default()).unwrap();
+        zip.write_all(br#"(
+            version: 1,
+            canvas_size: (2, 1),
+            layers: [(info: (name: "ink", blend: Multiply), file: "layers/0.png")],
+            palette: [(1, 2, 3, 255)],
+        )"#).unwrap();
+        zip.start_file("layers/0.png", SimpleFileOptions::default()).unwrap();
+        let image = RgbaImage::from_pixel(2, 1, Rgba([9, 8, 7, 255]));
+        zip.write_all(&encode_png(&image).unwrap()).unwrap();
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+
+        // the layers become the only frame.
+        let project = read_project(cursor).unwrap();
+        assert_eq!(project.frames.len(), 1);
+        assert_eq!(project.frames[0].duration_ms, DEFAULT_DURATION_MS);
+        assert_eq!(project.frames[0].layers[0].info.name, "ink");
+        assert_eq!(project.frames[0].layers[0].info.blend, MixMethod::Multiply);
+        assert_eq!(project.frames[0].layers[0].image, image);
+        assert_eq!(project.palette, vec![[1, 2, 3, 255]]);
+    }
+
+    #[test]
+    fn test_versions() {
+        let cursor = zip_with_manifest("(version: 999, canvas_size: (2, 2))");
+        assert!(matches!(read_project(cursor), Err(ProjectError::UnsupportedVersion(999))));
+        let cursor = zip_with_manifest("(version: 0, canvas_size: (2, 2))");
+        assert!(matches!(read_project(cursor), Err(ProjectError::Manifest(_))));
+        let manifest = migrate(&format!("(version: {}, canvas_size: (2, 2))", PROJECT_VERSION)).unwrap();
+        assert_eq!(manifest.version, PROJECT_VERSION);
+    }
+
+    #[test]
+    fn test_layer_size_mismatch() {
+        let mut project = sample_project();
+        project.canvas_size = UVec2::new(5, 5);
+        let mut bytes = Cursor::new(Vec::new());
+        write_project(&project, &mut bytes).unwrap();
+        bytes.set_position(0);
+        assert!(matches!(read_project(bytes), Err(ProjectError::BadLayer(_))));
+    }
+}
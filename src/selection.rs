@@ -0,0 +1,534 @@
+// The selection is a mask of the canvas size, the tools only write the selected pixels.
+// No mask means nothing is selected and the whole canvas can be written.
+use bevy::{
+    prelude::*,
+    asset::RenderAssetUsages,
+    image::ImageSampler,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_pancam::PanCam;
+use image::{imageops, GrayImage, Luma, Rgba, RgbaImage};
+
+use crate::canvas::{self, Canvas};
+use crate::config::AppConfig;
+use crate::flood_fill::{fill_region, Connectivity, FillMode};
+use crate::layers::LayerStack;
+use crate::painting::{canvas_to_world, window_to_canvas};
+use crate::pen_input::PenState;
+use crate::pressure_mask::Pressure;
+use crate::tools::PointTool;
+
+pub fn init_me(app: &mut App) {
+    let app_config = app.world().resource::<AppConfig<'static, 'static>>();
+    let size = UVec2::new(app_config.default_canvas_size.width, app_config.default_canvas_size.height);
+    app.insert_resource(Selection::new(size))
+        .add_systems(Update, (selection_by_keys, select_on_canvas).chain())
+        .add_systems(PostUpdate, update_selection_overlay);
+}
+
+pub(crate) const SELECTED: Luma<u8> = Luma([u8::MAX]);
+
+pub(crate) const SELECTION_TOOLS: [&str; 5] = ["select_rect", "select_ellipse", "lasso", "polygon", "magic_wand"];
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SelectionMode {
+    #[default]
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SelectionShape {
+    Rect(URect), // `max` is excluded.
+    Ellipse(URect), // inscribed in the rect.
+    Polygon(Vec<Vec2>), // closed back to the first point, even-odd rule.
+}
+
+fn polygon_contains(points: &[Vec2], p: Vec2) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[j]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+impl SelectionShape {
+    // The pixels whose center is inside the shape.
+    pub(crate) fn rasterize(&self, size: UVec2) -> GrayImage {
+        let mut ret = GrayImage::new(size.x, size.y);
+        match self {
+            SelectionShape::Rect(rect) => {
+                let rect = rect.intersect(URect::new(0, 0, size.x, size.y));
+                for y in rect.min.y..rect.max.y {
+                    for x in rect.min.x..rect.max.x {
+                        ret.put_pixel(x, y, SELECTED);
+                    }
+                }
+            },
+            SelectionShape::Ellipse(rect) => {
+                if rect.is_empty() { return ret; }
+                let center = rect.as_rect().center();
+                let half = rect.as_rect().half_size();
+                for (x, y, pixel) in ret.enumerate_pixels_mut() {
+                    let d = (Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center) / half;
+                    if d.length_squared() <= 1. {
+                        *pixel = SELECTED;
+                    }
+                }
+            },
+            SelectionShape::Polygon(points) => {
+                if points.len() < 3 { return ret; }
+                for (x, y, pixel) in ret.enumerate_pixels_mut() {
+                    if polygon_contains(points, Vec2::new(x as f32 + 0.5, y as f32 + 0.5)) {
+                        *pixel = SELECTED;
+                    }
+                }
+            },
+        }
+        ret
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub(crate) struct Selection {
+    size: UVec2,
+    mask: Option<GrayImage>,
+}
+
+impl Selection {
+    pub(crate) fn new(size: UVec2) -> Self {
+        Self {
+            size,
+            mask: None,
+        }
+    }
+
+    pub(crate) fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    pub(crate) fn mask(&self) -> Option<&GrayImage> {
+        self.mask.as_ref()
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.mask.is_some()
+    }
+
+    // Every pixel is writable without a selection.
+    pub(crate) fn contains(&self, x: u32, y: u32) -> bool {
+        match &self.mask {
+            Some(mask) => mask.get_pixel_checked(x, y).is_some_and(|x| *x == SELECTED),
+            None => x < self.size.x && y < self.size.y,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.mask = None;
+    }
+
+    pub(crate) fn select_all(&mut self) {
+        self.mask = Some(GrayImage::from_pixel(self.size.x, self.size.y, SELECTED));
+    }
+
+    // Combines a mask of the selection size with the current selection.
+    pub(crate) fn combine(&mut self, mask: GrayImage, mode: SelectionMode) {
+        assert_eq!(UVec2::from(mask.dimensions()), self.size, "Selection::combine: mask size mismatch");
+        let mut current = match (mode, self.mask.take()) {
+            (SelectionMode::Replace, _) => {
+                self.mask = Some(mask);
+                return;
+            },
+            (_, Some(o)) => o,
+            // adding to or subtracting from nothing starts from an empty mask.
+            (_, None) => GrayImage::new(self.size.x, self.size.y),
+        };
+        for (dst, src) in current.pixels_mut().zip(mask.pixels()) {
+            let (a, b) = (*dst == SELECTED, *src == SELECTED);
+            let selected = match mode {
+                SelectionMode::Replace | SelectionMode::Add => a || b,
+                SelectionMode::Subtract => a && !b,
+                SelectionMode::Intersect => a && b,
+            };
+            *dst = if selected { SELECTED } else { Luma([0]) };
+        }
+        self.mask = Some(current);
+    }
+
+    pub(crate) fn select(&mut self, shape: &SelectionShape, mode: SelectionMode) {
+        self.combine(shape.rasterize(self.size), mode);
+    }
+
+    // Selects the pixels the bucket would fill from `seed`.
+    pub(crate) fn magic_wand(&mut self, image: &RgbaImage, seed: UVec2, tolerance: &[u8; 4],
+        connectivity: Connectivity, fill_mode: FillMode, mode: SelectionMode) {
+        self.combine(fill_region(image, seed, tolerance, connectivity, fill_mode), mode);
+    }
+
+    // The smallest rect holding the selected pixels.
+    pub(crate) fn bounds(&self) -> Option<URect> {
+        let mask = self.mask.as_ref()?;
+        let mut ret: Option<URect> = None;
+        for (x, y, _) in mask.enumerate_pixels().filter(|x| *x.2 == SELECTED) {
+            let pixel = URect::new(x, y, x + 1, y + 1);
+            ret = Some(ret.map_or(pixel, |r| r.union(pixel)));
+        }
+        ret
+    }
+}
+
+// Wraps a tool so that it only changes the selected pixels.
+pub(crate) struct Clipped<'a, T: PointTool> {
+    tool: &'a T,
+    selection: &'a Selection,
+}
+
+impl <'a, T: PointTool> Clipped<'a, T> {
+    pub(crate) fn new(tool: &'a T, selection: &'a Selection) -> Self {
+        Self {
+            tool,
+            selection,
+        }
+    }
+
+    // Only the dirty rect of the tool is kept and put back.
+    fn clip(&self, image: &mut RgbaImage, dirty_rect: Option<IRect>, f: impl FnOnce(&mut RgbaImage)) {
+        let Some(mask) = self.selection.mask() else {
+            f(image);
+            return;
+        };
+        // the selection follows a new canvas a frame late.
+        if mask.dimensions() != image.dimensions() {
+            warn!("The selection doesn't fit the image, nothing is written.");
+            return;
+        }
+        let whole = IRect::from_corners(IVec2::ZERO, UVec2::from(image.dimensions()).as_ivec2());
+        let rect = dirty_rect.map_or(whole, |x| x.intersect(whole));
+        if rect.is_empty() { return; }
+        let (x0, y0) = (rect.min.x as u32, rect.min.y as u32);
+        let before = imageops::crop_imm(image, x0, y0, rect.width() as u32, rect.height() as u32).to_image();
+        f(image);
+        for (x, y, src) in before.enumerate_pixels() {
+            if *mask.get_pixel(x0 + x, y0 + y) != SELECTED {
+                image.put_pixel(x0 + x, y0 + y, *src);
+            }
+        }
+    }
+}
+
+impl <'a, T: PointTool> PointTool for Clipped<'a, T> {
+    fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
+        self.clip(image, self.tool.dirty_rect(relative_loc, None), |image| self.tool.apply(image, relative_loc));
+    }
+
+    fn apply_with_pressure(&self, image: &mut RgbaImage, relative_loc: Vec2, pressure: &Pressure) {
+        self.clip(image, self.tool.dirty_rect(relative_loc, Some(pressure)),
+            |image| self.tool.apply_with_pressure(image, relative_loc, pressure));
+    }
+
+    fn dirty_rect(&self, relative_loc: Vec2, pressure: Option<&Pressure>) -> Option<IRect> {
+        self.tool.dirty_rect(relative_loc, pressure)
+    }
+}
+
+// Shift adds, alt subtracts and both intersect.
+fn selection_mode(keys: &ButtonInput<KeyCode>) -> SelectionMode {
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let alt = keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+    match (shift, alt) {
+        (true, true) => SelectionMode::Intersect,
+        (true, false) => SelectionMode::Add,
+        (false, true) => SelectionMode::Subtract,
+        (false, false) => SelectionMode::Replace,
+    }
+}
+
+// Ctrl+A selects all, Ctrl+D drops the selection.
+fn selection_by_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut selection: ResMut<Selection>,
+) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) { return; }
+    if keys.just_pressed(KeyCode::KeyA) {
+        selection.select_all();
+    } else if keys.just_pressed(KeyCode::KeyD) {
+        selection.clear();
+    }
+}
+
+// The pixel rect spanned by two canvas locations, both pixels included.
+fn pixel_rect(from: Vec2, to: Vec2) -> URect {
+    let min = from.min(to).floor().max(Vec2::ZERO).as_uvec2();
+    let max = from.max(to).floor().max(Vec2::ZERO).as_uvec2() + UVec2::ONE;
+    URect::from_corners(min, max)
+}
+
+fn select_on_canvas(
+    pen: Res<PenState>,
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Single<(&Camera, &GlobalTransform), With<PanCam>>,
+    canvas: Single<&GlobalTransform, With<Canvas>>,
+    interactions: Query<&Interaction>,
+    layers: Res<LayerStack>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut selection: ResMut<Selection>,
+    mut points: Local<Vec<Vec2>>,
+    mut dragging: Local<bool>,
+    mut gizmos: Gizmos,
+) {
+    let tools_config = &app_config.tools_config;
+    let tool = tools_config.current_tool.as_deref().unwrap_or_default();
+    if !SELECTION_TOOLS.contains(&tool) {
+        points.clear();
+        *dragging = false;
+        return;
+    }
+
+    let (camera, camera_transform) = camera.into_inner();
+    let loc = pen.sample.and_then(|x|
+        window_to_canvas(x.position, camera, camera_transform, *canvas, layers.size()));
+    let mode = selection_mode(&keys);
+    let outside_ui = interactions.iter().all(|x| *x == Interaction::None);
+
+    match tool {
+        "polygon" => {
+            if keys.just_pressed(KeyCode::Escape) {
+                points.clear();
+            } else if keys.just_pressed(KeyCode::Enter) && points.len() >= 3 {
+                selection.select(&SelectionShape::Polygon(std::mem::take(&mut *points)), mode);
+            } else if let Some(loc) = loc.filter(|_| pen.just_pressed() && outside_ui) {
+                points.push(loc);
+            }
+        },
+        "magic_wand" => {
+            if let Some(loc) = loc.filter(|x| pen.just_pressed() && outside_ui && x.min_element() >= 0.) {
+                selection.magic_wand(&layers.active().image, loc.floor().as_uvec2(),
+                    &tools_config.fill_tolerance, tools_config.fill_connectivity, tools_config.fill_mode, mode);
+            }
+        },
+        _ => {
+            if pen.just_pressed() && outside_ui {
+                points.clear();
+                points.extend(loc);
+                *dragging = true;
+            } else if pen.pressed() && *dragging {
+                if let Some(loc) = loc {
+                    // the rect and the ellipse only need the corners.
+                    if tool != "lasso" && points.len() > 1 { points.pop(); }
+                    points.push(loc);
+                }
+            } else if *dragging {
+                *dragging = false;
+                // a click without a drag drops the selection.
+                if points.len() == 1 && mode == SelectionMode::Replace {
+                    points.clear();
+                    selection.clear();
+                    return;
+                }
+                let shape = match (tool, points.first(), points.last()) {
+                    ("lasso", _, _) => SelectionShape::Polygon(std::mem::take(&mut *points)),
+                    ("select_rect", Some(from), Some(to)) => SelectionShape::Rect(pixel_rect(*from, *to)),
+                    ("select_ellipse", Some(from), Some(to)) => SelectionShape::Ellipse(pixel_rect(*from, *to)),
+                    _ => return,
+                };
+                points.clear();
+                selection.select(&shape, mode);
+            }
+        },
+    }
+
+    // previews the shape being made.
+    let to_world = |x: Vec2| canvas_to_world(x, *canvas, layers.size());
+    let color = Color::srgb(1., 0., 1.);
+    match (tool, points.first(), points.last()) {
+        ("select_rect", Some(from), Some(to)) | ("select_ellipse", Some(from), Some(to)) => {
+            let rect = pixel_rect(*from, *to).as_rect();
+            let (a, b) = (to_world(rect.min), to_world(rect.max));
+            let center = (a + b) / 2.;
+            let size = (b - a).abs();
+            if tool == "select_rect" {
+                gizmos.rect_2d(Isometry2d::from_translation(center), size, color);
+            } else {
+                gizmos.ellipse_2d(Isometry2d::from_translation(center), size / 2., color);
+            }
+        },
+        _ => {
+            gizmos.linestrip_2d(points.iter().map(|x| to_world(*x)), color);
+        },
+    }
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct SelectionOverlay;
+
+// Dims the pixels outside of the selection.
+fn update_selection_overlay(
+    mut commands: Commands,
+    mut selection: ResMut<Selection>,
+    layers: Res<LayerStack>,
+    canvas: Single<Entity, With<Canvas>>,
+    overlay: Option<Single<&Sprite, With<SelectionOverlay>>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    // a new canvas drops the selection.
+    if selection.size() != layers.size() {
+        *selection = Selection::new(layers.size());
+    }
+    let Some(overlay) = overlay else {
+        let size = layers.size();
+        let mut image = Image::new_fill(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+        );
+        image.sampler = ImageSampler::nearest();
+        let overlay = commands.spawn((
+                SelectionOverlay,
+                Sprite::from_image(images.add(image)),
+                Transform::from_xyz(0., 0., 0.1),
+        )).id();
+        commands.entity(*canvas).add_child(overlay);
+        return;
+    };
+    if !selection.is_changed() { return; }
+
+    let size = selection.size();
+    let rgba = RgbaImage::from_fn(size.x, size.y, |x, y| {
+        if selection.is_active() && !selection.contains(x, y) { Rgba([0, 0, 0, 96]) } else { Rgba([0; 4]) }
+    });
+    if let Some(image) = images.get_mut(&overlay.image) {
+        canvas::rgba_to_image(rgba, image);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn selected(mask: &GrayImage) -> Vec<(u32, u32)> {
+        mask.enumerate_pixels().filter(|x| *x.2 == SELECTED).map(|x| (x.0, x.1)).collect()
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let size = UVec2::new(6, 5);
+        let rect = SelectionShape::Rect(URect::new(4, 3, 9, 9)).rasterize(size);
+        assert_eq!(selected(&rect), vec![(4, 3), (5, 3), (4, 4), (5, 4)]);
+
+        let ellipse = SelectionShape::Ellipse(URect::new(0, 0, 6, 4)).rasterize(size);
+        assert!(ellipse.get_pixel(3, 2)[0] != 0);
+        assert!(ellipse.get_pixel(0, 0)[0] == 0);
+        assert!(ellipse.get_pixel(0, 2)[0] != 0);
+        assert_eq!(selected(&ellipse).iter().filter(|x| x.1 == 4).count(), 0);
+
+        // a triangle with its right angle at the top left, the centers on the long side are out.
+        let triangle = SelectionShape::Polygon(vec![Vec2::ZERO, Vec2::new(4., 0.), Vec2::new(0., 4.)]);
+        assert_eq!(selected(&triangle.rasterize(size)),
+            vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)]);
+        assert!(selected(&SelectionShape::Polygon(vec![Vec2::ZERO, Vec2::ONE]).rasterize(size)).is_empty());
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut selection = Selection::new(UVec2::new(4, 1));
+        assert!(selection.contains(3, 0));
+        assert!(!selection.contains(4, 0));
+
+        selection.select(&SelectionShape::Rect(URect::new(0, 0, 2, 1)), SelectionMode::Replace);
+        assert_eq!(selected(selection.mask().unwrap()), vec![(0, 0), (1, 0)]);
+        selection.select(&SelectionShape::Rect(URect::new(3, 0, 4, 1)), SelectionMode::Add);
+        assert_eq!(selected(selection.mask().unwrap()), vec![(0, 0), (1, 0), (3, 0)]);
+        selection.select(&SelectionShape::Rect(URect::new(1, 0, 3, 1)), SelectionMode::Subtract);
+        assert_eq!(selected(selection.mask().unwrap()), vec![(0, 0), (3, 0)]);
+        selection.select(&SelectionShape::Rect(URect::new(2, 0, 4, 1)), SelectionMode::Intersect);
+        assert_eq!(selected(selection.mask().unwrap()), vec![(3, 0)]);
+        assert_eq!(selection.bounds(), Some(URect::new(3, 0, 4, 1)));
+        assert!(!selection.contains(0, 0));
+
+        selection.clear();
+        assert!(selection.contains(0, 0));
+        selection.select(&SelectionShape::Rect(URect::new(0, 0, 1, 1)), SelectionMode::Subtract);
+        assert!(selected(selection.mask().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_magic_wand() {
+        let image = RgbaImage::from_fn(4, 1, |x, _| if x == 2 { Rgba([0, 0, 0, 255]) } else { Rgba([255; 4]) });
+        let mut selection = Selection::new(UVec2::new(4, 1));
+        selection.magic_wand(&image, UVec2::ZERO, &[0; 4], Connectivity::Four, FillMode::Contiguous,
+            SelectionMode::Replace);
+        assert_eq!(selected(selection.mask().unwrap()), vec![(0, 0), (1, 0)]);
+        selection.magic_wand(&image, UVec2::ZERO, &[0; 4], Connectivity::Four, FillMode::Global,
+            SelectionMode::Replace);
+        assert_eq!(selected(selection.mask().unwrap()), vec![(0, 0), (1, 0), (3, 0)]);
+    }
+
+    struct Fill;
+
+    impl PointTool for Fill {
+        fn apply(&self, image: &mut RgbaImage, _relative_loc: Vec2) {
+            for pixel in image.pixels_mut() {
+                pixel.0 = [255, 0, 0, 255];
+            }
+        }
+    }
+
+    #[test]
+    fn test_clipped_tool() {
+        let mut image = RgbaImage::new(3, 3);
+        let mut selection = Selection::new(UVec2::splat(3));
+        Clipped::new(&Fill, &selection).apply(&mut image, Vec2::ZERO);
+        assert!(image.pixels().all(|x| x.0[3] == 255));
+
+        let mut image = RgbaImage::new(3, 3);
+        selection.select(&SelectionShape::Rect(URect::new(1, 1, 2, 3)), SelectionMode::Replace);
+        Clipped::new(&Fill, &selection).apply_with_pressure(&mut image, Vec2::ZERO, &Pressure::FULL);
+        let painted: Vec<(u32, u32)> = image.enumerate_pixels().filter(|x| x.2[3] == 255)
+            .map(|x| (x.0, x.1)).collect();
+        assert_eq!(painted, vec![(1, 1), (1, 2)]);
+    }
+
+    // Fills the whole image but only owns up to the rect, what is outside the rect stays written.
+    struct FillInRect(IRect);
+
+    impl PointTool for FillInRect {
+        fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
+            Fill.apply(image, relative_loc);
+        }
+
+        fn dirty_rect(&self, _relative_loc: Vec2, _pressure: Option<&Pressure>) -> Option<IRect> {
+            Some(self.0)
+        }
+    }
+
+    #[test]
+    fn test_clipped_dirty_rect() {
+        let mut selection = Selection::new(UVec2::splat(3));
+        selection.select(&SelectionShape::Rect(URect::new(1, 1, 2, 3)), SelectionMode::Replace);
+
+        // only the unselected pixels in the rect are put back.
+        let mut image = RgbaImage::new(3, 3);
+        Clipped::new(&FillInRect(IRect::new(-1, -1, 2, 2)), &selection).apply(&mut image, Vec2::ZERO);
+        let painted: Vec<(u32, u32)> = image.enumerate_pixels().filter(|x| x.2[3] == 255)
+            .map(|x| (x.0, x.1)).collect();
+        assert_eq!(painted, vec![(2, 0), (1, 1), (2, 1), (0, 2), (1, 2), (2, 2)]);
+
+        // a rect off the image changes nothing.
+        let mut image = RgbaImage::new(3, 3);
+        Clipped::new(&FillInRect(IRect::new(5, 5, 9, 9)), &selection).apply(&mut image, Vec2::ZERO);
+        assert!(image.pixels().all(|x| x.0[3] == 0));
+    }
+}
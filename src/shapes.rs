@@ -0,0 +1,401 @@
+// Shape tools, the pixels of a shape are stamped one by one with the brush.
+use bevy::{
+    prelude::*,
+    sprite::Anchor,
+};
+use bevy_pancam::PanCam;
+use image::{Rgba, RgbaImage};
+use std::collections::HashSet;
+
+use crate::canvas::{self, Canvas};
+use crate::config::AppConfig;
+use crate::eyedropper::picking_by_alt;
+use crate::history::History;
+use crate::layers::LayerStack;
+use crate::painting::window_to_canvas;
+use crate::pen_input::PenState;
+use crate::selection::{Clipped, Selection};
+use crate::stroke::bresenham_line;
+use crate::tools::{Brush, PointTool};
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, draw_shapes);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ShapeKind {
+    Line,
+    Rect,
+    FilledRect,
+    Ellipse,
+    FilledEllipse,
+    Polygon,
+}
+
+impl ShapeKind {
+    pub(crate) fn from_tool_name(name: &str) -> Option<Self> {
+        match name {
+            "line" => Some(ShapeKind::Line),
+            "rect" => Some(ShapeKind::Rect),
+            "filled_rect" => Some(ShapeKind::FilledRect),
+            "ellipse" => Some(ShapeKind::Ellipse),
+            "filled_ellipse" => Some(ShapeKind::FilledEllipse),
+            "polygon_shape" => Some(ShapeKind::Polygon),
+            _ => None,
+        }
+    }
+
+    // The pixels of the shape through `points`, two corners or the vertices of a polygon.
+    // Every pixel is given once.
+    pub(crate) fn rasterize(&self, points: &[IVec2]) -> Vec<IVec2> {
+        let (Some(first), Some(last)) = (points.first(), points.last()) else { return Vec::new(); };
+        let (min, max) = (first.min(*last), first.max(*last));
+        let ret = match self {
+            ShapeKind::Line => bresenham_line(*first, *last),
+            ShapeKind::Rect => rect_outline(min, max),
+            ShapeKind::FilledRect => (min.y..=max.y)
+                .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+                .collect(),
+            ShapeKind::Ellipse => midpoint_ellipse(min, max),
+            ShapeKind::FilledEllipse => fill_rows(midpoint_ellipse(min, max)),
+            ShapeKind::Polygon => points.iter().zip(points.iter().cycle().skip(1))
+                .flat_map(|(a, b)| bresenham_line(*a, *b))
+                .collect(),
+        };
+        let mut seen = HashSet::new();
+        ret.into_iter().filter(|x| seen.insert(*x)).collect()
+    }
+}
+
+fn rect_outline(min: IVec2, max: IVec2) -> Vec<IVec2> {
+    let mut ret = Vec::new();
+    for x in min.x..=max.x {
+        ret.push(IVec2::new(x, min.y));
+        ret.push(IVec2::new(x, max.y));
+    }
+    for y in min.y..=max.y {
+        ret.push(IVec2::new(min.x, y));
+        ret.push(IVec2::new(max.x, y));
+    }
+    ret
+}
+
+// The outline of the ellipse inscribed in the pixels from `min` to `max`, both included.
+// This is the midpoint algorithm by Alois Zingl, which also handles even sizes.
+pub(crate) fn midpoint_ellipse(min: IVec2, max: IVec2) -> Vec<IVec2> {
+    let (mut x0, mut y0, mut x1, mut y1) = (min.x as i64, min.y as i64, max.x as i64, max.y as i64);
+    let a = x1 - x0;
+    let b = y1 - y0;
+    let b1 = b & 1;
+    let mut dx = 4 * (1 - a) * b * b;
+    let mut dy = 4 * (b1 + 1) * a * a;
+    let mut err = dx + dy + b1 * a * a;
+    y0 += (b + 1) / 2;
+    y1 = y0 - b1;
+    let (a8, b8) = (8 * a * a, 8 * b * b);
+
+    let mut ret = Vec::new();
+    let mut push = |x: i64, y: i64| ret.push(IVec2::new(x as i32, y as i32));
+    loop {
+        push(x1, y0);
+        push(x0, y0);
+        push(x0, y1);
+        push(x1, y1);
+        let e2 = 2 * err;
+        if e2 <= dy {
+            y0 += 1;
+            y1 -= 1;
+            dy += a8;
+            err += dy;
+        }
+        if e2 >= dx || 2 * err > dy {
+            x0 += 1;
+            x1 -= 1;
+            dx += b8;
+            err += dx;
+        }
+        if x0 > x1 { break; }
+    }
+    // the tips of flat ellipses.
+    while y0 - y1 <= b {
+        push(x0 - 1, y0);
+        push(x1 + 1, y0);
+        push(x0 - 1, y1);
+        push(x1 + 1, y1);
+        y0 += 1;
+        y1 -= 1;
+    }
+    ret
+}
+
+// Fills every row between the leftmost and the rightmost pixel of an outline.
+fn fill_rows(outline: Vec<IVec2>) -> Vec<IVec2> {
+    let mut rows: Vec<(i32, i32, i32)> = Vec::new();
+    for p in outline {
+        match rows.iter_mut().find(|x| x.0 == p.y) {
+            Some(row) => {
+                row.1 = row.1.min(p.x);
+                row.2 = row.2.max(p.x);
+            },
+            None => rows.push((p.y, p.x, p.x)),
+        }
+    }
+    rows.sort();
+    rows.into_iter().flat_map(|(y, from, to)| (from..=to).map(move |x| IVec2::new(x, y))).collect()
+}
+
+pub(crate) fn stamp_pixels(tool: &impl PointTool, image: &mut RgbaImage, pixels: &[IVec2]) {
+    for p in pixels {
+        tool.apply(image, p.as_vec2() + Vec2::splat(0.5));
+    }
+}
+
+// Stamps `pixels` into an image of their bounding box on the canvas, which is returned
+// with its top left. The unselected pixels are left out.
+fn rasterize_preview(tool: &impl PointTool, selection: &Selection, pixels: &[IVec2], canvas_size: UVec2)
+    -> (IVec2, RgbaImage) {
+    let canvas_rect = IRect::from_corners(IVec2::ZERO, canvas_size.as_ivec2());
+    let bounds = pixels.iter()
+        .map(|p| tool.dirty_rect(p.as_vec2() + Vec2::splat(0.5), None).unwrap_or(canvas_rect))
+        .reduce(|a, b| a.union(b))
+        .map_or(IRect::default(), |x| x.intersect(canvas_rect));
+    if bounds.is_empty() {
+        return (IVec2::ZERO, RgbaImage::new(1, 1));
+    }
+
+    let size = bounds.size().as_uvec2();
+    let mut rgba = RgbaImage::new(size.x, size.y);
+    for p in pixels {
+        tool.apply(&mut rgba, (*p - bounds.min).as_vec2() + Vec2::splat(0.5));
+    }
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        if !selection.contains(bounds.min.x as u32 + x, bounds.min.y as u32 + y) {
+            *pixel = Rgba([0; 4]);
+        }
+    }
+    (bounds.min, rgba)
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct ShapePreview;
+
+// Drags the corners of a shape, the polygon takes a click per vertex and
+// is drawn by enter. The shape is previewed with the brush until it is drawn.
+fn draw_shapes(
+    mut commands: Commands,
+    pen: Res<PenState>,
+    keys: Res<ButtonInput<KeyCode>>,
+    camera: Single<(&Camera, &GlobalTransform), With<PanCam>>,
+    canvas: Single<Entity, With<Canvas>>,
+    canvas_transform: Single<&GlobalTransform, With<Canvas>>,
+    interactions: Query<&Interaction>,
+    mut layers: ResMut<LayerStack>,
+    selection: Res<Selection>,
+    mut history: ResMut<History>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    preview: Option<Single<(Entity, &Sprite, &mut Transform), With<ShapePreview>>>,
+    mut images: ResMut<Assets<Image>>,
+    mut points: Local<Vec<IVec2>>,
+    mut dragging: Local<bool>,
+    mut previewed: Local<Vec<IVec2>>,
+) {
+    let tools_config = &app_config.tools_config;
+    let kind = tools_config.current_tool.as_deref().and_then(ShapeKind::from_tool_name);
+    let Some(kind) = kind else {
+        points.clear();
+        *dragging = false;
+        if let Some(preview) = preview {
+            commands.entity(preview.0).despawn();
+        }
+        return;
+    };
+
+    let (camera, camera_transform) = camera.into_inner();
+    let loc = pen.sample.and_then(|x|
+        window_to_canvas(x.position, camera, camera_transform, *canvas_transform, layers.size()))
+        .map(|x| x.floor().as_ivec2());
+    // alt clicks pick a colour instead.
+    let outside_ui = interactions.iter().all(|x| *x == Interaction::None)
+        && !picking_by_alt(&keys, tools_config.current_tool.as_deref());
+
+    let mut done = false;
+    if kind == ShapeKind::Polygon {
+        if keys.just_pressed(KeyCode::Escape) {
+            points.clear();
+        } else if keys.just_pressed(KeyCode::Enter) {
+            done = points.len() >= 2;
+        } else if let Some(loc) = loc.filter(|_| pen.just_pressed() && outside_ui) {
+            points.push(loc);
+        }
+    } else if pen.just_pressed() && outside_ui {
+        points.clear();
+        points.extend(loc);
+        *dragging = !points.is_empty();
+    } else if pen.pressed() && *dragging {
+        if let Some(loc) = loc {
+            points.truncate(1);
+            points.push(loc);
+        }
+    } else if *dragging {
+        *dragging = false;
+        done = true;
+    }
+
+    let brush = Brush::from_config(tools_config);
+    let tool = Clipped::new(&brush, &selection);
+    if done {
+        let pixels = kind.rasterize(&points);
+        points.clear();
+        if history.is_recording() {
+            info!("A shape can't be drawn in the middle of another edit.");
+        } else if layers.active().is_editable() {
+            history.begin(layers.active());
+            stamp_pixels(&tool, &mut layers.active_mut().image, &pixels);
+            history.end(&layers);
+        } else {
+            info!("The active layer is hidden or locked.");
+        }
+    }
+
+    // the preview is redrawn only when the shape changes.
+    let mut outline = points.clone();
+    // the polygon being made is open and follows the pointer.
+    if kind == ShapeKind::Polygon && !outline.is_empty() {
+        outline.extend(loc);
+    }
+    if *previewed == outline && preview.is_some() { return; }
+    let pixels = match kind {
+        ShapeKind::Polygon => outline.windows(2).flat_map(|x| bresenham_line(x[0], x[1]))
+            .chain(outline.first().copied())
+            .collect(),
+        _ => kind.rasterize(&outline),
+    };
+    *previewed = outline;
+    // only the bounding box of the shape is drawn, at its place on the canvas.
+    let size = layers.size();
+    let (top_left, rgba) = rasterize_preview(&brush, &selection, &pixels, size);
+    let translation = Vec3::new(top_left.x as f32 - size.x as f32 / 2., size.y as f32 / 2. - top_left.y as f32, 0.08);
+    match preview {
+        Some(preview) => {
+            let (_, sprite, mut transform) = preview.into_inner();
+            if let Some(image) = images.get_mut(&sprite.image) {
+                canvas::rgba_to_image(rgba, image);
+            }
+            transform.translation = translation;
+        },
+        None => {
+            let mut image = Image::default();
+            canvas::rgba_to_image(rgba, &mut image);
+            image.sampler = bevy::image::ImageSampler::nearest();
+            let preview = commands.spawn((
+                    ShapePreview,
+                    Sprite {
+                        image: images.add(image),
+                        anchor: Anchor::TopLeft,
+                        ..default()
+                    },
+                    Transform::from_translation(translation),
+            )).id();
+            commands.entity(*canvas).add_child(preview);
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::selection::{SelectionMode, SelectionShape};
+
+    fn sorted(mut pixels: Vec<IVec2>) -> Vec<(i32, i32)> {
+        pixels.sort_by_key(|x| (x.y, x.x));
+        pixels.into_iter().map(|x| (x.x, x.y)).collect()
+    }
+
+    #[test]
+    fn test_rects_and_lines() {
+        let corners = [IVec2::new(3, 2), IVec2::new(0, 0)];
+        assert_eq!(ShapeKind::Rect.rasterize(&corners).len(), 10);
+        assert_eq!(ShapeKind::FilledRect.rasterize(&corners).len(), 12);
+        assert_eq!(ShapeKind::Rect.rasterize(&corners[..1]), vec![IVec2::new(3, 2)]);
+
+        let line = ShapeKind::Line.rasterize(&corners);
+        assert_eq!(line.first(), Some(&IVec2::new(3, 2)));
+        assert_eq!(line.last(), Some(&IVec2::ZERO));
+        assert!(ShapeKind::Line.rasterize(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_midpoint_ellipse() {
+        assert_eq!(sorted(ShapeKind::Ellipse.rasterize(&[IVec2::ONE, IVec2::ONE])), vec![(1, 1)]);
+
+        // a 5x5 circle.
+        let circle = sorted(ShapeKind::Ellipse.rasterize(&[IVec2::ZERO, IVec2::splat(4)]));
+        assert_eq!(circle, vec![
+            (1, 0), (2, 0), (3, 0),
+            (0, 1), (4, 1),
+            (0, 2), (4, 2),
+            (0, 3), (4, 3),
+            (1, 4), (2, 4), (3, 4),
+        ]);
+
+        // an even and flat one stays in its rect and is symmetric.
+        let flat = ShapeKind::Ellipse.rasterize(&[IVec2::ZERO, IVec2::new(7, 1)]);
+        assert!(flat.iter().all(|x| x.x >= 0 && x.x <= 7 && x.y >= 0 && x.y <= 1));
+        for p in &flat {
+            assert!(flat.contains(&IVec2::new(7 - p.x, 1 - p.y)));
+        }
+        assert!(flat.contains(&IVec2::new(0, 0)) && flat.contains(&IVec2::new(7, 1)));
+    }
+
+    #[test]
+    fn test_filled_ellipse_and_polygon() {
+        let filled = ShapeKind::FilledEllipse.rasterize(&[IVec2::ZERO, IVec2::splat(4)]);
+        assert_eq!(filled.len(), 3 + 5 + 5 + 5 + 3);
+
+        let triangle = ShapeKind::Polygon.rasterize(&[IVec2::ZERO, IVec2::new(2, 0), IVec2::new(0, 2)]);
+        assert_eq!(sorted(triangle), vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)]);
+    }
+
+    struct Dot;
+
+    impl PointTool for Dot {
+        fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
+            let p = relative_loc.floor().as_ivec2();
+            if p.min_element() < 0 { return; }
+            if let Some(pixel) = image.get_pixel_mut_checked(p.x as u32, p.y as u32) {
+                pixel.0[3] += 1;
+            }
+        }
+
+        fn dirty_rect(&self, relative_loc: Vec2, _pressure: Option<&crate::pressure_mask::Pressure>) -> Option<IRect> {
+            let p = relative_loc.floor().as_ivec2();
+            Some(IRect::from_corners(p, p + IVec2::ONE))
+        }
+    }
+
+    #[test]
+    fn test_stamp_pixels() {
+        let mut image = RgbaImage::new(4, 4);
+        stamp_pixels(&Dot, &mut image, &ShapeKind::Rect.rasterize(&[IVec2::ZERO, IVec2::splat(3)]));
+        assert_eq!(image.pixels().filter(|x| x.0[3] == 1).count(), 12);
+        assert_eq!(image.get_pixel(1, 1).0[3], 0);
+    }
+
+    #[test]
+    fn test_rasterize_preview() {
+        let size = UVec2::splat(8);
+        let mut selection = Selection::new(size);
+        let pixels = ShapeKind::Rect.rasterize(&[IVec2::new(2, 3), IVec2::new(-2, 5)]);
+        let (top_left, rgba) = rasterize_preview(&Dot, &selection, &pixels, size);
+        assert_eq!(top_left, IVec2::new(0, 3));
+        assert_eq!(rgba.dimensions(), (3, 3));
+        assert_eq!(rgba.pixels().filter(|x| x.0[3] == 1).count(), 7);
+
+        selection.select(&SelectionShape::Rect(URect::new(0, 0, 8, 4)), SelectionMode::Replace);
+        let (_, rgba) = rasterize_preview(&Dot, &selection, &pixels, size);
+        assert_eq!(rgba.pixels().filter(|x| x.0[3] == 1).count(), 3);
+
+        let (top_left, rgba) = rasterize_preview(&Dot, &selection, &[IVec2::splat(9)], size);
+        assert_eq!((top_left, rgba.dimensions()), (IVec2::ZERO, (1, 1)));
+    }
+}
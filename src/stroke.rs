@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+use image::RgbaImage;
+
+use crate::pressure_mask::Pressure;
+use crate::tools::{PixelPerfect, PointTool};
+
+// All the pixels on the line from `from` to `to`, both ends included.
+pub(crate) fn bresenham_line(from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let dx = (to.x - from.x).abs();
+    let dy = -(to.y - from.y).abs();
+    let sx = if from.x < to.x { 1 } else { -1 };
+    let sy = if from.y < to.y { 1 } else { -1 };
+
+    let mut ret = Vec::with_capacity((dx.max(-dy) + 1) as usize);
+    let mut cur = from;
+    let mut err = dx + dy;
+    loop {
+        ret.push(cur);
+        if cur == to { break; }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            cur.x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            cur.y += sy;
+        }
+    }
+    ret
+}
+
+// Turns the pointer samples of one stroke into stamp positions.
+// `spacing` is the distance between two stamps relative to the brush size,
+// when it is no more than one pixel the stamps walk the bresenham line instead.
+// The pressure is interpolated between the samples.
+// Pixel perfect strokes drop the L shaped corners of the bresenham lines.
+#[derive(Debug, Clone)]
+pub(crate) struct Stroke {
+    spacing: f32,
+    last: Option<(Vec2, Pressure)>,
+    residual: f32, // distance travelled since the last stamp.
+    pixel_perfect: Option<PixelPerfect<Pressure>>,
+}
+
+impl Stroke {
+    pub(crate) fn new(spacing: f32) -> Self {
+        Self {
+            spacing,
+            last: None,
+            residual: 0.,
+            pixel_perfect: None,
+        }
+    }
+
+    pub(crate) fn with_pixel_perfect(mut self, on: bool) -> Self {
+        self.pixel_perfect = on.then(PixelPerfect::default);
+        self
+    }
+
+    pub(crate) fn spacing_in_pixels(&self, brush_size: UVec2) -> f32 {
+        (self.spacing * brush_size.max_element() as f32).max(1.)
+    }
+
+    // Moves the stroke to `to` and returns the positions to stamp on the way.
+    pub(crate) fn advance(&mut self, to: Vec2, pressure: Pressure, brush_size: UVec2) -> Vec<(Vec2, Pressure)> {
+        let mut ret = Vec::new();
+        let step = self.spacing_in_pixels(brush_size);
+        if let Some(filter) = self.pixel_perfect.as_mut().filter(|_| step <= 1.) {
+            let line = match self.last {
+                None => vec![to.floor().as_ivec2()],
+                Some((from, _)) => bresenham_line(from.floor().as_ivec2(), to.floor().as_ivec2()),
+            };
+            let from_pressure = self.last.map_or(pressure, |x| x.1);
+            let last = (line.len() - 1).max(1) as f32;
+            ret.extend(line.into_iter()
+                .enumerate()
+                .filter_map(|(i, x)| filter.push(x, from_pressure.lerp(&pressure, i as f32 / last)))
+                .map(|(x, pressure)| (x.as_vec2() + Vec2::splat(0.5), pressure)));
+            self.last = Some((to, pressure));
+            return ret;
+        }
+        match self.last {
+            None => {
+                ret.push((to, pressure));
+                self.residual = 0.;
+            },
+            Some((from, from_pressure)) => {
+                if step <= 1. {
+                    let line = bresenham_line(from.floor().as_ivec2(), to.floor().as_ivec2());
+                    let last = (line.len() - 1).max(1) as f32;
+                    // the first pixel is stamped by the previous sample.
+                    ret.extend(line.into_iter()
+                        .enumerate()
+                        .skip(1)
+                        .map(|(i, x)| (x.as_vec2() + Vec2::splat(0.5),
+                            from_pressure.lerp(&pressure, i as f32 / last))));
+                } else {
+                    let delta = to - from;
+                    let len = delta.length();
+                    if len <= f32::EPSILON { return ret; }
+                    let dir = delta / len;
+
+                    let mut d = step - self.residual;
+                    while d <= len {
+                        ret.push((from + dir * d, from_pressure.lerp(&pressure, d / len)));
+                        d += step;
+                    }
+                    self.residual = len - (d - step);
+                }
+            }
+        }
+        self.last = Some((to, pressure));
+        ret
+    }
+
+    pub(crate) fn stroke_to(&mut self, tool: &impl PointTool, image: &mut RgbaImage,
+        to: Vec2, pressure: Pressure, brush_size: UVec2) {
+        for (loc, pressure) in self.advance(to, pressure, brush_size) {
+            tool.apply_with_pressure(image, loc, &pressure);
+        }
+    }
+
+    // Stamps what a pixel perfect stroke still holds back, when the pen is released.
+    pub(crate) fn finish(&mut self, tool: &impl PointTool, image: &mut RgbaImage) {
+        if let Some((pixel, pressure)) = self.pixel_perfect.as_mut().and_then(|x| x.finish()) {
+            tool.apply_with_pressure(image, pixel.as_vec2() + Vec2::splat(0.5), &pressure);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+
+    struct Recorder(RefCell<Vec<Vec2>>);
+
+    impl PointTool for Recorder {
+        fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
+            self.0.borrow_mut().push(relative_loc);
+            let loc = relative_loc.floor().as_uvec2();
+            if let Some(p) = image.get_pixel_mut_checked(loc.x, loc.y) {
+                p.0 = [0, 0, 0, 255];
+            }
+        }
+    }
+
+    #[test]
+    fn test_bresenham_line() {
+        assert_eq!(bresenham_line(IVec2::new(1, 1), IVec2::new(1, 1)), vec![IVec2::new(1, 1)]);
+        assert_eq!(bresenham_line(IVec2::new(0, 0), IVec2::new(3, 1)),
+            vec![IVec2::new(0, 0), IVec2::new(1, 0), IVec2::new(2, 1), IVec2::new(3, 1)]);
+        assert_eq!(bresenham_line(IVec2::new(2, 2), IVec2::new(0, 0)),
+            vec![IVec2::new(2, 2), IVec2::new(1, 1), IVec2::new(0, 0)]);
+    }
+
+    #[test]
+    fn test_continuous_pixel_stroke() {
+        let mut image = RgbaImage::new(10, 10);
+        let tool = Recorder(RefCell::new(Vec::new()));
+        let mut stroke = Stroke::new(0.25);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(0.5, 0.5), Pressure::FULL, UVec2::ONE);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(9.5, 0.5), Pressure::FULL, UVec2::ONE);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(9.5, 9.5), Pressure::FULL, UVec2::ONE);
+
+        for i in 0..10 {
+            assert_eq!(image.get_pixel(i, 0).0[3], 255);
+            assert_eq!(image.get_pixel(9, i).0[3], 255);
+        }
+        // no pixel is stamped twice.
+        assert_eq!(tool.0.borrow().len(), 19);
+    }
+
+    #[test]
+    fn test_spaced_stamps_across_samples() {
+        let mut image = RgbaImage::new(16, 16);
+        let tool = Recorder(RefCell::new(Vec::new()));
+        let mut stroke = Stroke::new(0.5);
+        let size = UVec2::splat(4);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(0., 0.), Pressure::FULL, size);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(3., 0.), Pressure::FULL, size);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(3., 0.), Pressure::FULL, size);
+        stroke.stroke_to(&tool, &mut image, Vec2::new(7., 0.), Pressure::FULL, size);
+
+        let xs: Vec<f32> = tool.0.borrow().iter().map(|x| x.x).collect();
+        assert_eq!(xs, vec![0., 2., 4., 6.]);
+    }
+
+    #[test]
+    fn test_pressure_is_interpolated() {
+        let mut stroke = Stroke::new(0.5);
+        let size = UVec2::splat(4);
+        let light = Pressure { pressure: 0., tilt: None };
+        let heavy = Pressure { pressure: 1., tilt: None };
+        stroke.advance(Vec2::ZERO, light, size);
+        let pressures: Vec<f32> = stroke.advance(Vec2::new(8., 0.), heavy, size)
+            .into_iter().map(|x| x.1.pressure).collect();
+        assert_eq!(pressures, vec![0.25, 0.5, 0.75, 1.]);
+
+        let mut stroke = Stroke::new(0.25);
+        stroke.advance(Vec2::splat(0.5), light, UVec2::ONE);
+        let pressures: Vec<f32> = stroke.advance(Vec2::new(2.5, 0.5), heavy, UVec2::ONE)
+            .into_iter().map(|x| x.1.pressure).collect();
+        assert_eq!(pressures, vec![0.5, 1.]);
+    }
+
+    #[test]
+    fn test_pixel_perfect_stroke() {
+        let mut image = RgbaImage::new(4, 4);
+        let tool = Recorder(RefCell::new(Vec::new()));
+        let mut stroke = Stroke::new(0.25).with_pixel_perfect(true);
+        // freehand samples of a staircase.
+        for (x, y) in [(0, 0), (1, 0), (1, 1), (2, 1), (2, 2)] {
+            stroke.stroke_to(&tool, &mut image, Vec2::new(x as f32 + 0.5, y as f32 + 0.5), Pressure::FULL, UVec2::ONE);
+        }
+        assert_eq!(tool.0.borrow().len(), 2);
+        stroke.finish(&tool, &mut image);
+        assert_eq!(*tool.0.borrow(), vec![Vec2::splat(0.5), Vec2::splat(1.5), Vec2::splat(2.5)]);
+        assert_eq!(image.get_pixel(1, 0).0[3], 0);
+    }
+}
@@ -0,0 +1,199 @@
+use bevy::{
+    prelude::*,
+    color::palettes::css,
+};
+use my_fluent_rs_helper::build_language_0;
+
+use crate::animation::{Animation, OnionSkin, PlayMode, Playback};
+use crate::config::AppConfig;
+use crate::history::History;
+use crate::layers::LayerStack;
+
+pub fn init_me(app: &mut App) {
+    app.add_systems(Update, (timeline_button_clicked, rebuild_timeline_panel).chain());
+}
+
+// The step of the duration buttons.
+const DURATION_STEP_MS: u32 = 10;
+const MAX_ONION_FRAMES: usize = 5;
+
+#[derive(Component, Debug)]
+pub(crate) struct TimelinePanel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimelineAction {
+    Select(usize),
+    TogglePlay,
+    ToggleMode,
+    Add,
+    Duplicate,
+    Remove,
+    MoveLeft,
+    MoveRight,
+    ToggleOnion,
+    MoreOnion,
+    LessOnion,
+    Longer,
+    Shorter,
+}
+
+#[derive(Component, Debug)]
+pub(crate) struct TimelineButton(pub TimelineAction);
+
+pub(crate) fn build_timeline_panel<'a, 'b>(parent: &'a mut ChildBuilder<'b>) {
+    parent.spawn((
+            TimelinePanel,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(25.),
+                right: Val::Percent(25.),
+                bottom: Val::Percent(6.),
+                display: Display::Flex,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.),
+                padding: UiRect::all(Val::Px(4.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(0., 0., 0., 0.6)),
+    ));
+}
+
+fn timeline_button_clicked(
+    query: Query<(&Interaction, &TimelineButton), (Changed<Interaction>, With<Button>)>,
+    mut animation: ResMut<Animation>,
+    mut playback: ResMut<Playback>,
+    mut onion: ResMut<OnionSkin>,
+    mut layers: ResMut<LayerStack>,
+    mut history: ResMut<History>,
+) {
+    for (interaction, button) in &query {
+        if *interaction != Interaction::Pressed { continue; }
+        let current = animation.current();
+        let duration = animation.durations()[current];
+        match button.0 {
+            TimelineAction::Select(i) => { animation.select(i, &mut layers, &mut history); },
+            TimelineAction::TogglePlay => playback.toggle(),
+            TimelineAction::ToggleMode => {
+                playback.mode = match playback.mode {
+                    PlayMode::Loop => PlayMode::PingPong,
+                    PlayMode::PingPong => PlayMode::Loop,
+                };
+            },
+            TimelineAction::Add => { animation.insert_frame(&mut layers, &mut history, false); },
+            TimelineAction::Duplicate => { animation.insert_frame(&mut layers, &mut history, true); },
+            TimelineAction::Remove => { animation.remove_frame(&mut layers, &mut history); },
+            TimelineAction::MoveLeft => {
+                if current > 0 { animation.move_frame(current, current - 1); }
+            },
+            TimelineAction::MoveRight => { animation.move_frame(current, current + 1); },
+            TimelineAction::ToggleOnion => onion.enabled = !onion.enabled,
+            // the same count of frames on both sides.
+            TimelineAction::MoreOnion => {
+                let count = (onion.before.max(onion.after) + 1).min(MAX_ONION_FRAMES);
+                onion.before = count;
+                onion.after = count;
+            },
+            TimelineAction::LessOnion => {
+                let count = onion.before.max(onion.after).saturating_sub(1).max(1);
+                onion.before = count;
+                onion.after = count;
+            },
+            TimelineAction::Longer => animation.set_duration(current, duration + DURATION_STEP_MS),
+            TimelineAction::Shorter => animation.set_duration(current,
+                duration.saturating_sub(DURATION_STEP_MS).max(DURATION_STEP_MS)),
+        }
+    }
+}
+
+fn spawn_text_button(builder: &mut ChildBuilder, action: TimelineAction, text: String, font: &TextFont, color: Srgba) {
+    builder.spawn((
+            Button,
+            TimelineButton(action),
+            Node {
+                padding: UiRect::horizontal(Val::Px(3.)),
+                ..default()
+            },
+    ))
+        .with_child((Text::new(text), font.clone(), TextColor(color.into())));
+}
+
+// Rebuilds the cells when the frames, their durations or the modes change.
+fn rebuild_timeline_panel(
+    mut commands: Commands,
+    panel: Single<Entity, With<TimelinePanel>>,
+    animation: Res<Animation>,
+    playback: Res<Playback>,
+    onion: Res<OnionSkin>,
+    asset_server: Res<AssetServer>,
+    app_config: Res<AppConfig<'static, 'static>>,
+    mut last: Local<Option<(usize, Vec<u32>, bool, PlayMode, bool, usize)>>,
+) {
+    let current = (animation.current(), animation.durations(), playback.playing, playback.mode, onion.enabled,
+        onion.before.max(onion.after));
+    if last.as_ref() == Some(&current) { return; }
+
+    let tools_config = &app_config.tools_config;
+    let font = TextFont {
+        font: asset_server.load(&tools_config.default_font),
+        font_size: tools_config.default_text_size * 2.,
+        ..default()
+    };
+
+    commands.entity(*panel).despawn_descendants().with_children(|builder| {
+        builder.spawn(Node {
+            display: Display::Flex,
+            flex_direction: FlexDirection::Row,
+            flex_wrap: FlexWrap::Wrap,
+            ..default()
+        }).with_children(|builder| {
+            let play = if current.2 { "pause" } else { "play" };
+            let mode = match current.3 {
+                PlayMode::Loop => "loop",
+                PlayMode::PingPong => "ping_pong",
+            };
+            spawn_text_button(builder, TimelineAction::TogglePlay, build_language_0(play), &font, css::LIME);
+            spawn_text_button(builder, TimelineAction::ToggleMode, build_language_0(mode), &font, css::LIME);
+            for (action, name) in [
+                (TimelineAction::Add, "frame_add"),
+                (TimelineAction::Duplicate, "frame_duplicate"),
+                (TimelineAction::Remove, "frame_remove"),
+                (TimelineAction::MoveLeft, "frame_left"),
+                (TimelineAction::MoveRight, "frame_right"),
+                (TimelineAction::Shorter, "frame_shorter"),
+                (TimelineAction::Longer, "frame_longer"),
+            ] {
+                spawn_text_button(builder, action, build_language_0(name), &font, css::LIME);
+            }
+            spawn_text_button(builder, TimelineAction::ToggleOnion,
+                format!("{} {}", build_language_0("onion_skin"), current.5), &font,
+                if current.4 { css::RED } else { css::WHITE });
+            spawn_text_button(builder, TimelineAction::LessOnion, "-".to_owned(), &font, css::LIME);
+            spawn_text_button(builder, TimelineAction::MoreOnion, "+".to_owned(), &font, css::LIME);
+        });
+
+        builder.spawn(Node {
+            display: Display::Flex,
+            flex_direction: FlexDirection::Row,
+            flex_wrap: FlexWrap::Wrap,
+            column_gap: Val::Px(2.),
+            ..default()
+        }).with_children(|builder| {
+            for (i, duration) in current.1.iter().enumerate() {
+                let color = if i == current.0 { css::RED } else { css::WHITE };
+                builder.spawn((
+                        Button,
+                        TimelineButton(TimelineAction::Select(i)),
+                        Node {
+                            padding: UiRect::all(Val::Px(2.)),
+                            border: UiRect::all(Val::Px(1.)),
+                            ..default()
+                        },
+                        BorderColor(color.into()),
+                ))
+                    .with_child((Text::new(format!("{}\n{}ms", i + 1, duration)), font.clone(),
+                        TextColor(color.into())));
+            }
+        });
+    });
+    *last = Some(current);
+}
@@ -3,8 +3,29 @@ use image::RgbaImage;
 use std::sync::{Arc, RwLock};
 
 use crate::patterns::PatternGeneratingFunc;
-use crate::pressure_mask::MaskGeneratingFunc;
+use crate::pressure_mask::{MaskGeneratingFunc, Pressure, PressureDynamics};
 use crate::mix_methods::MixMethod;
+use crate::config::ToolsConfig;
+use crate::flood_fill::{fill_region, Connectivity, FillMode, FILLED};
+use crate::palette::Palette;
+
+// How strongly a stamp is laid down, given by the pen pressure.
+struct StampStrength {
+    opacity: f32,
+    mask_strength: f32,
+}
+
+impl StampStrength {
+    const FULL: Self = Self {
+        opacity: 1.,
+        mask_strength: 1.,
+    };
+
+    // a weak mask strength flattens the mask towards full coverage.
+    fn mask(&self, mask: f32) -> f32 {
+        (1. - self.mask_strength * (1. - mask)) * self.opacity
+    }
+}
 
 fn brush_mix4(
     mask_generating_func: &MaskGeneratingFunc,
@@ -13,10 +34,12 @@ fn brush_mix4(
     mix_method: &MixMethod,
     loc: &UVec2,
     size: &UVec2,
+    strength: &StampStrength,
     ) -> Srgba {
-    let mask = (mask_generating_func.fun)(loc.x, loc.y, size);
+    let mask = strength.mask((mask_generating_func.fun)(loc.x, loc.y, size));
     let pattern = (pattern_generating_func.fun)(loc.x, loc.y, size);
-    let masked_pattern = mask * pattern;
+    // the mask is the coverage of the pattern.
+    let masked_pattern = pattern.with_alpha(pattern.alpha * mask);
 
     Srgba::from_u8_array(mix_method.perform_operation_4(
             &masked_pattern.to_u8_array(), &origin.to_u8_array()))
@@ -29,8 +52,9 @@ fn brush_mix3(
     mix_method: &MixMethod,
     loc: &UVec2,
     size: &UVec2,
+    strength: &StampStrength,
     ) -> Srgba {
-    let mask = (mask_generating_func.fun)(loc.x, loc.y, size);
+    let mask = strength.mask((mask_generating_func.fun)(loc.x, loc.y, size));
     let pattern = (pattern_generating_func.fun)(loc.x, loc.y, size);
     let masked_pattern = mask * pattern;
 
@@ -43,61 +67,308 @@ pub(crate) struct BottomTools {
     pub tool_name: String,
 }
 
-#[derive(Component, Debug)]
-struct Bucket;
+pub(crate) struct Bucket<'a> {
+    pattern_generating_func: &'a PatternGeneratingFunc<'a>,
+    mix_method: &'a MixMethod,
+    tolerance: [u8; 4],
+    connectivity: Connectivity,
+    fill_mode: FillMode,
+    palette: Option<&'a Palette>,
+}
+
+impl <'a> Bucket<'a> {
+    pub(crate) fn new(
+        pattern_generating_func: &'a PatternGeneratingFunc<'a>,
+        mix_method: &'a MixMethod,
+        tolerance: [u8; 4],
+        connectivity: Connectivity,
+        fill_mode: FillMode,
+        ) -> Self {
+        Self {
+            pattern_generating_func,
+            mix_method,
+            tolerance,
+            connectivity,
+            fill_mode,
+            palette: None,
+        }
+    }
+
+    // The mixed colours are snapped to `palette`.
+    pub(crate) fn with_palette(mut self, palette: Option<&'a Palette>) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub(crate) fn from_config(tools_config: &'a ToolsConfig) -> Self {
+        Self::new(&tools_config.pattern, &tools_config.mix_method,
+            tools_config.fill_tolerance, tools_config.fill_connectivity, tools_config.fill_mode)
+            .with_palette(tools_config.palette_lock.then_some(&tools_config.palette))
+    }
+}
+
+impl <'a> PointTool for Bucket<'a> {
+    fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
+        if relative_loc.x < 0. || relative_loc.y < 0. { return; }
+        let region = fill_region(image, relative_loc.floor().as_uvec2(),
+            &self.tolerance, self.connectivity, self.fill_mode);
+
+        // the pattern is laid over the whole image.
+        let image_size = UVec2::from(image.dimensions());
+        for (x, y, filled) in region.enumerate_pixels() {
+            if *filled != FILLED { continue; }
+            let pixel = image.get_pixel_mut(x, y);
+            let pattern = (self.pattern_generating_func.fun)(x, y, &image_size);
+            pixel.0 = self.mix_method.perform_operation_4(&pattern.to_u8_array(), &pixel.0);
+            if let Some(palette) = self.palette {
+                pixel.0 = palette.snap(pixel.0);
+            }
+        }
+    }
+}
 
 pub trait PointTool {
     fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2);
+
+    // Tools that don't react to the pen pressure ignore it.
+    fn apply_with_pressure(&self, image: &mut RgbaImage, relative_loc: Vec2, _pressure: &Pressure) {
+        self.apply(image, relative_loc);
+    }
+
+    // The pixels an apply at `relative_loc` may change, none for anywhere.
+    fn dirty_rect(&self, _relative_loc: Vec2, _pressure: Option<&Pressure>) -> Option<IRect> {
+        None
+    }
 }
 
 pub(crate) struct Brush<'a> {
-    mask_generating_func: MaskGeneratingFunc<'a>,
-    pattern_generating_func: PatternGeneratingFunc<'a>,
+    mask_generating_func: &'a MaskGeneratingFunc<'a>,
+    pattern_generating_func: &'a PatternGeneratingFunc<'a>,
     size: Arc<RwLock<UVec2>>,
-    mix_method: MixMethod,
+    mix_method: &'a MixMethod,
+    dynamics: &'a PressureDynamics,
+    palette: Option<&'a Palette>,
     mix_width: u8,
 }
 
-impl <'a> PointTool for Brush<'a> {
-    fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
-        let image_size = image.dimensions();
-        let image_rect = Rect::new(0f32, 0., image_size.0 as f32, image_size.1 as f32);
+impl <'a> Brush<'a> {
+    pub(crate) fn new(
+        mask_generating_func: &'a MaskGeneratingFunc<'a>,
+        pattern_generating_func: &'a PatternGeneratingFunc<'a>,
+        size: Arc<RwLock<UVec2>>,
+        mix_method: &'a MixMethod,
+        dynamics: &'a PressureDynamics,
+        ) -> Self {
+        Self {
+            mask_generating_func,
+            pattern_generating_func,
+            size,
+            mix_method,
+            dynamics,
+            palette: None,
+            mix_width: 4,
+        }
+    }
 
+    // The mixed colours are snapped to `palette`.
+    pub(crate) fn with_palette(mut self, palette: Option<&'a Palette>) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub(crate) fn from_config(tools_config: &'a ToolsConfig) -> Self {
+        Self::new(&tools_config.pressure_mask, &tools_config.pattern,
+            tools_config.brush_size.clone(), &tools_config.mix_method, &tools_config.pressure_dynamics)
+            .with_palette(tools_config.palette_lock.then_some(&tools_config.palette))
+    }
+
+    // The size of the pattern scaled by the pressure.
+    fn stamp_size(&self, pressure: Option<&Pressure>) -> UVec2 {
         let pattern_size = *self.size.read().expect("get pattern size failed.");
-        let half_pattern_size = pattern_size / 2;
-        let f = half_pattern_size.as_vec2();
-        let pattern_rect = Rect::from_corners(relative_loc - f, relative_loc + f);
+        match pressure {
+            Some(pressure) => {
+                let scale = self.dynamics.size_scale(pressure);
+                (pattern_size.as_vec2() * scale).round().as_uvec2().max(UVec2::ONE)
+            },
+            None => pattern_size,
+        }
+    }
+
+    // the stamp is centered on `relative_loc`, a 1x1 stamp covers the pixel under it.
+    fn top_left(relative_loc: Vec2, pattern_size: UVec2) -> IVec2 {
+        (relative_loc - pattern_size.as_vec2() / 2.).floor().as_ivec2()
+    }
 
-        let overlap = image_rect.intersect(pattern_rect);
-        if overlap.is_empty() { return; }
-        let top_left = (overlap.min.x.ceil() as u32, overlap.min.y.ceil() as u32);
-        let bottom_right = (overlap.max.x.floor() as u32, overlap.max.y.floor() as u32);
+    fn stamp(&self, image: &mut RgbaImage, relative_loc: Vec2, pattern_size: UVec2, strength: &StampStrength) {
+        let (width, height) = image.dimensions();
+        let top_left = Self::top_left(relative_loc, pattern_size);
 
-        for i in top_left.0..bottom_right.0 {
-            for j in top_left.1..bottom_right.1 {
+        for j in 0..pattern_size.y {
+            for i in 0..pattern_size.x {
+                let (x, y) = (top_left.x + i as i32, top_left.y + j as i32);
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 { continue; }
+
+                let pixel0 = image.get_pixel_mut(x as u32, y as u32);
+                let pixel = Srgba::from_u8_array(pixel0.0);
                 if self.mix_width == 4 {
-                    let pixel0 = image.get_pixel_mut_checked(i, j)
-                        .expect("<Brush as PointTool>::apply: coordinate calculated error");
-                    let pixel = Srgba::from_u8_array(pixel0.0);
                     let srgba = 
-                        brush_mix4(&self.mask_generating_func, 
-                            &self.pattern_generating_func, &pixel, &self.mix_method, 
-                            &UVec2::new(i - top_left.0, j - top_left.1), &pattern_size); 
+                        brush_mix4(self.mask_generating_func, 
+                            self.pattern_generating_func, &pixel, self.mix_method, 
+                            &UVec2::new(i, j), &pattern_size, strength); 
 
                     pixel0.0 = srgba.to_u8_array();
                 } else if self.mix_width == 3 {
-                    let pixel0 = image.get_pixel_mut_checked(i, j)
-                        .expect("<Brush as PointTool>::apply: coordinate calculated error");
-                    let pixel = Srgba::from_u8_array(pixel0.0);
-
                     let srgba = 
-                        brush_mix3(&self.mask_generating_func, 
-                            &self.pattern_generating_func, &pixel, &self.mix_method, 
-                            &UVec2::new(i - top_left.0, j - top_left.1), &pattern_size); 
+                        brush_mix3(self.mask_generating_func, 
+                            self.pattern_generating_func, &pixel, self.mix_method, 
+                            &UVec2::new(i, j), &pattern_size, strength); 
 
                     pixel0.0 = srgba.to_u8_array();
                 }
+                if let Some(palette) = self.palette {
+                    pixel0.0 = palette.snap(pixel0.0);
+                }
             }
         }
     }
 }
+
+impl <'a> PointTool for Brush<'a> {
+    fn apply(&self, image: &mut RgbaImage, relative_loc: Vec2) {
+        self.stamp(image, relative_loc, self.stamp_size(None), &StampStrength::FULL);
+    }
+
+    fn apply_with_pressure(&self, image: &mut RgbaImage, relative_loc: Vec2, pressure: &Pressure) {
+        let strength = StampStrength {
+            opacity: self.dynamics.opacity(pressure),
+            mask_strength: self.dynamics.mask_strength(pressure),
+        };
+        self.stamp(image, relative_loc, self.stamp_size(Some(pressure)), &strength);
+    }
+
+    fn dirty_rect(&self, relative_loc: Vec2, pressure: Option<&Pressure>) -> Option<IRect> {
+        let pattern_size = self.stamp_size(pressure);
+        let top_left = Self::top_left(relative_loc, pattern_size);
+        Some(IRect::from_corners(top_left, top_left + pattern_size.as_ivec2()))
+    }
+}
+
+// Pixel perfect pencil, drops the corner pixel of every L shaped step so 1px lines stay clean,
+// e.g. (0, 0), (1, 0), (1, 1) becomes (0, 0), (1, 1).
+// A pixel is held back until the next one tells whether it is a corner.
+#[derive(Debug, Clone)]
+pub(crate) struct PixelPerfect<T> {
+    kept: Option<IVec2>,
+    pending: Option<(IVec2, T)>,
+}
+
+impl <T> Default for PixelPerfect<T> {
+    fn default() -> Self {
+        Self {
+            kept: None,
+            pending: None,
+        }
+    }
+}
+
+fn is_l_corner(a: IVec2, b: IVec2, c: IVec2) -> bool {
+    (a - c).abs() == IVec2::ONE && (a - b).abs().element_sum() == 1 && (b - c).abs().element_sum() == 1
+}
+
+impl <T> PixelPerfect<T> {
+    // Gives the pixel that is now known to stay, if any.
+    pub(crate) fn push(&mut self, pixel: IVec2, data: T) -> Option<(IVec2, T)> {
+        if self.pending.as_ref().is_some_and(|x| x.0 == pixel) { return None; }
+        let ret = match self.pending.take() {
+            Some(pending) if self.kept.is_some_and(|x| is_l_corner(x, pending.0, pixel)) => None,
+            Some(pending) => {
+                self.kept = Some(pending.0);
+                Some(pending)
+            },
+            None => None,
+        };
+        self.pending = Some((pixel, data));
+        ret
+    }
+
+    // Ends the path and gives the pixel held back.
+    pub(crate) fn finish(&mut self) -> Option<(IVec2, T)> {
+        self.kept = None;
+        self.pending.take()
+    }
+}
+
+pub(crate) fn pixel_perfect(points: &[IVec2]) -> Vec<IVec2> {
+    let mut filter = PixelPerfect::default();
+    let mut ret: Vec<IVec2> = points.iter().filter_map(|x| filter.push(*x, ())).map(|x| x.0).collect();
+    ret.extend(filter.finish().map(|x| x.0));
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path(points: &[(i32, i32)]) -> Vec<IVec2> {
+        points.iter().map(|x| IVec2::new(x.0, x.1)).collect()
+    }
+
+    #[test]
+    fn test_pixel_perfect_staircase() {
+        // a freehand diagonal drawn as right, down, right, down.
+        let stairs = path(&[(0, 0), (1, 0), (1, 1), (2, 1), (2, 2), (3, 2)]);
+        assert_eq!(pixel_perfect(&stairs), path(&[(0, 0), (1, 1), (2, 2), (3, 2)]));
+
+        // straight and clean diagonal lines are kept as they are.
+        let line = path(&[(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(pixel_perfect(&line), line);
+        let diagonal = path(&[(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(pixel_perfect(&diagonal), diagonal);
+    }
+
+    #[test]
+    fn test_pixel_perfect_corners_and_repeats() {
+        // a real corner of a longer horizontal and vertical run.
+        let corner = path(&[(0, 0), (1, 0), (2, 0), (2, 1), (2, 2)]);
+        assert_eq!(pixel_perfect(&corner), path(&[(0, 0), (1, 0), (2, 1), (2, 2)]));
+
+        // repeated samples of the same pixel don't hide a corner.
+        let repeats = path(&[(0, 0), (0, 0), (1, 0), (1, 0), (1, 1)]);
+        assert_eq!(pixel_perfect(&repeats), path(&[(0, 0), (1, 1)]));
+
+        // going back and forth is not an L.
+        let back = path(&[(0, 0), (1, 0), (0, 0)]);
+        assert_eq!(pixel_perfect(&back), back);
+
+        assert!(pixel_perfect(&[]).is_empty());
+        assert_eq!(pixel_perfect(&path(&[(5, 5)])), path(&[(5, 5)]));
+    }
+
+    #[test]
+    fn test_pixel_perfect_keeps_data() {
+        let mut filter = PixelPerfect::default();
+        assert_eq!(filter.push(IVec2::new(0, 0), 'a'), None);
+        assert_eq!(filter.push(IVec2::new(0, 1), 'b'), Some((IVec2::new(0, 0), 'a')));
+        assert_eq!(filter.push(IVec2::new(1, 1), 'c'), None);
+        assert_eq!(filter.finish(), Some((IVec2::new(1, 1), 'c')));
+        assert_eq!(filter.finish(), None);
+    }
+
+    #[test]
+    fn test_palette_lock() {
+        let mask = MaskGeneratingFunc::new(None, |_, _, _| 1.);
+        let pattern = PatternGeneratingFunc::new(None, |_, _, _| Srgba::rgb_u8(250, 20, 10));
+        let dynamics = PressureDynamics::default();
+        let palette = Palette::new(vec![[0, 0, 0, 255], [255, 0, 0, 255]]);
+        let size = Arc::new(RwLock::new(UVec2::ONE));
+
+        let mut image = RgbaImage::new(2, 1);
+        Brush::new(&mask, &pattern, size.clone(), &MixMethod::Normal, &dynamics)
+            .apply(&mut image, Vec2::new(0.5, 0.5));
+        Brush::new(&mask, &pattern, size, &MixMethod::Normal, &dynamics)
+            .with_palette(Some(&palette))
+            .apply(&mut image, Vec2::new(1.5, 0.5));
+        assert_eq!(image.get_pixel(0, 0).0, [250, 20, 10, 255]);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 0, 0, 255]);
+    }
+}
@@ -19,6 +19,42 @@ pub(crate) fn build_tools_bar<'a, 'b>(tools_config: &mut ToolsConfig, asset_serv
 
 pub fn init_me(app: &mut App) {
     group_checker::init_me::<BottomToolsChecker>(app);
+    app.add_event::<SelectTool>()
+        .add_systems(Update, (select_tool, sync_current_tool).chain());
+}
+
+// Checks the bottom tool of the name, or none, e.g. when a project is opened.
+#[derive(Event, Debug, Clone)]
+pub(crate) struct SelectTool(pub Option<String>);
+
+fn select_tool(
+    mut commands: Commands,
+    mut events: EventReader<SelectTool>,
+    mut tools: Query<(Entity, &BottomTools, &mut group_checker::Checkable<BottomToolsChecker>, &Children,
+        Has<group_checker::CheckMarker<BottomToolsChecker>>)>,
+) {
+    let Some(SelectTool(name)) = events.read().last() else { return; };
+    if name.as_ref().is_some_and(|x| tools.iter().all(|tool| tool.1.tool_name != *x)) {
+        warn!("There is no tool named {:?}.", name);
+        return;
+    }
+    for (entity, tool, mut checkable, children, checked) in &mut tools {
+        let check = name.as_ref() == Some(&tool.tool_name);
+        if check != checked {
+            group_checker::set_checked(&mut commands, entity, &mut checkable, children, check);
+        }
+    }
+}
+
+// Keeps `ToolsConfig::current_tool` the same as the checked bottom tool.
+fn sync_current_tool(
+    checked: Option<Single<&BottomTools, With<group_checker::CheckMarker<BottomToolsChecker>>>>,
+    mut app_config: ResMut<AppConfig<'static, 'static>>,
+) {
+    let checked = checked.map(|x| x.tool_name.clone());
+    if app_config.tools_config.current_tool != checked {
+        app_config.tools_config.current_tool = checked;
+    }
 }
 
 #[derive(Clone)]
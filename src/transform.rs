@@ -0,0 +1,382 @@
+// Scaling and rotation that keep the edges of pixel art crisp: every output pixel is
+// one of the input colours, nothing is blended, but hq2x which smooths the edges.
+use image::{Rgba, RgbaImage};
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaler {
+    Nearest(u32),
+    // also known as EPX, the results are the same.
+    Scale2x,
+    Scale3x,
+    Hq2x,
+}
+
+impl Default for Scaler {
+    fn default() -> Self {
+        Scaler::Nearest(1)
+    }
+}
+
+impl Scaler {
+    pub fn factor(&self) -> u32 {
+        match *self {
+            Scaler::Nearest(factor) => factor,
+            Scaler::Scale2x | Scaler::Hq2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+
+    // "2" for nearest, "scale2x", "epx", "scale3x" or "hq2x".
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.to_lowercase().as_str() {
+            "scale2x" | "epx" => Some(Scaler::Scale2x),
+            "scale3x" => Some(Scaler::Scale3x),
+            "hq2x" => Some(Scaler::Hq2x),
+            x => x.parse().ok().filter(|x| *x > 0).map(Scaler::Nearest),
+        }
+    }
+}
+
+// Scaled images of more pixels than this aren't made, about 1 GiB of rgba.
+pub const MAX_SCALED_PIXELS: u64 = 1 << 28;
+
+// The size of `size` scaled, none when it would be too large.
+pub fn scaled_size(size: (u32, u32), scaler: Scaler) -> Option<(u32, u32)> {
+    let factor = scaler.factor();
+    let (width, height) = (size.0.checked_mul(factor)?, size.1.checked_mul(factor)?);
+    (width as u64 * height as u64 <= MAX_SCALED_PIXELS).then_some((width, height))
+}
+
+pub fn scale(image: &RgbaImage, scaler: Scaler) -> RgbaImage {
+    match scaler {
+        Scaler::Nearest(factor) => nearest(image, factor),
+        Scaler::Scale2x => scale2x(image),
+        Scaler::Scale3x => scale3x(image),
+        Scaler::Hq2x => hq2x(image),
+    }
+}
+
+pub fn nearest(image: &RgbaImage, factor: u32) -> RgbaImage {
+    RgbaImage::from_fn(image.width() * factor, image.height() * factor,
+        |x, y| *image.get_pixel(x / factor, y / factor))
+}
+
+// The neighbours of a pixel, the ones off the image repeat the edge.
+//     a b c
+//     d e f
+//     g h i
+struct Neighbours {
+    a: Rgba<u8>, b: Rgba<u8>, c: Rgba<u8>,
+    d: Rgba<u8>, e: Rgba<u8>, f: Rgba<u8>,
+    g: Rgba<u8>, h: Rgba<u8>, i: Rgba<u8>,
+}
+
+impl Neighbours {
+    fn of(image: &RgbaImage, x: u32, y: u32) -> Self {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(image.width() - 1));
+        let (up, down) = (y.saturating_sub(1), (y + 1).min(image.height() - 1));
+        let at = |x, y| *image.get_pixel(x, y);
+        Self {
+            a: at(left, up), b: at(x, up), c: at(right, up),
+            d: at(left, y), e: at(x, y), f: at(right, y),
+            g: at(left, down), h: at(x, down), i: at(right, down),
+        }
+    }
+
+    fn to_array(&self) -> [Rgba<u8>; 9] {
+        [self.a, self.b, self.c, self.d, self.e, self.f, self.g, self.h, self.i]
+    }
+}
+
+pub fn scale2x(image: &RgbaImage) -> RgbaImage {
+    let mut ret = RgbaImage::new(image.width() * 2, image.height() * 2);
+    for (x, y, _) in image.enumerate_pixels() {
+        let Neighbours { b, d, e, f, h, .. } = Neighbours::of(image, x, y);
+        let out = if b != h && d != f {
+            [
+                if d == b { d } else { e },
+                if b == f { f } else { e },
+                if d == h { d } else { e },
+                if h == f { f } else { e },
+            ]
+        } else {
+            [e; 4]
+        };
+        for (i, pixel) in out.into_iter().enumerate() {
+            ret.put_pixel(x * 2 + i as u32 % 2, y * 2 + i as u32 / 2, pixel);
+        }
+    }
+    ret
+}
+
+pub fn scale3x(image: &RgbaImage) -> RgbaImage {
+    let mut ret = RgbaImage::new(image.width() * 3, image.height() * 3);
+    for (x, y, _) in image.enumerate_pixels() {
+        let Neighbours { a, b, c, d, e, f, g, h, i } = Neighbours::of(image, x, y);
+        let out = if b != h && d != f {
+            [
+                if d == b { d } else { e },
+                if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                if b == f { f } else { e },
+                if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                e,
+                if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                if d == h { d } else { e },
+                if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                if h == f { f } else { e },
+            ]
+        } else {
+            [e; 9]
+        };
+        for (k, pixel) in out.into_iter().enumerate() {
+            ret.put_pixel(x * 3 + k as u32 % 3, y * 3 + k as u32 / 3, pixel);
+        }
+    }
+    ret
+}
+
+// The colours are compared in YUV like hqx does, the alpha like the luma.
+fn yuv_differ(x: Rgba<u8>, y: Rgba<u8>) -> bool {
+    let yuva = |p: Rgba<u8>| {
+        let [r, g, b, a] = p.0.map(|x| x as f32);
+        [0.299 * r + 0.587 * g + 0.114 * b,
+            -0.169 * r - 0.331 * g + 0.5 * b + 128.,
+            0.5 * r - 0.419 * g - 0.081 * b + 128.,
+            a].map(|x| x as i32)
+    };
+    let (x, y) = (yuva(x), yuva(y));
+    (x[0] - y[0]).abs() > 0x30 || (x[1] - y[1]).abs() > 7 || (x[2] - y[2]).abs() > 6 || (x[3] - y[3]).abs() > 0x30
+}
+
+// The weighted sum of `colors` shifted right by `shift`, the weights add up to 1 << shift.
+fn blend(colors: &[(Rgba<u8>, u32)], shift: u32) -> Rgba<u8> {
+    Rgba(std::array::from_fn(|k| (colors.iter().map(|(c, w)| c.0[k] as u32 * w).sum::<u32>() >> shift) as u8))
+}
+
+// The top left quarter of the centre of `w` for hq2x, the others are mirrored into it by
+// `p`, the position of each neighbour. `pattern` has a bit for every neighbour but the
+// centre that differs from it, from the top left row by row.
+fn hq2x_quarter(w: &[Rgba<u8>; 9], pattern: u32, p: [usize; 9]) -> Rgba<u8> {
+    let bit = |n: usize| if n > 4 { n - 1 } else { n };
+    let k = [0, 1, 2, 3, 5, 6, 7, 8].into_iter()
+        .fold(0, |k, n| k | ((pattern >> bit(n)) & 1) << bit(p[n]));
+    let (w0, w1, w3, w4, w5, w7) = (w[p[0]], w[p[1]], w[p[3]], w[p[4]], w[p[5]], w[p[7]]);
+    let is = |rules: &[(u32, u32)]| rules.iter().any(|(mask, value)| k & mask == *value);
+
+    if is(&[(0xbf, 0x37), (0xdb, 0x13)]) && yuv_differ(w1, w5) {
+        return blend(&[(w4, 3), (w3, 1)], 2);
+    }
+    if is(&[(0xdb, 0x49), (0xef, 0x6d)]) && yuv_differ(w7, w3) {
+        return blend(&[(w4, 3), (w1, 1)], 2);
+    }
+    if is(&[(0x0b, 0x0b), (0xfe, 0x4a), (0xfe, 0x1a)]) && yuv_differ(w3, w1) {
+        return w4;
+    }
+    if is(&[(0x6f, 0x2a), (0x5b, 0x0a), (0xbf, 0x3a), (0xdf, 0x5a), (0x9f, 0x8a), (0xcf, 0x8a),
+        (0xef, 0x4e), (0x3f, 0x0e), (0xfb, 0x5a), (0xbb, 0x8a), (0x7f, 0x5a), (0xaf, 0x8a),
+        (0xeb, 0x8a)]) && yuv_differ(w3, w1) {
+        return blend(&[(w4, 3), (w0, 1)], 2);
+    }
+    if is(&[(0x0b, 0x08)]) {
+        return blend(&[(w4, 2), (w0, 1), (w1, 1)], 2);
+    }
+    if is(&[(0x0b, 0x02)]) {
+        return blend(&[(w4, 2), (w0, 1), (w3, 1)], 2);
+    }
+    if is(&[(0x2f, 0x2f)]) {
+        return blend(&[(w4, 14), (w3, 1), (w1, 1)], 4);
+    }
+    if is(&[(0xbf, 0x37), (0xdb, 0x13)]) {
+        return blend(&[(w4, 5), (w1, 2), (w3, 1)], 3);
+    }
+    if is(&[(0xdb, 0x49), (0xef, 0x6d)]) {
+        return blend(&[(w4, 5), (w3, 2), (w1, 1)], 3);
+    }
+    if is(&[(0x1b, 0x03), (0x4f, 0x43), (0x8b, 0x83), (0x6b, 0x43)]) {
+        return blend(&[(w4, 3), (w3, 1)], 2);
+    }
+    if is(&[(0x4b, 0x09), (0x8b, 0x89), (0x1f, 0x19), (0x3b, 0x19)]) {
+        return blend(&[(w4, 3), (w1, 1)], 2);
+    }
+    if is(&[(0x7e, 0x2a), (0xef, 0xab), (0xbf, 0x8f), (0x7e, 0x0e)]) {
+        return blend(&[(w4, 2), (w3, 3), (w1, 3)], 3);
+    }
+    if is(&[(0xfb, 0x6a), (0x6f, 0x6e), (0x3f, 0x3e), (0xfb, 0xfa), (0xdf, 0xde), (0xdf, 0x1e)]) {
+        return blend(&[(w4, 3), (w0, 1)], 2);
+    }
+    if is(&[(0x0a, 0x00), (0x4f, 0x4b), (0x9f, 0x1b), (0x2f, 0x0b), (0xbe, 0x0a), (0xee, 0x0a),
+        (0x7e, 0x0a), (0xeb, 0x4b), (0x3b, 0x1b)]) {
+        return blend(&[(w4, 2), (w3, 1), (w1, 1)], 2);
+    }
+    blend(&[(w4, 6), (w3, 1), (w1, 1)], 3)
+}
+
+// hq2x of the hqx family, the rules follow the compact form of FFmpeg's hqx filter.
+pub fn hq2x(image: &RgbaImage) -> RgbaImage {
+    const QUARTERS: [[usize; 9]; 4] = [
+        [0, 1, 2, 3, 4, 5, 6, 7, 8],
+        [2, 1, 0, 5, 4, 3, 8, 7, 6],
+        [6, 7, 8, 3, 4, 5, 0, 1, 2],
+        [8, 7, 6, 5, 4, 3, 2, 1, 0],
+    ];
+    let mut ret = RgbaImage::new(image.width() * 2, image.height() * 2);
+    for (x, y, _) in image.enumerate_pixels() {
+        let w = Neighbours::of(image, x, y).to_array();
+        let pattern = [0, 1, 2, 3, 5, 6, 7, 8].into_iter().enumerate()
+            .filter(|(_, n)| w[*n] != w[4] && yuv_differ(w[4], w[*n]))
+            .fold(0, |k, (i, _)| k | 1 << i);
+        for (i, p) in QUARTERS.into_iter().enumerate() {
+            ret.put_pixel(x * 2 + i as u32 % 2, y * 2 + i as u32 / 2, hq2x_quarter(&w, pattern, p));
+        }
+    }
+    ret
+}
+
+// RotSprite: the image is scaled up 8 times with Scale2x, which rounds the
+// staircases into slopes, then sampled at the centres of the rotated pixels.
+// Clockwise in degrees, the image grows to hold the rotated corners.
+pub fn rotsprite(image: &RgbaImage, degrees: f32) -> RgbaImage {
+    if image.width() == 0 || image.height() == 0 {
+        return image.clone();
+    }
+    const FACTOR: f32 = 8.;
+    let big = scale2x(&scale2x(&scale2x(image)));
+    let (sin, cos) = (degrees.rem_euclid(360.) * PI / 180.).sin_cos();
+    // snaps the rounding errors of the right angles.
+    let (sin, cos) = (snap(sin), snap(cos));
+
+    let (w, h) = (image.width() as f32, image.height() as f32);
+    let size = |x: f32| (x - 1e-3).ceil().max(1.) as u32;
+    let (width, height) = (size(w * cos.abs() + h * sin.abs()), size(w * sin.abs() + h * cos.abs()));
+    let (centre_x, centre_y) = (width as f32 / 2., height as f32 / 2.);
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - centre_x, y as f32 + 0.5 - centre_y);
+        // back from the output to the input.
+        let (sx, sy) = (dx * cos + dy * sin + w / 2., -dx * sin + dy * cos + h / 2.);
+        if sx < 0. || sy < 0. || sx >= w || sy >= h {
+            return Rgba([0; 4]);
+        }
+        *big.get_pixel(
+            ((sx * FACTOR) as u32).min(big.width() - 1),
+            ((sy * FACTOR) as u32).min(big.height() - 1))
+    })
+}
+
+fn snap(x: f32) -> f32 {
+    if (x - x.round()).abs() < 1e-6 { x.round() } else { x }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    const O: Rgba<u8> = Rgba([0, 0, 0, 0]);
+    const X: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    fn image_of(rows: &[&str]) -> RgbaImage {
+        RgbaImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            if rows[y as usize].as_bytes()[x as usize] == b'#' { X } else { O }
+        })
+    }
+
+    // Compares with a png in tests/golden, PIXELIN_BLESS=1 writes it instead.
+    fn assert_golden(image: &RgbaImage, name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name);
+        if std::env::var_os("PIXELIN_BLESS").is_some() {
+            image.save(&path).unwrap();
+            return;
+        }
+        let golden = image::open(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)).to_rgba8();
+        assert!(golden == *image, "{} differs from the golden image", name);
+    }
+
+    // A 16x16 sprite with slopes, a curve and three colours.
+    fn sprite() -> RgbaImage {
+        image::load_from_memory(include_bytes!("../tests/golden/sprite.png")).unwrap().to_rgba8()
+    }
+
+    #[test]
+    fn test_scale2x_rounds_diagonals() {
+        let diagonal = image_of(&[
+            "....",
+            ".#..",
+            "..#.",
+            "....",
+        ]);
+        assert_eq!(scale2x(&diagonal), image_of(&[
+            "........",
+            "........",
+            "..##....",
+            "..###...",
+            "...###..",
+            "....##..",
+            "........",
+            "........",
+        ]));
+        // a single pixel or a straight line stays square.
+        assert_eq!(scale2x(&image_of(&["...", ".#.", "..."])), nearest(&image_of(&["...", ".#.", "..."]), 2));
+        assert_eq!(scale3x(&image_of(&["###"])), nearest(&image_of(&["###"]), 3));
+    }
+
+    #[test]
+    fn test_hq2x() {
+        // a flat image and a single pixel, the pixel is blended with its background.
+        let flat = image_of(&["###", "###"]);
+        assert_eq!(hq2x(&flat), nearest(&flat, 2));
+        let dot = hq2x(&image_of(&["...", ".#.", "..."]));
+        assert_eq!(dot.dimensions(), (6, 6));
+        assert_eq!(dot.get_pixel(0, 0).0, O.0);
+        assert_eq!(dot.get_pixel(2, 2).0, [0, 0, 0, 223]);
+
+        // the staircase of a diagonal becomes a smooth slope, the pixels on it stay.
+        let diagonal = hq2x(&image_of(&["#..", ".#.", "..#"]));
+        assert_eq!(diagonal.get_pixel(3, 3).0, X.0);
+        assert!(diagonal.get_pixel(3, 2).0[3] > 0 && diagonal.get_pixel(3, 2).0[3] < 255);
+    }
+
+    #[test]
+    fn test_scaled_size() {
+        assert_eq!(scaled_size((3, 2), Scaler::Scale3x), Some((9, 6)));
+        assert_eq!(scaled_size((32, 32), Scaler::Nearest(100000)), None);
+        assert_eq!(scaled_size((u32::MAX, 1), Scaler::Scale2x), None);
+    }
+
+    #[test]
+    fn test_parse_scaler() {
+        assert_eq!(Scaler::parse("3"), Some(Scaler::Nearest(3)));
+        assert_eq!(Scaler::parse("EPX"), Some(Scaler::Scale2x));
+        assert_eq!(Scaler::parse("scale3x").map(|x| x.factor()), Some(3));
+        assert_eq!(Scaler::parse("0"), None);
+        assert_eq!(Scaler::parse("HQ2x"), Some(Scaler::Hq2x));
+        assert_eq!(Scaler::parse("hq4x"), None);
+    }
+
+    #[test]
+    fn test_rotsprite_keeps_the_colours() {
+        let sprite = sprite();
+        let colors: HashSet<_> = sprite.pixels().collect();
+        for degrees in [0., 30., 45., 90., 200.] {
+            let rotated = rotsprite(&sprite, degrees);
+            assert!(rotated.pixels().all(|x| colors.contains(x) || x.0[3] == 0), "{}", degrees);
+        }
+        // right angles turn the pixels exactly.
+        assert_eq!(rotsprite(&sprite, 0.), sprite);
+        assert_eq!(rotsprite(&sprite, 90.), image::imageops::rotate90(&sprite));
+        assert_eq!(rotsprite(&sprite, -180.), image::imageops::rotate180(&sprite));
+    }
+
+    #[test]
+    fn test_golden_images() {
+        let sprite = sprite();
+        assert_golden(&nearest(&sprite, 3), "sprite_nearest3.png");
+        assert_golden(&scale2x(&sprite), "sprite_scale2x.png");
+        assert_golden(&scale3x(&sprite), "sprite_scale3x.png");
+        assert_golden(&hq2x(&sprite), "sprite_hq2x.png");
+        assert_golden(&rotsprite(&sprite, 30.), "sprite_rotsprite30.png");
+        assert_golden(&rotsprite(&sprite, 45.), "sprite_rotsprite45.png");
+    }
+}